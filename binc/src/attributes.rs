@@ -1,4 +1,4 @@
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum AttributeValue {
     String(String),
     Bool(bool),
@@ -13,22 +13,408 @@ pub enum AttributeValue {
     I24(I24),
     I32(i32),
     I64(i64),
+    F16(F16),
     F32(f32),
     F64(f64),
+    BoolArray(Vec<bool>),
+    StringArray(Vec<String>),
+    UuidArray(Vec<uuid::Uuid>),
+    U8Array(Vec<u8>),
+    U16Array(Vec<u16>),
+    U24Array(Vec<U24>),
+    U32Array(Vec<u32>),
+    U64Array(Vec<u64>),
+    I8Array(Vec<i8>),
+    I16Array(Vec<i16>),
+    I24Array(Vec<I24>),
+    I32Array(Vec<i32>),
+    I64Array(Vec<i64>),
+    F16Array(Vec<F16>),
+    F32Array(Vec<f32>),
+    F64Array(Vec<f64>),
 }
 
-type I24 = [u8; 3];
-type U24 = [u8; 3];
+/// Error returned when a native value does not fit in a narrower attribute type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutOfRangeError;
+
+impl std::fmt::Display for OutOfRangeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Value out of range")
+    }
+}
+
+impl std::error::Error for OutOfRangeError {}
+
+/// Unsigned 24-bit integer, stored as three big-endian bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct U24(u32);
+
+impl U24 {
+    pub const MIN: U24 = U24(0);
+    pub const MAX: U24 = U24(0xFF_FFFF);
+
+    pub fn from_be_bytes(bytes: [u8; 3]) -> U24 {
+        U24(u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]))
+    }
+
+    pub fn to_be_bytes(self) -> [u8; 3] {
+        let b = self.0.to_be_bytes();
+        [b[1], b[2], b[3]]
+    }
+
+    pub fn value(self) -> u32 {
+        self.0
+    }
+}
+
+impl TryFrom<u32> for U24 {
+    type Error = OutOfRangeError;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        if value <= U24::MAX.0 {
+            Ok(U24(value))
+        } else {
+            Err(OutOfRangeError)
+        }
+    }
+}
+
+impl From<u16> for U24 {
+    fn from(value: u16) -> Self {
+        U24(value as u32)
+    }
+}
+
+impl From<U24> for u32 {
+    fn from(value: U24) -> Self {
+        value.0
+    }
+}
+
+impl From<U24> for i64 {
+    fn from(value: U24) -> Self {
+        value.0 as i64
+    }
+}
+
+impl std::fmt::Display for U24 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Signed 24-bit integer, stored as three big-endian bytes in two's complement
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct I24(i32);
+
+impl I24 {
+    pub const MIN: I24 = I24(-0x80_0000);
+    pub const MAX: I24 = I24(0x7F_FFFF);
+
+    pub fn from_be_bytes(bytes: [u8; 3]) -> I24 {
+        // Place the bytes in the top of an i32 and shift back down to sign-extend
+        I24(i32::from_be_bytes([bytes[0], bytes[1], bytes[2], 0]) >> 8)
+    }
+
+    pub fn to_be_bytes(self) -> [u8; 3] {
+        let b = self.0.to_be_bytes();
+        [b[1], b[2], b[3]]
+    }
+
+    pub fn value(self) -> i32 {
+        self.0
+    }
+}
+
+impl TryFrom<i32> for I24 {
+    type Error = OutOfRangeError;
+
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        if (I24::MIN.0..=I24::MAX.0).contains(&value) {
+            Ok(I24(value))
+        } else {
+            Err(OutOfRangeError)
+        }
+    }
+}
+
+impl From<i16> for I24 {
+    fn from(value: i16) -> Self {
+        I24(value as i32)
+    }
+}
+
+impl From<I24> for i32 {
+    fn from(value: I24) -> Self {
+        value.0
+    }
+}
+
+impl From<I24> for i64 {
+    fn from(value: I24) -> Self {
+        value.0 as i64
+    }
+}
+
+impl std::fmt::Display for I24 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// IEEE 754 half precision float, kept as its raw bits
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct F16(u16);
+
+impl F16 {
+    pub fn from_bits(bits: u16) -> F16 {
+        F16(bits)
+    }
+
+    pub fn to_bits(self) -> u16 {
+        self.0
+    }
+
+    /// Convert from single precision, rounding to the nearest representable value (ties to even)
+    pub fn from_f32(value: f32) -> F16 {
+        let x = value.to_bits();
+        let sign = ((x >> 16) & 0x8000) as u16;
+        let exponent = ((x >> 23) & 0xFF) as i32;
+        let mantissa = x & 0x7F_FFFF;
+
+        if exponent == 0xFF {
+            // Infinity or NaN, keep NaN quiet so it stays a NaN
+            let nan = if mantissa != 0 { 0x200 } else { 0 };
+            return F16(sign | 0x7C00 | nan | (mantissa >> 13) as u16);
+        }
+
+        let e = exponent - 127 + 15;
+        if e >= 0x1F {
+            return F16(sign | 0x7C00);
+        }
+
+        if e <= 0 {
+            // Subnormal in half precision (or too small, becoming zero)
+            if e < -10 {
+                return F16(sign);
+            }
+            let m = mantissa | 0x80_0000;
+            let shift = (14 - e) as u32;
+            let half = 1 << (shift - 1);
+            let rounded = (m + half - 1 + ((m >> shift) & 1)) >> shift;
+            return F16(sign | rounded as u16);
+        }
+
+        let m = mantissa >> 13;
+        let rest = mantissa & 0x1FFF;
+        let mut bits = sign as u32 | ((e as u32) << 10) | m;
+        if rest > 0x1000 || (rest == 0x1000 && (m & 1) == 1) {
+            // A carry into the exponent is the correct result, up to infinity
+            bits += 1;
+        }
+        F16(bits as u16)
+    }
+
+    pub fn to_f32(self) -> f32 {
+        let sign = ((self.0 as u32) & 0x8000) << 16;
+        let exponent = ((self.0 >> 10) & 0x1F) as u32;
+        let mantissa = (self.0 & 0x3FF) as u32;
+
+        match exponent {
+            0 => {
+                let value = mantissa as f32 * 2f32.powi(-24);
+                if sign != 0 {
+                    -value
+                } else {
+                    value
+                }
+            }
+            0x1F => f32::from_bits(sign | 0x7F80_0000 | (mantissa << 13)),
+            _ => f32::from_bits(sign | ((exponent + 112) << 23) | (mantissa << 13)),
+        }
+    }
+}
+
+impl From<f32> for F16 {
+    fn from(value: f32) -> Self {
+        F16::from_f32(value)
+    }
+}
+
+impl From<F16> for f32 {
+    fn from(value: F16) -> Self {
+        value.to_f32()
+    }
+}
+
+impl From<F16> for f64 {
+    fn from(value: F16) -> Self {
+        value.to_f32() as f64
+    }
+}
+
+impl std::fmt::Display for F16 {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_f32())
+    }
+}
 
 impl AttributeValue {
+    /// Returns the value as an `i64` if it is an integer that fits
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            AttributeValue::U8(v) => Some(*v as i64),
+            AttributeValue::U16(v) => Some(*v as i64),
+            AttributeValue::U24(v) => Some(i64::from(*v)),
+            AttributeValue::U32(v) => Some(*v as i64),
+            AttributeValue::U64(v) => i64::try_from(*v).ok(),
+            AttributeValue::I8(v) => Some(*v as i64),
+            AttributeValue::I16(v) => Some(*v as i64),
+            AttributeValue::I24(v) => Some(i64::from(*v)),
+            AttributeValue::I32(v) => Some(*v as i64),
+            AttributeValue::I64(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as an `f64` if it is numeric
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            AttributeValue::U64(v) => Some(*v as f64),
+            AttributeValue::F16(v) => Some(f64::from(*v)),
+            AttributeValue::F32(v) => Some(*v as f64),
+            AttributeValue::F64(v) => Some(*v),
+            _ => self.as_i64().map(|v| v as f64),
+        }
+    }
+
     pub(crate) fn too_long_for_display(&self) -> bool {
         match self {
             AttributeValue::String(s) => s.len() > 100,
+            AttributeValue::StringArray(a) => a.len() > 32 || a.iter().any(|s| s.len() > 100),
+            AttributeValue::BoolArray(a) => a.len() > 32,
+            AttributeValue::UuidArray(a) => a.len() > 32,
+            AttributeValue::U8Array(a) => a.len() > 32,
+            AttributeValue::U16Array(a) => a.len() > 32,
+            AttributeValue::U24Array(a) => a.len() > 32,
+            AttributeValue::U32Array(a) => a.len() > 32,
+            AttributeValue::U64Array(a) => a.len() > 32,
+            AttributeValue::I8Array(a) => a.len() > 32,
+            AttributeValue::I16Array(a) => a.len() > 32,
+            AttributeValue::I24Array(a) => a.len() > 32,
+            AttributeValue::I32Array(a) => a.len() > 32,
+            AttributeValue::I64Array(a) => a.len() > 32,
+            AttributeValue::F16Array(a) => a.len() > 32,
+            AttributeValue::F32Array(a) => a.len() > 32,
+            AttributeValue::F64Array(a) => a.len() > 32,
             _ => false,
         }
     }
 }
 
+impl From<bool> for AttributeValue {
+    fn from(value: bool) -> Self {
+        AttributeValue::Bool(value)
+    }
+}
+
+impl From<uuid::Uuid> for AttributeValue {
+    fn from(value: uuid::Uuid) -> Self {
+        AttributeValue::Uuid(value)
+    }
+}
+
+impl From<u8> for AttributeValue {
+    fn from(value: u8) -> Self {
+        AttributeValue::U8(value)
+    }
+}
+
+impl From<u16> for AttributeValue {
+    fn from(value: u16) -> Self {
+        AttributeValue::U16(value)
+    }
+}
+
+impl From<U24> for AttributeValue {
+    fn from(value: U24) -> Self {
+        AttributeValue::U24(value)
+    }
+}
+
+impl From<u32> for AttributeValue {
+    fn from(value: u32) -> Self {
+        AttributeValue::U32(value)
+    }
+}
+
+impl From<u64> for AttributeValue {
+    fn from(value: u64) -> Self {
+        AttributeValue::U64(value)
+    }
+}
+
+impl From<i8> for AttributeValue {
+    fn from(value: i8) -> Self {
+        AttributeValue::I8(value)
+    }
+}
+
+impl From<i16> for AttributeValue {
+    fn from(value: i16) -> Self {
+        AttributeValue::I16(value)
+    }
+}
+
+impl From<I24> for AttributeValue {
+    fn from(value: I24) -> Self {
+        AttributeValue::I24(value)
+    }
+}
+
+impl From<i32> for AttributeValue {
+    fn from(value: i32) -> Self {
+        AttributeValue::I32(value)
+    }
+}
+
+impl From<i64> for AttributeValue {
+    fn from(value: i64) -> Self {
+        AttributeValue::I64(value)
+    }
+}
+
+impl From<F16> for AttributeValue {
+    fn from(value: F16) -> Self {
+        AttributeValue::F16(value)
+    }
+}
+
+impl From<f32> for AttributeValue {
+    fn from(value: f32) -> Self {
+        AttributeValue::F32(value)
+    }
+}
+
+impl From<f64> for AttributeValue {
+    fn from(value: f64) -> Self {
+        AttributeValue::F64(value)
+    }
+}
+
+impl From<&str> for AttributeValue {
+    fn from(value: &str) -> Self {
+        AttributeValue::String(value.to_string())
+    }
+}
+
+impl From<String> for AttributeValue {
+    fn from(value: String) -> Self {
+        AttributeValue::String(value)
+    }
+}
+
 impl std::fmt::Display for AttributeValue {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -37,18 +423,49 @@ impl std::fmt::Display for AttributeValue {
             AttributeValue::Uuid(u) => write!(f, "{}", u),
             AttributeValue::U8(u) => write!(f, "{}", u),
             AttributeValue::U16(u) => write!(f, "{}", u),
-            AttributeValue::U24(u) => write!(f, "U24"),
+            AttributeValue::U24(u) => write!(f, "{}", u),
             AttributeValue::U32(u) => write!(f, "{}", u),
             AttributeValue::U64(u) => write!(f, "{}", u),
             AttributeValue::I8(u) => write!(f, "{}", u),
             AttributeValue::I16(u) => write!(f, "{}", u),
-            AttributeValue::I24(u) => write!(f, "I24"),
+            AttributeValue::I24(u) => write!(f, "{}", u),
             AttributeValue::I32(u) => write!(f, "{}", u),
             AttributeValue::I64(u) => write!(f, "{}", u),
+            AttributeValue::F16(u) => write!(f, "{}", u),
             AttributeValue::F32(u) => write!(f, "{}", u),
             AttributeValue::F64(u) => write!(f, "{}", u),
+            AttributeValue::BoolArray(a) => write_array(f, a),
+            AttributeValue::StringArray(a) => write_array(f, a),
+            AttributeValue::UuidArray(a) => write_array(f, a),
+            AttributeValue::U8Array(a) => write_array(f, a),
+            AttributeValue::U16Array(a) => write_array(f, a),
+            AttributeValue::U24Array(a) => write_array(f, a),
+            AttributeValue::U32Array(a) => write_array(f, a),
+            AttributeValue::U64Array(a) => write_array(f, a),
+            AttributeValue::I8Array(a) => write_array(f, a),
+            AttributeValue::I16Array(a) => write_array(f, a),
+            AttributeValue::I24Array(a) => write_array(f, a),
+            AttributeValue::I32Array(a) => write_array(f, a),
+            AttributeValue::I64Array(a) => write_array(f, a),
+            AttributeValue::F16Array(a) => write_array(f, a),
+            AttributeValue::F32Array(a) => write_array(f, a),
+            AttributeValue::F64Array(a) => write_array(f, a),
+        }
+    }
+}
+
+fn write_array<T: std::fmt::Display>(
+    f: &mut std::fmt::Formatter<'_>,
+    values: &[T],
+) -> std::fmt::Result {
+    write!(f, "[")?;
+    for (i, v) in values.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
         }
+        write!(f, "{}", v)?;
     }
+    write!(f, "]")
 }
 
 pub fn attribute_type(value: &AttributeValue) -> &str {
@@ -66,17 +483,34 @@ pub fn attribute_type(value: &AttributeValue) -> &str {
         AttributeValue::I24(_) => "I24",
         AttributeValue::I32(_) => "I32",
         AttributeValue::I64(_) => "I64",
+        AttributeValue::F16(_) => "F16",
         AttributeValue::F32(_) => "F32",
         AttributeValue::F64(_) => "F64",
+        AttributeValue::BoolArray(_) => "BoolArray",
+        AttributeValue::StringArray(_) => "StringArray",
+        AttributeValue::UuidArray(_) => "UuidArray",
+        AttributeValue::U8Array(_) => "U8Array",
+        AttributeValue::U16Array(_) => "U16Array",
+        AttributeValue::U24Array(_) => "U24Array",
+        AttributeValue::U32Array(_) => "U32Array",
+        AttributeValue::U64Array(_) => "U64Array",
+        AttributeValue::I8Array(_) => "I8Array",
+        AttributeValue::I16Array(_) => "I16Array",
+        AttributeValue::I24Array(_) => "I24Array",
+        AttributeValue::I32Array(_) => "I32Array",
+        AttributeValue::I64Array(_) => "I64Array",
+        AttributeValue::F16Array(_) => "F16Array",
+        AttributeValue::F32Array(_) => "F32Array",
+        AttributeValue::F64Array(_) => "F64Array",
     }
 }
 
-#[derive(Debug, Clone, Default)]
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct AttributeStore {
     attributes: Vec<AttributeEntry>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct AttributeEntry {
     pub key: usize,
     pub value: AttributeValue,
@@ -101,6 +535,10 @@ impl AttributeStore {
             .map(|x| &x.value)
     }
 
+    pub(crate) fn remove(&mut self, key: usize) {
+        self.attributes.retain(|x| x.key != key);
+    }
+
     pub fn get_mut(&mut self, key: usize) -> Option<&mut AttributeValue> {
         self.attributes
             .iter_mut()
@@ -116,3 +554,61 @@ impl AttributeStore {
         self.attributes.len()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_u24_conversions() {
+        for value in [0u32, 1, 255, 256, 65535, 65536, 0xFF_FFFF] {
+            let u = U24::try_from(value).unwrap();
+            assert_eq!(u32::from(u), value);
+            assert_eq!(U24::from_be_bytes(u.to_be_bytes()), u);
+        }
+        assert!(U24::try_from(0x100_0000u32).is_err());
+    }
+
+    #[test]
+    fn test_i24_conversions() {
+        for value in [0i32, 1, -1, 127, -128, 32767, -32768, 0x7F_FFFF, -0x80_0000] {
+            let i = I24::try_from(value).unwrap();
+            assert_eq!(i32::from(i), value);
+            assert_eq!(I24::from_be_bytes(i.to_be_bytes()), i);
+        }
+        assert_eq!(I24::from_be_bytes([0xFF, 0xFF, 0xFE]).value(), -2);
+        assert!(I24::try_from(0x80_0000).is_err());
+        assert!(I24::try_from(-0x80_0001).is_err());
+    }
+
+    #[test]
+    fn test_f16_conversions() {
+        for value in [
+            0.0f32,
+            -0.0,
+            1.0,
+            -2.5,
+            0.099975586,
+            65504.0,
+            6.1035156e-5,
+            5.9604645e-8,
+        ] {
+            assert_eq!(F16::from_f32(value).to_f32(), value);
+        }
+        assert_eq!(F16::from_f32(1.0).to_bits(), 0x3C00);
+        assert_eq!(F16::from_f32(65520.0).to_f32(), f32::INFINITY);
+        assert_eq!(F16::from_f32(f32::NEG_INFINITY).to_f32(), f32::NEG_INFINITY);
+        assert!(F16::from_f32(f32::NAN).to_f32().is_nan());
+        // Ties round to even
+        assert_eq!(F16::from_f32(1.0 + 1.0 / 2048.0).to_bits(), 0x3C00);
+        assert_eq!(F16::from_f32(1.0 + 3.0 / 2048.0).to_bits(), 0x3C02);
+        assert_eq!(F16::from_f32(1e-10).to_bits(), 0);
+    }
+
+    #[test]
+    fn test_display_numeric() {
+        assert_eq!(AttributeValue::U24(U24::MAX).to_string(), "16777215");
+        assert_eq!(AttributeValue::I24(I24::MIN).to_string(), "-8388608");
+        assert_eq!(AttributeValue::F16(F16::from_f32(0.5)).to_string(), "0.5");
+    }
+}
@@ -0,0 +1,167 @@
+use crate::attributes::AttributeValue;
+use crate::node_id::NodeId;
+use crate::operation::Operation;
+use crate::session::Session;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+/// The operation that last set a field of a node. It is shown with operations numbered from 1, as
+/// in the history of a document.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Origin {
+    /// Index of the operation in the journal
+    pub index: usize,
+    /// Index of the first snapshot after the operation, which is the snapshot it is part of.
+    /// None if no snapshot has been taken since.
+    pub snapshot: Option<usize>,
+    /// Session the operation was made in, which tells the author. None for operations made
+    /// before the first session.
+    pub session: Option<Session>,
+}
+
+impl Display for Origin {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "operation {}", self.index + 1)?;
+        if let Some(session) = &self.session {
+            write!(f, " by {}", session)?;
+        }
+        if let Some(snapshot) = self.snapshot {
+            write!(f, " in snapshot {}", snapshot + 1)?;
+        }
+        Ok(())
+    }
+}
+
+/// Where the fields of a node were last set
+#[derive(Debug, Clone, PartialEq)]
+pub struct Blame {
+    /// The operation that added the node
+    pub created: Origin,
+    /// The last move to another parent or place, if the node has been moved
+    pub moved: Option<Origin>,
+    pub name: Option<Origin>,
+    pub type_id: Option<Origin>,
+    /// By attribute id
+    pub attributes: BTreeMap<usize, Origin>,
+    /// The operations that set the tags the node has, by tag id
+    pub tags: BTreeMap<usize, Origin>,
+}
+
+/// Snapshots and sessions of the operations in a line of the journal
+pub(crate) struct Origins<'a> {
+    operations: &'a [Operation],
+    /// Indices of the operations, oldest first
+    lineage: &'a [usize],
+    /// The first snapshot at or after each position in the lineage
+    snapshots: Vec<Option<usize>>,
+    /// The last session operation at or before each position in the lineage
+    sessions: Vec<Option<usize>>,
+}
+
+impl<'a> Origins<'a> {
+    pub(crate) fn new(operations: &'a [Operation], lineage: &'a [usize]) -> Origins<'a> {
+        let mut snapshots = vec![None; lineage.len()];
+        let mut next = None;
+        for (position, index) in lineage.iter().enumerate().rev() {
+            if let Operation::Snapshot { .. } = operations[*index] {
+                next = Some(*index);
+            }
+            snapshots[position] = next;
+        }
+        let mut sessions = vec![None; lineage.len()];
+        let mut last = None;
+        for (position, index) in lineage.iter().enumerate() {
+            if let Operation::Session { .. } = operations[*index] {
+                last = Some(*index);
+            }
+            sessions[position] = last;
+        }
+        Origins {
+            operations,
+            lineage,
+            snapshots,
+            sessions,
+        }
+    }
+
+    /// Origin of the operation at `position` in the lineage
+    fn at(&self, position: usize) -> Origin {
+        let index = self.lineage[position];
+        Origin {
+            index,
+            snapshot: self.snapshots[position],
+            session: self.sessions[position]
+                .and_then(|session| Session::from_operation(&self.operations[session])),
+        }
+    }
+
+    /// Where the fields of `node` were last set. None if no operation in the lineage added it.
+    pub(crate) fn blame(&self, node: NodeId) -> Option<Blame> {
+        let mut blame: Option<Blame> = None;
+        for (position, index) in self.lineage.iter().enumerate() {
+            let operation = &self.operations[*index];
+            if let Operation::AddNode { id, .. } = operation {
+                if *id == node {
+                    blame = Some(Blame {
+                        created: self.at(position),
+                        moved: None,
+                        name: None,
+                        type_id: None,
+                        attributes: BTreeMap::new(),
+                        tags: BTreeMap::new(),
+                    });
+                }
+                continue;
+            }
+            let Some(blame) = blame.as_mut() else {
+                continue;
+            };
+            match operation {
+                Operation::MoveNode { id, .. } if *id == node => {
+                    blame.moved = Some(self.at(position))
+                }
+                Operation::SetName { node: id, .. } if *id == node => {
+                    blame.name = Some(self.at(position))
+                }
+                Operation::SetType { node: id, .. } if *id == node => {
+                    blame.type_id = Some(self.at(position))
+                }
+                Operation::SetAttribute {
+                    node: id,
+                    attribute,
+                    ..
+                } if *id == node => {
+                    blame.attributes.insert(*attribute, self.at(position));
+                }
+                Operation::SetTag { node: id, tag } if *id == node => {
+                    blame.tags.insert(*tag, self.at(position));
+                }
+                Operation::RemoveTag { node: id, tag } if *id == node => {
+                    blame.tags.remove(tag);
+                }
+                _ => {}
+            }
+        }
+        blame
+    }
+
+    /// Every value that has been set for an attribute of a node, oldest first
+    pub(crate) fn attribute_history(
+        &self,
+        node: NodeId,
+        attribute: usize,
+    ) -> Vec<(Origin, AttributeValue)> {
+        self.lineage
+            .iter()
+            .enumerate()
+            .filter_map(|(position, index)| match &self.operations[*index] {
+                Operation::SetAttribute {
+                    node: id,
+                    attribute: a,
+                    value,
+                } if *id == node && *a == attribute => Some((self.at(position), value.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,279 @@
+use crate::error::ApplyError;
+use crate::operation::Operation;
+use std::fmt::{Display, Formatter};
+
+/// Name of the branch a journal starts on
+pub const MAIN_BRANCH: &str = "main";
+
+/// A named line of revisions in a journal
+#[derive(Debug, Clone, PartialEq)]
+pub struct Branch {
+    pub name: String,
+    /// The branch is at the state after the first `head` operations of the journal
+    pub head: usize,
+}
+
+impl Display for Branch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (revision {})", self.name, self.head)
+    }
+}
+
+/// The branches in a journal, and the one operations are added to
+#[derive(Debug, Clone, PartialEq)]
+pub struct Branches {
+    /// In the order they were created
+    branches: Vec<Branch>,
+    current: usize,
+}
+
+impl Default for Branches {
+    fn default() -> Self {
+        Branches {
+            branches: vec![Branch {
+                name: MAIN_BRANCH.to_string(),
+                head: 0,
+            }],
+            current: 0,
+        }
+    }
+}
+
+impl Branches {
+    /// Follow the branch operations in a journal. Reading stops at the first branch operation
+    /// that is not valid, which is returned with its index.
+    pub(crate) fn read(operations: &[Operation]) -> (Branches, Option<(usize, ApplyError)>) {
+        let mut branches = Branches::default();
+        for (index, operation) in operations.iter().enumerate() {
+            if let Err(error) = branches.apply(index, operation) {
+                return (branches, Some((index, error)));
+            }
+        }
+        (branches, None)
+    }
+
+    /// Follow operation number `index` of the journal. Nothing is changed if it is not valid.
+    pub(crate) fn apply(&mut self, index: usize, operation: &Operation) -> Result<(), ApplyError> {
+        match operation {
+            Operation::Undo { revision } | Operation::Redo { revision } if *revision > index => {
+                return Err(ApplyError::UnknownRevision {
+                    revision: *revision,
+                });
+            }
+            Operation::Branch { name, revision } => {
+                if *revision > index {
+                    return Err(ApplyError::UnknownRevision {
+                        revision: *revision,
+                    });
+                }
+                self.current = match self.position(name) {
+                    Some(position) => position,
+                    None => {
+                        self.branches.push(Branch {
+                            name: name.clone(),
+                            head: 0,
+                        });
+                        self.branches.len() - 1
+                    }
+                };
+            }
+            Operation::DeleteBranch { name } => {
+                let position = self
+                    .position(name)
+                    .ok_or_else(|| ApplyError::UnknownBranch { name: name.clone() })?;
+                if position == self.current {
+                    return Err(ApplyError::CurrentBranch { name: name.clone() });
+                }
+                self.branches.remove(position);
+                if position < self.current {
+                    self.current -= 1;
+                }
+            }
+            _ => {}
+        }
+        self.advance(index);
+        Ok(())
+    }
+
+    /// Operation number `index` of the journal belongs to the current branch
+    pub(crate) fn advance(&mut self, index: usize) {
+        self.branches[self.current].head = index + 1;
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.branches.iter().position(|b| b.name == name)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Branch> {
+        self.branches.iter().find(|b| b.name == name)
+    }
+
+    /// The branch new operations are added to
+    pub fn current(&self) -> &Branch {
+        &self.branches[self.current]
+    }
+
+    pub fn list(&self) -> &[Branch] {
+        &self.branches
+    }
+}
+
+/// True for the operations that the document follows instead of the nodes: the ones that create,
+/// switch or delete branches, and undo and redo
+pub(crate) fn is_branch_operation(operation: &Operation) -> bool {
+    matches!(
+        operation,
+        Operation::Branch { .. }
+            | Operation::DeleteBranch { .. }
+            | Operation::Undo { .. }
+            | Operation::Redo { .. }
+    )
+}
+
+/// The revision whose state an operation continues from, for the operations that go back to an
+/// earlier state
+fn continues_from(operation: &Operation) -> Option<usize> {
+    match operation {
+        Operation::Branch { revision, .. }
+        | Operation::Undo { revision }
+        | Operation::Redo { revision } => Some(*revision),
+        _ => None,
+    }
+}
+
+/// Indices of the operations to replay for the state after the first `end` operations, oldest
+/// first. Going back from `end`, a branch, undo or redo operation continues at the revision it
+/// goes back to. The walk stops at revision 0 or at the first revision `stop` returns true for, which is
+/// returned as the revision to replay from.
+pub(crate) fn lineage(
+    operations: &[Operation],
+    end: usize,
+    stop: impl Fn(usize) -> bool,
+) -> (usize, Vec<usize>) {
+    let mut indices = vec![];
+    let mut revision = end;
+    while revision > 0 && !stop(revision) {
+        match continues_from(&operations[revision - 1]) {
+            Some(from) if from < revision => revision = from,
+            _ => {
+                revision -= 1;
+                indices.push(revision);
+            }
+        }
+    }
+    indices.reverse();
+    (revision, indices)
+}
+
+/// Indices of the operations that lead to the state after the first `end` operations, newest
+/// first, as in `lineage`
+pub(crate) fn lineage_back(operations: &[Operation], end: usize) -> impl Iterator<Item = usize> {
+    let mut revision = end;
+    std::iter::from_fn(move || {
+        while revision > 0 {
+            match continues_from(&operations[revision - 1]) {
+                Some(from) if from < revision => revision = from,
+                _ => {
+                    revision -= 1;
+                    return Some(revision);
+                }
+            }
+        }
+        None
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn branch(name: &str, revision: usize) -> Operation {
+        Operation::Branch {
+            name: name.to_string(),
+            revision,
+        }
+    }
+
+    fn snapshot() -> Operation {
+        Operation::Snapshot {
+            author: "test".to_string(),
+            message: String::new(),
+            time: None,
+        }
+    }
+
+    #[test]
+    fn test_lineage() {
+        let operations = vec![
+            snapshot(),
+            snapshot(),
+            branch("feature", 1),
+            snapshot(),
+            branch(MAIN_BRANCH, 2),
+            snapshot(),
+        ];
+
+        assert_eq!(lineage(&operations, 6, |_| false), (0, vec![0, 1, 5]));
+        assert_eq!(lineage(&operations, 4, |_| false), (0, vec![0, 3]));
+        assert_eq!(lineage(&operations, 6, |r| r == 1), (1, vec![1, 5]));
+        assert!(lineage_back(&operations, 6).eq([5, 1, 0]));
+
+        let (branches, error) = Branches::read(&operations);
+        assert!(error.is_none());
+        assert_eq!(branches.current().name, MAIN_BRANCH);
+        assert_eq!(branches.current().head, 6);
+        assert_eq!(branches.get("feature").unwrap().head, 4);
+    }
+
+    #[test]
+    fn test_undo_lineage() {
+        let operations = vec![
+            snapshot(),
+            snapshot(),
+            Operation::Undo { revision: 1 },
+            snapshot(),
+            Operation::Redo { revision: 2 },
+        ];
+
+        assert_eq!(lineage(&operations, 5, |_| false), (0, vec![0, 1]));
+        assert_eq!(lineage(&operations, 4, |_| false), (0, vec![0, 3]));
+        assert!(lineage_back(&operations, 3).eq([0]));
+
+        let (branches, error) = Branches::read(&operations);
+        assert!(error.is_none());
+        assert_eq!(branches.current().head, 5);
+        assert_eq!(
+            Branches::default().apply(0, &Operation::Undo { revision: 1 }),
+            Err(ApplyError::UnknownRevision { revision: 1 })
+        );
+    }
+
+    #[test]
+    fn test_invalid_branch_operations() {
+        let operations = vec![
+            snapshot(),
+            branch("feature", 1),
+            Operation::DeleteBranch {
+                name: "feature".to_string(),
+            },
+        ];
+        let (branches, error) = Branches::read(&operations);
+        assert_eq!(
+            error,
+            Some((
+                2,
+                ApplyError::CurrentBranch {
+                    name: "feature".to_string()
+                }
+            ))
+        );
+        assert_eq!(branches.list().len(), 2);
+
+        let mut branches = Branches::default();
+        assert_eq!(
+            branches.apply(0, &branch("feature", 1)),
+            Err(ApplyError::UnknownRevision { revision: 1 })
+        );
+        assert_eq!(branches, Branches::default());
+    }
+}
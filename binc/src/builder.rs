@@ -1,111 +1,135 @@
 use crate::attributes::AttributeValue;
 use crate::document::Document;
+use crate::error::ApplyError;
 use crate::node_id::NodeId;
 use crate::operation::Operation;
 
 pub trait NodeBuilder {
-    fn add_node(&mut self, parent: NodeId) -> NodeId;
-    fn insert_node(&mut self, parent: NodeId, index: usize) -> NodeId;
-
-    fn set_node_name(&mut self, node_id: NodeId, name: &str);
-    fn set_node_type(&mut self, node_id: NodeId, type_name: &str);
-    fn set_node_attribute_s(&mut self, node_id: NodeId, attribute: &str, name: &str);
-    fn set_node_tag(&mut self, node_id: NodeId, tag: &str);
+    fn add_node(&mut self, parent: NodeId) -> Result<NodeId, ApplyError>;
+    fn insert_node(&mut self, parent: NodeId, index: usize) -> Result<NodeId, ApplyError>;
+
+    fn set_node_name(&mut self, node_id: NodeId, name: &str) -> Result<(), ApplyError>;
+    fn set_node_type(&mut self, node_id: NodeId, type_name: &str) -> Result<(), ApplyError>;
+    fn set_node_attribute(
+        &mut self,
+        node_id: NodeId,
+        attribute: &str,
+        value: AttributeValue,
+    ) -> Result<(), ApplyError>;
+    fn set_node_attribute_s(
+        &mut self,
+        node_id: NodeId,
+        attribute: &str,
+        name: &str,
+    ) -> Result<(), ApplyError>;
+    fn set_node_tag(&mut self, node_id: NodeId, tag: &str) -> Result<(), ApplyError>;
 }
 
 impl NodeBuilder for Document {
-    fn add_node(&mut self, parent: NodeId) -> NodeId {
-        let id = self.node_id_generator.next_id();
-        let index_in_parent = self
-            .nodes
-            .get(parent)
-            .expect("Parent must exist")
-            .children
-            .len();
-        self.add_and_apply(Operation::AddNode {
-            id,
-            parent,
-            index_in_parent,
-        });
-        id
+    fn add_node(&mut self, parent: NodeId) -> Result<NodeId, ApplyError> {
+        let index_in_parent = match self.nodes.get(parent) {
+            Some(p) if self.nodes.exists(parent) => p.children.len(),
+            _ => return Err(ApplyError::ParentMissing { parent }),
+        };
+        self.insert_node(parent, index_in_parent)
     }
 
-    fn insert_node(&mut self, parent: NodeId, index: usize) -> NodeId {
-        let id = self.node_id_generator.next_id();
+    fn insert_node(&mut self, parent: NodeId, index: usize) -> Result<NodeId, ApplyError> {
+        let id = self.next_id();
         self.add_and_apply(Operation::AddNode {
             id,
             parent,
             index_in_parent: index,
-        });
-        id
+        })?;
+        Ok(id)
     }
 
-    fn set_node_name(&mut self, node_id: NodeId, name: &str) {
+    fn set_node_name(&mut self, node_id: NodeId, name: &str) -> Result<(), ApplyError> {
         self.add_and_apply(Operation::SetName {
             node: node_id,
             name: name.to_string(),
-        });
+        })
     }
 
-    fn set_node_type(&mut self, node_id: NodeId, type_name: &str) {
-        let t = self.nodes.type_names.get_index(type_name);
-
-        let t = if t.is_none() {
-            let new_id = self.nodes.type_names.len();
-            self.add_and_apply(Operation::DefineTypeName {
-                id: new_id,
-                name: type_name.to_string(),
-            });
-            new_id
-        } else {
-            t.unwrap()
-        };
-
-        self.add_and_apply(Operation::SetType {
-            node: node_id,
-            type_id: t,
-        });
+    fn set_node_type(&mut self, node_id: NodeId, type_name: &str) -> Result<(), ApplyError> {
+        self.transaction(|d| {
+            let t = d.nodes.type_names.get_index(type_name);
+
+            let t = if t.is_none() {
+                let new_id = d.nodes.type_names.len();
+                d.add_and_apply(Operation::DefineTypeName {
+                    id: new_id,
+                    name: type_name.to_string(),
+                })?;
+                new_id
+            } else {
+                t.unwrap()
+            };
+
+            d.add_and_apply(Operation::SetType {
+                node: node_id,
+                type_id: t,
+            })
+        })
     }
 
-    fn set_node_attribute_s(&mut self, node_id: NodeId, attribute: &str, name: &str) {
-        let attr = self.nodes.attribute_names.get_index(attribute);
-
-        let attr = if attr.is_none() {
-            let new_id = self.nodes.attribute_names.len();
-            self.add_and_apply(Operation::DefineAttributeName {
-                id: new_id,
-                name: attribute.to_string(),
-            });
-            new_id
-        } else {
-            attr.unwrap()
-        };
-
-        self.add_and_apply(Operation::SetAttribute {
-            node: node_id,
-            attribute: attr,
-            value: AttributeValue::String(name.to_string()),
-        });
+    fn set_node_attribute(
+        &mut self,
+        node_id: NodeId,
+        attribute: &str,
+        value: AttributeValue,
+    ) -> Result<(), ApplyError> {
+        self.transaction(|d| {
+            let attr = d.nodes.attribute_names.get_index(attribute);
+
+            let attr = if attr.is_none() {
+                let new_id = d.nodes.attribute_names.len();
+                d.add_and_apply(Operation::DefineAttributeName {
+                    id: new_id,
+                    name: attribute.to_string(),
+                })?;
+                new_id
+            } else {
+                attr.unwrap()
+            };
+
+            d.add_and_apply(Operation::SetAttribute {
+                node: node_id,
+                attribute: attr,
+                value,
+            })
+        })
     }
 
-    fn set_node_tag(&mut self, node_id: NodeId, tag: &str) {
-        let t = self.nodes.tag_names.get_index(tag);
-
-        let t = if t.is_none() {
-            let new_id = self.nodes.tag_names.len();
-            self.add_and_apply(Operation::DefineTagName {
-                id: new_id,
-                name: tag.to_string(),
-            });
-            new_id
-        } else {
-            t.unwrap()
-        };
+    fn set_node_attribute_s(
+        &mut self,
+        node_id: NodeId,
+        attribute: &str,
+        name: &str,
+    ) -> Result<(), ApplyError> {
+        self.set_node_attribute(node_id, attribute, AttributeValue::String(name.to_string()))
+    }
 
-        self.add_and_apply(Operation::SetTag {
-            node: node_id,
-            tag: t,
-        });
+    fn set_node_tag(&mut self, node_id: NodeId, tag: &str) -> Result<(), ApplyError> {
+        self.transaction(|d| {
+            let t = d.nodes.tag_names.get_index(tag);
+
+            let t = if t.is_none() {
+                let new_id = d.nodes.tag_names.len();
+                d.add_and_apply(Operation::DefineTagName {
+                    id: new_id,
+                    name: tag.to_string(),
+                })?;
+                new_id
+            } else {
+                t.unwrap()
+            };
+
+            d.add_and_apply(Operation::SetTag {
+                node: node_id,
+                tag: t,
+            })
+        })
     }
 }
 
@@ -116,11 +140,60 @@ mod tests {
     #[test]
     fn test_add_child() {
         let mut document = Document::default();
-        let a = document.add_node(NodeId::ROOT_NODE);
-        document.set_node_name(a, "hey");
-        let b = document.add_node(NodeId::ROOT_NODE);
-        document.set_node_name(b, "hey");
-        document.set_node_attribute_s(b, "speed", "high");
+        let a = document.add_node(NodeId::ROOT_NODE).unwrap();
+        document.set_node_name(a, "hey").unwrap();
+        let b = document.add_node(NodeId::ROOT_NODE).unwrap();
+        document.set_node_name(b, "hey").unwrap();
+        document.set_node_attribute_s(b, "speed", "high").unwrap();
         assert_eq!(document.find_roots().len(), 2)
     }
+
+    #[test]
+    fn test_set_array_attribute() {
+        let mut document = Document::default();
+        let a = document.add_node(NodeId::ROOT_NODE).unwrap();
+        document
+            .set_node_attribute(a, "samples", AttributeValue::F32Array(vec![0.5, -1.0]))
+            .unwrap();
+        let samples = document.nodes.attribute_names.get_index("samples").unwrap();
+        let node = document.nodes.get(a).unwrap();
+        assert_eq!(
+            node.get_f32_array_attribute(samples),
+            Some(&[0.5, -1.0][..])
+        );
+        assert_eq!(node.get_string_array_attribute(samples), None);
+    }
+
+    #[test]
+    fn test_undo_name_with_value() {
+        let mut document = Document::default();
+        let a = document.add_node(NodeId::ROOT_NODE).unwrap();
+        document.set_node_type(a, "task").unwrap();
+        document.set_node_attribute_s(a, "speed", "high").unwrap();
+        document.set_node_tag(a, "later").unwrap();
+
+        document.undo();
+        assert!(document.nodes.tag_names.get_index("later").is_none());
+        document.undo();
+        assert!(document.nodes.attribute_names.get_index("speed").is_none());
+        document.undo();
+        assert!(document.nodes.type_names.get_index("task").is_none());
+        let node = document.nodes.get(a).unwrap();
+        assert!(node.type_id.is_none() && node.attributes.iter().next().is_none());
+        assert!(document.nodes.exists(a));
+    }
+
+    #[test]
+    fn test_invalid_parent() {
+        let mut document = Document::default();
+        let a = document.add_node(NodeId::ROOT_NODE).unwrap();
+        assert_eq!(
+            document.add_node(NodeId::new(5)),
+            Err(ApplyError::ParentMissing {
+                parent: NodeId::new(5)
+            })
+        );
+        assert!(document.insert_node(a, 1).is_err());
+        assert_eq!(document.num_operations(), 2);
+    }
 }
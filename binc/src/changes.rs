@@ -1,6 +1,10 @@
-use crate::attributes::AttributeValue;
+use crate::attributes::{AttributeValue, F16, I24, U24};
 use crate::node_id::NodeId;
 use crate::operation::Operation;
+use crate::session::Session;
+use crate::sources::Source;
+use crate::util::current_time;
+use uuid::Uuid;
 
 #[derive(Debug, Clone, Default)]
 pub struct Changes {
@@ -46,6 +50,81 @@ impl Changes {
         self
     }
 
+    /// The operations that follow are made in `session`
+    pub fn start_session(&mut self, session: &Session) -> &mut Self {
+        self.operations.push(session.to_operation());
+        self
+    }
+
+    /// Record that the subtree at `node` was imported from `source`
+    pub fn add_source(&mut self, id: usize, node: NodeId, source: &Source) -> &mut Self {
+        self.operations.push(Operation::AddSource {
+            id,
+            node,
+            uri: source.uri.clone(),
+            hash: source.hash.clone(),
+            time: source.time,
+        });
+        self
+    }
+
+    pub fn update_source(&mut self, id: usize, source: &Source) -> &mut Self {
+        self.operations.push(Operation::UpdateSource {
+            id,
+            uri: source.uri.clone(),
+            hash: source.hash.clone(),
+            time: source.time,
+        });
+        self
+    }
+
+    pub fn remove_source(&mut self, id: usize) -> &mut Self {
+        self.operations.push(Operation::RemoveSource { id });
+        self
+    }
+
+    /// Comment on `node`. Use `Comments::next_id` of the node for the id.
+    pub fn add_comment(
+        &mut self,
+        node: NodeId,
+        id: usize,
+        comment: &str,
+        author: &str,
+        response_to: Option<usize>,
+    ) -> &mut Self {
+        self.operations.push(Operation::AddComment {
+            node,
+            id: Some(id),
+            comment: comment.to_string(),
+            author: author.to_string(),
+            response_to: response_to.unwrap_or(0),
+            time: Some(current_time()),
+        });
+        self
+    }
+
+    pub fn edit_comment(&mut self, node: NodeId, id: usize, comment: &str) -> &mut Self {
+        self.operations.push(Operation::EditComment {
+            node,
+            id,
+            comment: comment.to_string(),
+            time: current_time(),
+        });
+        self
+    }
+
+    pub fn delete_comment(&mut self, node: NodeId, id: usize) -> &mut Self {
+        self.operations.push(Operation::DeleteComment { node, id });
+        self
+    }
+
+    /// Resolve a comment, or reopen it if `resolved` is false
+    pub fn resolve_comment(&mut self, node: NodeId, id: usize, resolved: bool) -> &mut Self {
+        self.operations
+            .push(Operation::ResolveComment { node, id, resolved });
+        self
+    }
+
     pub fn set_type_s(&mut self, node: NodeId, type_name: &str) -> &mut Self {
         let id = self.get_or_add_type_id(type_name);
         self.set_type(node, id)
@@ -67,6 +146,26 @@ impl Changes {
         self
     }
 
+    pub fn add_tag_s(&mut self, node: NodeId, tag: &str) -> &mut Self {
+        let id = self.get_or_add_tag_id(tag);
+        self.add_tag(node, id)
+    }
+
+    pub fn add_tag(&mut self, node: NodeId, tag: usize) -> &mut Self {
+        self.operations.push(Operation::SetTag { node, tag });
+        self
+    }
+
+    pub fn remove_tag_s(&mut self, node: NodeId, tag: &str) -> &mut Self {
+        let id = self.get_or_add_tag_id(tag);
+        self.remove_tag(node, id)
+    }
+
+    pub fn remove_tag(&mut self, node: NodeId, tag: usize) -> &mut Self {
+        self.operations.push(Operation::RemoveTag { node, tag });
+        self
+    }
+
     pub fn set_string_s(&mut self, node: NodeId, attribute: &str, value: &str) -> &mut Self {
         let id = self.get_or_add_attribute_id(attribute);
         self.set_string(node, id, value)
@@ -90,6 +189,99 @@ impl Changes {
         self
     }
 
+    pub fn set_attribute_s(
+        &mut self,
+        node: NodeId,
+        attribute: &str,
+        value: AttributeValue,
+    ) -> &mut Self {
+        let id = self.get_or_add_attribute_id(attribute);
+        self.set_attribute(node, id, value)
+    }
+
+    pub fn set_attribute(
+        &mut self,
+        node: NodeId,
+        attribute: usize,
+        value: AttributeValue,
+    ) -> &mut Self {
+        self.operations.push(Operation::SetAttribute {
+            node,
+            attribute,
+            value,
+        });
+        self
+    }
+
+    pub fn set_bool_array(&mut self, node: NodeId, attribute: usize, value: &[bool]) -> &mut Self {
+        self.set_attribute(node, attribute, AttributeValue::BoolArray(value.to_vec()))
+    }
+
+    pub fn set_string_array(
+        &mut self,
+        node: NodeId,
+        attribute: usize,
+        value: &[String],
+    ) -> &mut Self {
+        self.set_attribute(node, attribute, AttributeValue::StringArray(value.to_vec()))
+    }
+
+    pub fn set_uuid_array(&mut self, node: NodeId, attribute: usize, value: &[Uuid]) -> &mut Self {
+        self.set_attribute(node, attribute, AttributeValue::UuidArray(value.to_vec()))
+    }
+
+    pub fn set_u8_array(&mut self, node: NodeId, attribute: usize, value: &[u8]) -> &mut Self {
+        self.set_attribute(node, attribute, AttributeValue::U8Array(value.to_vec()))
+    }
+
+    pub fn set_u16_array(&mut self, node: NodeId, attribute: usize, value: &[u16]) -> &mut Self {
+        self.set_attribute(node, attribute, AttributeValue::U16Array(value.to_vec()))
+    }
+
+    pub fn set_u24_array(&mut self, node: NodeId, attribute: usize, value: &[U24]) -> &mut Self {
+        self.set_attribute(node, attribute, AttributeValue::U24Array(value.to_vec()))
+    }
+
+    pub fn set_u32_array(&mut self, node: NodeId, attribute: usize, value: &[u32]) -> &mut Self {
+        self.set_attribute(node, attribute, AttributeValue::U32Array(value.to_vec()))
+    }
+
+    pub fn set_u64_array(&mut self, node: NodeId, attribute: usize, value: &[u64]) -> &mut Self {
+        self.set_attribute(node, attribute, AttributeValue::U64Array(value.to_vec()))
+    }
+
+    pub fn set_i8_array(&mut self, node: NodeId, attribute: usize, value: &[i8]) -> &mut Self {
+        self.set_attribute(node, attribute, AttributeValue::I8Array(value.to_vec()))
+    }
+
+    pub fn set_i16_array(&mut self, node: NodeId, attribute: usize, value: &[i16]) -> &mut Self {
+        self.set_attribute(node, attribute, AttributeValue::I16Array(value.to_vec()))
+    }
+
+    pub fn set_i24_array(&mut self, node: NodeId, attribute: usize, value: &[I24]) -> &mut Self {
+        self.set_attribute(node, attribute, AttributeValue::I24Array(value.to_vec()))
+    }
+
+    pub fn set_i32_array(&mut self, node: NodeId, attribute: usize, value: &[i32]) -> &mut Self {
+        self.set_attribute(node, attribute, AttributeValue::I32Array(value.to_vec()))
+    }
+
+    pub fn set_i64_array(&mut self, node: NodeId, attribute: usize, value: &[i64]) -> &mut Self {
+        self.set_attribute(node, attribute, AttributeValue::I64Array(value.to_vec()))
+    }
+
+    pub fn set_f16_array(&mut self, node: NodeId, attribute: usize, value: &[F16]) -> &mut Self {
+        self.set_attribute(node, attribute, AttributeValue::F16Array(value.to_vec()))
+    }
+
+    pub fn set_f32_array(&mut self, node: NodeId, attribute: usize, value: &[f32]) -> &mut Self {
+        self.set_attribute(node, attribute, AttributeValue::F32Array(value.to_vec()))
+    }
+
+    pub fn set_f64_array(&mut self, node: NodeId, attribute: usize, value: &[f64]) -> &mut Self {
+        self.set_attribute(node, attribute, AttributeValue::F64Array(value.to_vec()))
+    }
+
     fn get_or_add_attribute_id(&mut self, attribute_name: &str) -> usize {
         let mut next_id = 0;
         for c in &self.operations {
@@ -131,4 +323,22 @@ impl Changes {
         });
         next_id
     }
+
+    fn get_or_add_tag_id(&mut self, tag_name: &str) -> usize {
+        let mut next_id = 0;
+        for c in &self.operations {
+            if let Operation::DefineTagName { id, name } = c {
+                if tag_name == name {
+                    return *id;
+                }
+                next_id = *id + 1;
+            }
+        }
+
+        self.operations.push(Operation::DefineTagName {
+            id: next_id,
+            name: tag_name.to_string(),
+        });
+        next_id
+    }
 }
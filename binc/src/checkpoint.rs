@@ -0,0 +1,26 @@
+use crate::compaction;
+use crate::journal::JournalReader;
+use crate::node_store::NodeStore;
+use crate::operation::Operation;
+use std::io;
+
+/// Create a checkpoint operation holding the state of `nodes` after the first `revision` operations.
+///
+/// The state is stored as the compacted operations that rebuild it.
+pub(crate) fn checkpoint_operation(nodes: &NodeStore, revision: usize) -> io::Result<Operation> {
+    let mut data = vec![];
+    for operation in compaction::compact(nodes, &[]).operations {
+        operation.write(&mut data)?;
+    }
+    Ok(Operation::Checkpoint { revision, data })
+}
+
+/// Rebuild the state stored in a checkpoint, or None if the data is damaged
+pub(crate) fn restore(data: &[u8]) -> Option<NodeStore> {
+    let mut nodes = NodeStore::new();
+    for item in JournalReader::without_header(data, 0) {
+        let (_, operation) = item.ok()?;
+        operation.apply(&mut nodes).ok()?;
+    }
+    Some(nodes)
+}
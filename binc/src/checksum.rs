@@ -0,0 +1,130 @@
+use crate::operation::Operation;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::io::Read;
+use std::ops::Range;
+
+/// Outcome of checking one range of a journal against its checksum
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChecksumStatus {
+    /// The checksum at the end of the range matches its content
+    Valid,
+    /// The checksum does not match, or the range could not be parsed
+    Corrupted,
+    /// Operations after the last checksum, which have nothing to be checked against
+    Unchecked,
+}
+
+/// A range of the file covered by a single checksum
+#[derive(Debug, Clone)]
+pub struct ChecksumRange {
+    /// Byte range in the file, excluding the checksum operation itself
+    pub bytes: Range<u64>,
+    /// Indices of the operations in the range
+    pub operations: Range<usize>,
+    pub status: ChecksumStatus,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Verification {
+    pub ranges: Vec<ChecksumRange>,
+}
+
+impl Verification {
+    /// True if no range is corrupted. Unchecked ranges are not considered corrupt.
+    pub fn is_valid(&self) -> bool {
+        self.ranges
+            .iter()
+            .all(|r| r.status != ChecksumStatus::Corrupted)
+    }
+
+    pub fn corrupted(&self) -> impl Iterator<Item = &ChecksumRange> {
+        self.ranges
+            .iter()
+            .filter(|r| r.status == ChecksumStatus::Corrupted)
+    }
+}
+
+impl Display for ChecksumRange {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "bytes {}..{}, operations {}..{}: {:?}",
+            self.bytes.start,
+            self.bytes.end,
+            self.operations.start,
+            self.operations.end,
+            self.status
+        )
+    }
+}
+
+/// Compute a checksum operation covering all the given operations, as they are written to a file
+pub(crate) fn checksum_operation(operations: &[Operation]) -> io::Result<Operation> {
+    let mut hasher = blake3::Hasher::new();
+    for operation in operations {
+        operation.write(&mut hasher)?;
+    }
+    Ok(Operation::Checksum {
+        data: hasher.finalize().as_bytes().to_vec(),
+    })
+}
+
+/// Walk the operations in `r`, which starts at byte `offset` of the file, and check every
+/// checksum against the bytes since the previous checksum
+pub(crate) fn verify<T: Read>(r: &mut T, offset: u64) -> io::Result<Verification> {
+    let mut data = vec![];
+    r.read_to_end(&mut data)?;
+
+    let mut verification = Verification::default();
+    let mut range_start = 0;
+    let mut first_operation = 0;
+    let mut index = 0;
+    let mut position = 0;
+
+    while position < data.len() {
+        let mut cursor = &data[position..];
+        let operation = match Operation::read(&mut cursor) {
+            Ok(operation) => operation,
+            Err(_) => {
+                // Everything from the start of this range is suspect, as it cannot be parsed
+                verification.ranges.push(ChecksumRange {
+                    bytes: offset + range_start as u64..offset + data.len() as u64,
+                    operations: first_operation..index,
+                    status: ChecksumStatus::Corrupted,
+                });
+                return Ok(verification);
+            }
+        };
+        let end = data.len() - cursor.len();
+
+        if let Operation::Checksum { data: hash } = &operation {
+            let actual = blake3::hash(&data[range_start..position]);
+            let status = if actual.as_bytes().as_slice() == hash.as_slice() {
+                ChecksumStatus::Valid
+            } else {
+                ChecksumStatus::Corrupted
+            };
+            verification.ranges.push(ChecksumRange {
+                bytes: offset + range_start as u64..offset + position as u64,
+                operations: first_operation..index,
+                status,
+            });
+            range_start = end;
+            first_operation = index + 1;
+        }
+
+        position = end;
+        index += 1;
+    }
+
+    if range_start < data.len() {
+        verification.ranges.push(ChecksumRange {
+            bytes: offset + range_start as u64..offset + data.len() as u64,
+            operations: first_operation..index,
+            status: ChecksumStatus::Unchecked,
+        });
+    }
+
+    Ok(verification)
+}
@@ -1,22 +1,147 @@
-#[derive(Debug, Default)]
+use chrono::{DateTime, Utc};
+
+/// Comments on a node, in the order they were added
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct Comments {
     pub comments: Vec<Comment>,
 }
 
 impl Comments {
-    pub(crate) fn add_comment(&mut self, comment: &str, author: &str, response_to: usize) {
+    pub fn get(&self, id: usize) -> Option<&Comment> {
+        self.comments.iter().find(|c| c.id == id)
+    }
+
+    /// Id that is not used by any comment. Ids start at 1, since 0 means "not a response".
+    pub fn next_id(&self) -> usize {
+        self.comments.iter().map(|c| c.id).max().unwrap_or(0) + 1
+    }
+
+    pub fn len(&self) -> usize {
+        self.comments.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.comments.is_empty()
+    }
+
+    /// The comments as a tree of responses. Comments that respond to a missing comment are roots.
+    pub fn threads(&self) -> Vec<CommentThread<'_>> {
+        self.comments
+            .iter()
+            .filter(|c| c.response_to.is_none_or(|id| self.get(id).is_none()))
+            .map(|c| self.thread(c))
+            .collect()
+    }
+
+    fn thread<'a>(&'a self, comment: &'a Comment) -> CommentThread<'a> {
+        let replies = self
+            .comments
+            .iter()
+            .filter(|c| c.response_to == Some(comment.id) && c.id != comment.id)
+            .map(|c| self.thread(c))
+            .collect();
+        CommentThread { comment, replies }
+    }
+
+    /// Add a comment. Without an id (from files written before comments had ids) the next free
+    /// id is used.
+    pub(crate) fn add_comment(
+        &mut self,
+        id: Option<usize>,
+        comment: &str,
+        author: &str,
+        response_to: usize,
+        time: Option<DateTime<Utc>>,
+    ) {
         let comment = Comment {
+            id: id.unwrap_or_else(|| self.next_id()),
             author: author.to_string(),
             text: comment.to_string(),
-            response_to: Some(response_to),
+            response_to: (response_to != 0).then_some(response_to),
+            time,
+            edited: None,
+            deleted: false,
+            resolved: false,
         };
         self.comments.push(comment);
     }
+
+    pub(crate) fn edit_comment(&mut self, id: usize, comment: &str, time: DateTime<Utc>) {
+        if let Some(c) = self.get_mut(id) {
+            c.text = comment.to_string();
+            c.edited = Some(time);
+        }
+    }
+
+    /// Delete the text of a comment, but keep it in place so responses to it stay in the thread
+    pub(crate) fn delete_comment(&mut self, id: usize) {
+        if let Some(c) = self.get_mut(id) {
+            c.text.clear();
+            c.deleted = true;
+        }
+    }
+
+    pub(crate) fn set_resolved(&mut self, id: usize, resolved: bool) {
+        if let Some(c) = self.get_mut(id) {
+            c.resolved = resolved;
+        }
+    }
+
+    fn get_mut(&mut self, id: usize) -> Option<&mut Comment> {
+        self.comments.iter_mut().find(|c| c.id == id)
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Comment {
+    pub id: usize,
     pub author: String,
     pub text: String,
     pub response_to: Option<usize>,
+    /// When the comment was written, if known
+    pub time: Option<DateTime<Utc>>,
+    /// When the text was last edited
+    pub edited: Option<DateTime<Utc>>,
+    pub deleted: bool,
+    pub resolved: bool,
+}
+
+/// A comment and all responses to it
+#[derive(Debug)]
+pub struct CommentThread<'a> {
+    pub comment: &'a Comment,
+    pub replies: Vec<CommentThread<'a>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn comment_threads() {
+        let mut comments = Comments::default();
+        comments.add_comment(None, "first", "a", 0, None);
+        comments.add_comment(None, "second", "b", 0, None);
+        comments.add_comment(Some(5), "reply", "b", 1, None);
+        comments.add_comment(None, "reply to reply", "a", 5, None);
+        comments.add_comment(None, "orphan", "a", 3, None);
+
+        assert_eq!(comments.next_id(), 8);
+        let threads = comments.threads();
+        assert_eq!(threads.len(), 3);
+        assert_eq!(threads[0].comment.text, "first");
+        assert_eq!(threads[0].replies.len(), 1);
+        assert_eq!(threads[0].replies[0].comment.id, 5);
+        assert_eq!(threads[0].replies[0].replies[0].comment.id, 6);
+        assert!(threads[1].replies.is_empty());
+        assert_eq!(threads[2].comment.text, "orphan");
+
+        comments.delete_comment(5);
+        comments.set_resolved(1, true);
+        let threads = comments.threads();
+        assert!(threads[0].comment.resolved);
+        assert!(threads[0].replies[0].comment.deleted);
+        assert!(threads[0].replies[0].comment.text.is_empty());
+        assert_eq!(threads[0].replies[0].replies.len(), 1);
+    }
 }
@@ -0,0 +1,158 @@
+use crate::journal::Journal;
+use crate::node_id::NodeId;
+use crate::node_store::{NodeStore, MAX_ID_GAP};
+use crate::operation::Operation;
+use crate::sources::Source;
+
+/// Create the shortest journal that rebuilds `nodes` from an empty document.
+///
+/// Node ids and name indices are kept, so anything referring to them stays valid. The given
+/// snapshots are added at the end, as their original positions no longer exist.
+pub(crate) fn compact<'a, I: IntoIterator<Item = &'a Operation>>(
+    nodes: &NodeStore,
+    snapshots: I,
+) -> Journal {
+    let mut journal = Journal::new();
+
+    // A node can only be added a limited distance above the ids in use, and the depth first
+    // order below does not follow the ids. Grow the store to its full size with nodes that are
+    // removed again, so every id is in reach.
+    let mut size = NodeStore::new().len();
+    while size.saturating_add(MAX_ID_GAP) < nodes.len() {
+        let id = NodeId::new(size + MAX_ID_GAP - 1);
+        journal.add_operation(Operation::AddNode {
+            id,
+            parent: NodeId::ROOT_NODE,
+            index_in_parent: 0,
+        });
+        journal.add_operation(Operation::RemoveNode { id });
+        size += MAX_ID_GAP;
+    }
+
+    // Before the nodes, as a reservation cannot cover ids that are in use
+    for reservation in &nodes.id_reservations {
+        journal.add_operation(Operation::ReserveIds {
+            client: reservation.client,
+            start: reservation.start,
+            count: reservation.count,
+        });
+    }
+    for (id, name) in nodes.type_names.iter() {
+        journal.add_operation(Operation::DefineTypeName {
+            id,
+            name: name.to_string(),
+        });
+    }
+    for (id, name) in nodes.attribute_names.iter() {
+        journal.add_operation(Operation::DefineAttributeName {
+            id,
+            name: name.to_string(),
+        });
+    }
+    for (id, name) in nodes.tag_names.iter() {
+        journal.add_operation(Operation::DefineTagName {
+            id,
+            name: name.to_string(),
+        });
+    }
+
+    // Sources that are not attached to any node
+    for (id, source) in nodes.sources.iter() {
+        let attached = nodes.nodes().iter().any(|n| n.sources.contains(&id));
+        if !attached {
+            journal.add_operation(source_operation(id, NodeId::NO_NODE, source));
+        }
+    }
+
+    // Depth first, so every parent exists and children are added in order
+    let mut stack: Vec<NodeId> = nodes.find_roots().iter().rev().cloned().collect();
+    while let Some(id) = stack.pop() {
+        let node = nodes.get(id).expect("Node should exist");
+        let parent = nodes.get(node.parent).expect("Parent should exist");
+        let index_in_parent = parent.get_child_index(id).expect("Node should be a child");
+
+        journal.add_operation(Operation::AddNode {
+            id,
+            parent: node.parent,
+            index_in_parent,
+        });
+        if let Some(type_id) = node.type_id {
+            journal.add_operation(Operation::SetType { node: id, type_id });
+        }
+        if let Some(name) = &node.name {
+            journal.add_operation(Operation::SetName {
+                node: id,
+                name: name.clone(),
+            });
+        }
+        for entry in node.attributes.iter() {
+            journal.add_operation(Operation::SetAttribute {
+                node: id,
+                attribute: entry.key,
+                value: entry.value.clone(),
+            });
+        }
+        for tag in &node.tags {
+            journal.add_operation(Operation::SetTag {
+                node: id,
+                tag: *tag,
+            });
+        }
+        for source in &node.sources {
+            if let Some(s) = nodes.sources.get(*source) {
+                journal.add_operation(source_operation(*source, id, s));
+            }
+        }
+        for comment in &node.comments.comments {
+            journal.add_operation(Operation::AddComment {
+                node: id,
+                id: Some(comment.id),
+                comment: comment.text.clone(),
+                author: comment.author.clone(),
+                response_to: comment.response_to.unwrap_or(0),
+                time: comment.time,
+            });
+            if let Some(time) = comment.edited {
+                journal.add_operation(Operation::EditComment {
+                    node: id,
+                    id: comment.id,
+                    comment: comment.text.clone(),
+                    time,
+                });
+            }
+            if comment.deleted {
+                journal.add_operation(Operation::DeleteComment {
+                    node: id,
+                    id: comment.id,
+                });
+            }
+            if comment.resolved {
+                journal.add_operation(Operation::ResolveComment {
+                    node: id,
+                    id: comment.id,
+                    resolved: true,
+                });
+            }
+        }
+
+        stack.extend(node.children.iter().rev());
+    }
+
+    for snapshot in snapshots {
+        if let Operation::Snapshot { .. } = snapshot {
+            journal.add_operation(snapshot.clone());
+        }
+    }
+
+    journal
+}
+
+fn source_operation(id: usize, node: NodeId, source: &Source) -> Operation {
+    Operation::AddSource {
+        id,
+        node,
+        uri: source.uri.clone(),
+        hash: source.hash.clone(),
+        time: source.time,
+    }
+}
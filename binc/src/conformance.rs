@@ -0,0 +1,67 @@
+use crate::attributes::AttributeValue;
+use crate::branch;
+use crate::journal::Journal;
+use crate::operation::Operation;
+
+/// Result of converting a journal to binc version 1
+pub struct V1Conversion {
+    /// The journal with only version 1 operations
+    pub journal: Journal,
+    /// Indices of operations that were replaced with a version 1 equivalent
+    pub downgraded: Vec<usize>,
+    /// Indices of operations without a version 1 equivalent, which were left out
+    pub removed: Vec<usize>,
+}
+
+/// All operations in the journal that are not part of binc version 1, with their indices
+pub(crate) fn non_v1_operations(journal: &Journal) -> Vec<(usize, &Operation)> {
+    journal
+        .operations
+        .iter()
+        .enumerate()
+        .filter(|(_, operation)| !operation.is_v1())
+        .collect()
+}
+
+/// Convert a journal to binc version 1. Attributes of other types become strings, and
+/// operations that have no version 1 equivalent are left out. Version 1 has no branches, so only
+/// the operations leading to the branch the journal ends on are kept.
+pub(crate) fn convert_to_v1(journal: &Journal) -> V1Conversion {
+    let mut conversion = V1Conversion {
+        journal: Journal::new(),
+        downgraded: vec![],
+        removed: vec![],
+    };
+
+    let (_, lineage) = branch::lineage(&journal.operations, journal.operations.len(), |_| false);
+    let mut lineage = lineage.into_iter().peekable();
+    for (index, operation) in journal.operations.iter().enumerate() {
+        if lineage.next_if_eq(&index).is_none() {
+            conversion.removed.push(index);
+        } else if operation.is_v1() {
+            conversion.journal.add_operation(operation.clone());
+        } else if let Some(downgraded) = downgrade(operation) {
+            conversion.journal.add_operation(downgraded);
+            conversion.downgraded.push(index);
+        } else {
+            conversion.removed.push(index);
+        }
+    }
+
+    conversion
+}
+
+fn downgrade(operation: &Operation) -> Option<Operation> {
+    match operation {
+        Operation::SetAttribute {
+            node,
+            attribute,
+            value,
+        } => Some(Operation::SetAttribute {
+            node: *node,
+            attribute: *attribute,
+            value: AttributeValue::String(value.to_string()),
+        }),
+        _ => None,
+    }
+}
@@ -0,0 +1,537 @@
+use crate::attributes::AttributeValue;
+use crate::changes::Changes;
+use crate::error::NameKind;
+use crate::merge::index_after;
+use crate::node_id::NodeId;
+use crate::node_store::{Node, NodeStore};
+use crate::operation::Operation;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+
+/// A difference between two states of a document. Types, attributes and tags are identified by
+/// name, so states with different name ids can be compared.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Difference {
+    /// The node was added at `index` among the children of `parent`
+    NodeAdded {
+        node: NodeId,
+        parent: NodeId,
+        index: usize,
+    },
+    /// The node was removed with its descendants, except those that were moved elsewhere
+    NodeRemoved {
+        node: NodeId,
+    },
+    /// The node got another parent, or another order among the siblings it kept
+    NodeMoved {
+        node: NodeId,
+        old_parent: NodeId,
+        old_index: usize,
+        new_parent: NodeId,
+        new_index: usize,
+    },
+    NameChanged {
+        node: NodeId,
+        old: Option<String>,
+        new: Option<String>,
+    },
+    TypeChanged {
+        node: NodeId,
+        old: Option<String>,
+        new: Option<String>,
+    },
+    AttributeChanged {
+        node: NodeId,
+        attribute: String,
+        old: Option<AttributeValue>,
+        new: Option<AttributeValue>,
+    },
+    TagAdded {
+        node: NodeId,
+        tag: String,
+    },
+    TagRemoved {
+        node: NodeId,
+        tag: String,
+    },
+}
+
+/// Differences that turn `a` into `b`. Added and moved nodes come in tree order, so parents come
+/// before their children, followed by the changes to each node. Removed nodes come last, and only
+/// the top of each removed subtree is listed.
+pub fn diff(a: &NodeStore, b: &NodeStore) -> Vec<Difference> {
+    let moved = moved_nodes(a, b);
+    let mut differences = vec![];
+
+    for id in tree_order(b) {
+        let node = b.get(id).expect("Node should exist");
+        let index = child_index(b, node);
+        let old = a.get(id).filter(|_| a.exists(id));
+        match old {
+            None => differences.push(Difference::NodeAdded {
+                node: id,
+                parent: node.parent,
+                index,
+            }),
+            Some(old) if moved.contains(&id) => differences.push(Difference::NodeMoved {
+                node: id,
+                old_parent: old.parent,
+                old_index: child_index(a, old),
+                new_parent: node.parent,
+                new_index: index,
+            }),
+            Some(_) => {}
+        }
+        node_differences(a, old, b, node, &mut differences);
+    }
+
+    for id in tree_order(a) {
+        let parent = a.get(id).expect("Node should exist").parent;
+        if !b.exists(id) && b.exists(parent) {
+            differences.push(Difference::NodeRemoved { node: id });
+        }
+    }
+    differences
+}
+
+/// Changes that turn `a` into the state the differences were computed against, and the
+/// differences they leave out. Those are names, types and attributes that were cleared, as no
+/// operation clears them, and differences that do not fit `a`.
+pub fn to_changes(a: &NodeStore, differences: &[Difference]) -> (Changes, Vec<Difference>) {
+    let mut placed = HashMap::new();
+    let mut removed = HashSet::new();
+    for difference in differences {
+        match difference {
+            Difference::NodeAdded {
+                node,
+                parent,
+                index,
+            } => {
+                placed.insert(*node, (*parent, *index, difference));
+            }
+            Difference::NodeMoved {
+                node,
+                new_parent,
+                new_index,
+                ..
+            } => {
+                placed.insert(*node, (*new_parent, *new_index, difference));
+            }
+            Difference::NodeRemoved { node } => {
+                removed.insert(*node);
+            }
+            _ => {}
+        }
+    }
+
+    let mut incoming: HashMap<NodeId, Vec<(usize, NodeId)>> = HashMap::new();
+    for (node, (parent, index, _)) in &placed {
+        incoming.entry(*parent).or_default().push((*index, *node));
+    }
+    for children in incoming.values_mut() {
+        children.sort_by_key(|(index, _)| *index);
+    }
+
+    let mut builder = ChangeBuilder {
+        nodes: a.clone(),
+        changes: Changes::new(),
+        left_out: vec![],
+    };
+
+    // Place each node right after its new left sibling, parents first. Siblings that stay keep
+    // their order, so the children end up in the new order once the removed nodes are gone.
+    let mut stack = vec![NodeId::ROOT_NODE];
+    while let Some(parent) = stack.pop() {
+        let kept: Vec<NodeId> = match a.get(parent).filter(|_| a.exists(parent)) {
+            Some(node) => node
+                .children
+                .iter()
+                .filter(|c| !placed.contains_key(c) && !removed.contains(c))
+                .copied()
+                .collect(),
+            None => vec![],
+        };
+        let children = interleave(kept, incoming.get(&parent).map_or(&[][..], |v| v));
+
+        for (i, child) in children.iter().enumerate() {
+            let Some((_, _, difference)) = placed.get(child) else {
+                continue;
+            };
+            let left = i.checked_sub(1).map(|left| children[left]);
+            let index = index_after(&builder.nodes, *child, parent, left, 0);
+            let operation = if let Difference::NodeAdded { .. } = difference {
+                Operation::AddNode {
+                    id: *child,
+                    parent,
+                    index_in_parent: index,
+                }
+            } else {
+                Operation::MoveNode {
+                    id: *child,
+                    new_parent: parent,
+                    index_in_new_parent: index,
+                }
+            };
+            builder.push(difference, operation);
+        }
+        stack.extend(children.iter().rev());
+    }
+
+    for difference in differences {
+        match difference {
+            Difference::NodeRemoved { node } => {
+                builder.push(difference, Operation::RemoveNode { id: *node });
+            }
+            Difference::NameChanged {
+                node,
+                new: Some(name),
+                ..
+            } => builder.push(
+                difference,
+                Operation::SetName {
+                    node: *node,
+                    name: name.clone(),
+                },
+            ),
+            Difference::TypeChanged {
+                node,
+                new: Some(name),
+                ..
+            } => {
+                let type_id = builder.name_id(NameKind::Type, name);
+                builder.push(
+                    difference,
+                    Operation::SetType {
+                        node: *node,
+                        type_id,
+                    },
+                );
+            }
+            Difference::AttributeChanged {
+                node,
+                attribute,
+                new: Some(value),
+                ..
+            } => {
+                let attribute = builder.name_id(NameKind::Attribute, attribute);
+                builder.push(
+                    difference,
+                    Operation::SetAttribute {
+                        node: *node,
+                        attribute,
+                        value: value.clone(),
+                    },
+                );
+            }
+            Difference::TagAdded { node, tag } => {
+                let tag = builder.name_id(NameKind::Tag, tag);
+                builder.push(difference, Operation::SetTag { node: *node, tag });
+            }
+            Difference::TagRemoved { node, tag } => {
+                // A tag without a name cannot be on the node in the first place
+                if let Some(tag) = builder.nodes.tag_names.get_index(tag) {
+                    builder.push(difference, Operation::RemoveTag { node: *node, tag });
+                }
+            }
+            Difference::NameChanged { new: None, .. }
+            | Difference::TypeChanged { new: None, .. }
+            | Difference::AttributeChanged { new: None, .. } => {
+                builder.left_out.push(difference.clone());
+            }
+            Difference::NodeAdded { .. } | Difference::NodeMoved { .. } => {}
+        }
+    }
+    (builder.changes, builder.left_out)
+}
+
+/// Changes, the state they lead to so far, and the differences that could not be made
+struct ChangeBuilder {
+    nodes: NodeStore,
+    changes: Changes,
+    left_out: Vec<Difference>,
+}
+
+impl ChangeBuilder {
+    /// Add the operation that makes the difference, or leave the difference out if it fails
+    fn push(&mut self, difference: &Difference, operation: Operation) {
+        if !self.apply(operation) {
+            self.left_out.push(difference.clone());
+        }
+    }
+
+    fn apply(&mut self, operation: Operation) -> bool {
+        let applied = operation.apply(&mut self.nodes).is_ok();
+        if applied {
+            self.changes.operations.push(operation);
+        }
+        applied
+    }
+
+    /// Id of a type, attribute or tag name, defined if it is new
+    fn name_id(&mut self, kind: NameKind, name: &str) -> usize {
+        let names = match kind {
+            NameKind::Type => &self.nodes.type_names,
+            NameKind::Attribute => &self.nodes.attribute_names,
+            NameKind::Tag => &self.nodes.tag_names,
+        };
+        if let Some(id) = names.get_index(name) {
+            return id;
+        }
+        let id = names.len();
+        let name = name.to_string();
+        self.apply(match kind {
+            NameKind::Type => Operation::DefineTypeName { id, name },
+            NameKind::Attribute => Operation::DefineAttributeName { id, name },
+            NameKind::Tag => Operation::DefineTagName { id, name },
+        });
+        id
+    }
+}
+
+/// The kept children in order, with the incoming ones inserted at their indices
+fn interleave(kept: Vec<NodeId>, incoming: &[(usize, NodeId)]) -> Vec<NodeId> {
+    let mut children = Vec::with_capacity(kept.len() + incoming.len());
+    let mut kept = kept.into_iter().peekable();
+    let mut incoming = incoming.iter().peekable();
+    while kept.peek().is_some() || incoming.peek().is_some() {
+        match incoming.peek() {
+            Some((index, node)) if *index <= children.len() || kept.peek().is_none() => {
+                children.push(*node);
+                incoming.next();
+            }
+            _ => children.extend(kept.next()),
+        }
+    }
+    children
+}
+
+/// Live nodes in depth first order, without the root
+fn tree_order(nodes: &NodeStore) -> Vec<NodeId> {
+    let mut order = vec![];
+    let mut stack: Vec<NodeId> = nodes.find_roots().iter().rev().copied().collect();
+    while let Some(id) = stack.pop() {
+        order.push(id);
+        if let Some(node) = nodes.get(id) {
+            stack.extend(node.children.iter().rev());
+        }
+    }
+    order
+}
+
+fn child_index(nodes: &NodeStore, node: &Node) -> usize {
+    nodes
+        .get(node.parent)
+        .and_then(|p| p.get_child_index(node.id))
+        .unwrap_or(0)
+}
+
+/// Nodes in both states that changed parent, or that are not part of the longest run of
+/// siblings that kept their order
+fn moved_nodes(a: &NodeStore, b: &NodeStore) -> HashSet<NodeId> {
+    let mut moved = HashSet::new();
+    for parent in b.nodes().iter().filter(|n| n.id.exists()) {
+        let mut kept = vec![];
+        let mut old_indices = vec![];
+        for child in &parent.children {
+            let Some(old) = a.get(*child).filter(|_| a.exists(*child)) else {
+                continue;
+            };
+            if old.parent == parent.id {
+                kept.push(*child);
+                old_indices.push(child_index(a, old));
+            } else {
+                moved.insert(*child);
+            }
+        }
+        let in_order: HashSet<usize> = longest_increasing(&old_indices).into_iter().collect();
+        for (i, child) in kept.iter().enumerate() {
+            if !in_order.contains(&i) {
+                moved.insert(*child);
+            }
+        }
+    }
+    moved
+}
+
+/// Positions of a longest increasing subsequence of `values`
+fn longest_increasing(values: &[usize]) -> Vec<usize> {
+    // tails[k] is the position of the smallest value that ends an increasing run of length k + 1
+    let mut tails: Vec<usize> = vec![];
+    let mut previous = vec![None; values.len()];
+    for (i, value) in values.iter().enumerate() {
+        let k = tails.partition_point(|t| values[*t] < *value);
+        if k > 0 {
+            previous[i] = Some(tails[k - 1]);
+        }
+        if k == tails.len() {
+            tails.push(i);
+        } else {
+            tails[k] = i;
+        }
+    }
+
+    let mut positions = vec![];
+    let mut current = tails.last().copied();
+    while let Some(i) = current {
+        positions.push(i);
+        current = previous[i];
+    }
+    positions
+}
+
+/// Name, type, attribute and tag differences of a node. `old` is None for an added node.
+fn node_differences(
+    a: &NodeStore,
+    old: Option<&Node>,
+    b: &NodeStore,
+    new: &Node,
+    differences: &mut Vec<Difference>,
+) {
+    let node = new.id;
+    let old_name = old.and_then(|n| n.name.clone());
+    if old_name != new.name {
+        differences.push(Difference::NameChanged {
+            node,
+            old: old_name,
+            new: new.name.clone(),
+        });
+    }
+
+    let old_type = old.and_then(|n| type_name(a, n));
+    let new_type = type_name(b, new);
+    if old_type != new_type {
+        differences.push(Difference::TypeChanged {
+            node,
+            old: old_type,
+            new: new_type,
+        });
+    }
+
+    let old_attributes = old.map(|n| attributes(a, n)).unwrap_or_default();
+    let new_attributes = attributes(b, new);
+    for (attribute, value) in &new_attributes {
+        let old_value = old_attributes.iter().find(|(name, _)| name == attribute);
+        if old_value.map(|(_, v)| v) != Some(value) {
+            differences.push(Difference::AttributeChanged {
+                node,
+                attribute: attribute.clone(),
+                old: old_value.map(|(_, v)| v.clone()),
+                new: Some(value.clone()),
+            });
+        }
+    }
+    for (attribute, value) in &old_attributes {
+        if !new_attributes.iter().any(|(name, _)| name == attribute) {
+            differences.push(Difference::AttributeChanged {
+                node,
+                attribute: attribute.clone(),
+                old: Some(value.clone()),
+                new: None,
+            });
+        }
+    }
+
+    let old_tags = old.map(|n| tags(a, n)).unwrap_or_default();
+    let new_tags = tags(b, new);
+    for tag in &new_tags {
+        if !old_tags.contains(tag) {
+            differences.push(Difference::TagAdded {
+                node,
+                tag: tag.clone(),
+            });
+        }
+    }
+    for tag in old_tags {
+        if !new_tags.contains(&tag) {
+            differences.push(Difference::TagRemoved { node, tag });
+        }
+    }
+}
+
+/// Name of a type, attribute or tag id, or the id itself if it has no name
+fn name_or_id(name: Option<&str>, id: usize) -> String {
+    name.map_or_else(|| id.to_string(), str::to_string)
+}
+
+fn type_name(nodes: &NodeStore, node: &Node) -> Option<String> {
+    node.type_id
+        .map(|id| name_or_id(nodes.type_names.get(id), id))
+}
+
+fn attributes(nodes: &NodeStore, node: &Node) -> Vec<(String, AttributeValue)> {
+    node.attributes
+        .iter()
+        .map(|a| {
+            (
+                name_or_id(nodes.attribute_names.get(a.key), a.key),
+                a.value.clone(),
+            )
+        })
+        .collect()
+}
+
+fn tags(nodes: &NodeStore, node: &Node) -> Vec<String> {
+    node.tags
+        .iter()
+        .map(|t| name_or_id(nodes.tag_names.get(*t), *t))
+        .collect()
+}
+
+fn optional<T: Display>(value: &Option<T>) -> String {
+    value
+        .as_ref()
+        .map_or_else(|| "none".to_string(), |v| v.to_string())
+}
+
+impl Display for Difference {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Difference::NodeAdded {
+                node,
+                parent,
+                index,
+            } => write!(f, "Added node {} to {} at {}", node, parent, index),
+            Difference::NodeRemoved { node } => write!(f, "Removed node {}", node),
+            Difference::NodeMoved {
+                node,
+                old_parent,
+                old_index,
+                new_parent,
+                new_index,
+            } => write!(
+                f,
+                "Moved node {} from {} at {} to {} at {}",
+                node, old_parent, old_index, new_parent, new_index
+            ),
+            Difference::NameChanged { node, old, new } => write!(
+                f,
+                "Node {} name: {} -> {}",
+                node,
+                optional(old),
+                optional(new)
+            ),
+            Difference::TypeChanged { node, old, new } => write!(
+                f,
+                "Node {} type: {} -> {}",
+                node,
+                optional(old),
+                optional(new)
+            ),
+            Difference::AttributeChanged {
+                node,
+                attribute,
+                old,
+                new,
+            } => write!(
+                f,
+                "Node {} {}: {} -> {}",
+                node,
+                attribute,
+                optional(old),
+                optional(new)
+            ),
+            Difference::TagAdded { node, tag } => write!(f, "Node {} tagged {}", node, tag),
+            Difference::TagRemoved { node, tag } => write!(f, "Node {} untagged {}", node, tag),
+        }
+    }
+}
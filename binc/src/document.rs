@@ -1,29 +1,113 @@
+use crate::attributes::AttributeValue;
+use crate::blame::{Blame, Origin, Origins};
+use crate::branch::{self, Branch, Branches};
 use crate::changes::Changes;
+use crate::checkpoint;
+use crate::compaction;
+use crate::diff::Difference;
+use crate::error::{ApplyError, BincError};
+use crate::inverse::Inverse;
 use crate::journal::Journal;
-use crate::node_id::{NodeId, NodeIdGenerator};
+use crate::node_id::{IdReservation, NodeId, NodeIdGenerator};
 use crate::node_store::NodeStore;
 use crate::operation::Operation;
+use crate::session::Session;
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeMap, BTreeSet};
 use std::io;
 use std::io::{Read, Write};
+use std::time::{Duration, Instant};
+use uuid::Uuid;
 
 pub struct Document {
     /// Journal containing all revisions
     pub journal: Journal,
     /// This is a cache of the current state of the document, as of the last revision and all pending operations
     pub nodes: NodeStore,
-    /// Revision that have been undone to
+    /// Earlier revision shown by `jump_to_revision`
     pub undo_revision: Option<usize>,
+    /// Ids for new nodes, from the block last reserved for `client`
     pub node_id_generator: NodeIdGenerator,
+    /// Client that reserves blocks of node ids in the journal. Set by `start_session`.
+    pub client: Uuid,
+    /// Number of operations that are already in the file the document was read from or saved to.
+    /// None if the file has to be written from scratch.
+    pub persisted_revision: Option<usize>,
+    /// Copies of the state at some revisions, so undo and redo don't replay from the start
+    checkpoints: BTreeMap<usize, NodeStore>,
+    /// Heads of the branches in the journal
+    branches: Branches,
+    /// Revisions inside a transaction, which undo and redo step over
+    grouped: BTreeSet<usize>,
+    /// Number of transactions that have been started and not ended
+    transaction_depth: usize,
+    /// Index of the first operation in the open transaction
+    transaction_start: Option<usize>,
+    /// Index of the last operation added by `edit`, and when, for combining quick edits
+    last_edit: Option<(usize, Instant)>,
+    /// How to undo the operations applied since the document was loaded, by index.
+    /// Undo uses them instead of replaying the journal.
+    inverses: BTreeMap<usize, Inverse>,
+    /// Ids reserved while an earlier revision is shown. The reservation is added to the journal
+    /// when the document continues from that revision, as asking for ids should not end the jump.
+    pending_reservation: Option<IdReservation>,
 }
 
-fn compute_nodes(journal: &Journal, end_revision: Option<usize>) -> NodeStore {
-    let mut nodes: NodeStore = NodeStore::new();
+/// Number of operations between the in-memory copies of the state
+const CHECKPOINT_INTERVAL: usize = 256;
 
-    let to = end_revision.unwrap_or(journal.operations.len());
-    for operation in &journal.operations.as_slice()[..to] {
-        operation.apply(&mut nodes);
+/// Largest number of in-memory copies of the state
+const MAX_CHECKPOINTS: usize = 32;
+
+/// Number of node ids reserved at a time
+const ID_BLOCK_SIZE: usize = 256;
+
+/// Edits of the same field within this time are combined into one operation
+const COALESCE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Keep a copy of the state at regular intervals
+fn keep_checkpoint(
+    checkpoints: &mut BTreeMap<usize, NodeStore>,
+    revision: usize,
+    nodes: &NodeStore,
+) {
+    if revision.is_multiple_of(CHECKPOINT_INTERVAL) && !checkpoints.contains_key(&revision) {
+        checkpoints.insert(revision, nodes.clone());
+        if checkpoints.len() > MAX_CHECKPOINTS {
+            thin_out_checkpoints(checkpoints);
+        }
+    }
+}
+
+/// Remove the copy closest to the one before it, the oldest of those if there are several. The
+/// copies then get further apart towards the start of the journal, where they are needed less.
+fn thin_out_checkpoints(checkpoints: &mut BTreeMap<usize, NodeStore>) {
+    let revisions: Vec<usize> = checkpoints.keys().copied().collect();
+    let closest = revisions
+        .windows(2)
+        .min_by_key(|pair| pair[1] - pair[0])
+        .map(|pair| pair[1]);
+    if let Some(revision) = closest {
+        checkpoints.remove(&revision);
+    }
+}
+
+/// Revisions inside the transactions recorded in a journal, and those after a reservation, which
+/// is undone with the operation that follows it
+fn recorded_groups(operations: &[Operation]) -> BTreeSet<usize> {
+    let mut grouped = BTreeSet::new();
+    for (index, operation) in operations.iter().enumerate() {
+        match operation {
+            Operation::Transaction { revision } if *revision < index => {
+                grouped.extend(revision + 1..=index);
+            }
+            Operation::ReserveIds { .. } => {
+                grouped.insert(index + 1);
+            }
+            _ => {}
+        }
     }
-    nodes
+    grouped
 }
 
 impl Default for Document {
@@ -32,33 +116,309 @@ impl Default for Document {
             journal: Journal::new(),
             nodes: NodeStore::new(),
             undo_revision: None,
-            node_id_generator: NodeIdGenerator::new(),
+            // Empty, so the first id reserves a block above the existing nodes
+            node_id_generator: NodeIdGenerator::for_range(1, 1),
+            client: Uuid::new_v4(),
+            persisted_revision: None,
+            checkpoints: BTreeMap::new(),
+            branches: Branches::default(),
+            grouped: BTreeSet::new(),
+            transaction_depth: 0,
+            transaction_start: None,
+            last_edit: None,
+            inverses: BTreeMap::new(),
+            pending_reservation: None,
         }
     }
 }
 
 impl Document {
+    /// Id for a new node. Ids come from a block reserved for `client` by an operation in the
+    /// journal, so they do not collide with loaded nodes or with ids used by writers whose
+    /// reservations are in the journal. A new block is reserved when the current one is used up
+    /// or its reservation was undone.
+    ///
+    /// Blocks start above the ids in use, so writers that work from the same state reserve the
+    /// same block. Their journals have to be combined with `merge`, which moves the ids of one
+    /// side. Appending one journal to the other fails at the second reservation instead.
     pub fn next_id(&mut self) -> NodeId {
-        self.node_id_generator.next_id()
+        loop {
+            match self.node_id_generator.peek() {
+                Some(id) if self.is_reserved(id) => {
+                    self.node_id_generator.next_id();
+                    if !self.nodes.exists(id) {
+                        return id;
+                    }
+                }
+                _ => self.reserve_ids(ID_BLOCK_SIZE),
+            }
+        }
+    }
+
+    /// The id is in a block reserved for `client`
+    fn is_reserved(&self, id: NodeId) -> bool {
+        self.nodes.reserved_by(id) == Some(self.client)
+            || self
+                .pending_reservation
+                .as_ref()
+                .is_some_and(|r| r.contains(id.index()))
+    }
+
+    fn reserve_ids(&mut self, count: usize) {
+        let reservation = IdReservation {
+            client: self.client,
+            start: self.nodes.next_free_id(),
+            count,
+        };
+        self.node_id_generator = NodeIdGenerator::for_range(reservation.start, reservation.end());
+        if self.undo_revision.is_some() {
+            self.pending_reservation = Some(reservation);
+        } else {
+            self.add_reservation(reservation);
+        }
+    }
+
+    /// Add a reservation to the journal. Outside a transaction it is undone together with the
+    /// operation after it, rather than as a step of its own.
+    fn add_reservation(&mut self, reservation: IdReservation) {
+        self.add_and_apply_always(Operation::ReserveIds {
+            client: reservation.client,
+            start: reservation.start,
+            count: reservation.count,
+        });
+        self.grouped.insert(self.num_operations());
     }
 
     pub fn new(journal: Journal) -> Document {
-        let nodes = compute_nodes(&journal, None);
-        Document {
+        let mut document = Document {
             journal,
-            nodes,
-            undo_revision: None,
-            node_id_generator: NodeIdGenerator::new(),
+            ..Default::default()
+        };
+        document.load();
+        document
+    }
+
+    /// Read a document, failing on the first operation that cannot be read or applied
+    pub fn read<T: Read>(file: &mut T) -> Result<Document, BincError> {
+        match Self::read_lenient(file) {
+            (_, Some(e)) => Err(e),
+            (document, None) => Ok(document),
+        }
+    }
+
+    /// Read as much of a document as possible. If reading stops early, the document up to the
+    /// failing operation is returned together with the error.
+    pub fn read_lenient<T: Read>(file: &mut T) -> (Document, Option<BincError>) {
+        let (journal, mut error) = Journal::read_lenient(file);
+        let mut document = Document {
+            journal,
+            ..Default::default()
+        };
+        if let Some(apply_error) = document.load() {
+            error = Some(apply_error);
+        }
+        if error.is_none() {
+            document.mark_persisted();
+        }
+        (document, error)
+    }
+
+    /// Compute the state from the latest valid checkpoint in the journal. The journal is cut
+    /// before the first operation that cannot be applied.
+    fn load(&mut self) -> Option<BincError> {
+        let (branches, invalid) = Branches::read(&self.journal.operations);
+        self.branches = branches;
+        if let Some((index, _)) = invalid {
+            self.journal.operations.truncate(index);
+        }
+
+        let checkpoint =
+            self.journal
+                .operations
+                .iter()
+                .enumerate()
+                .rev()
+                .find_map(|(index, operation)| match operation {
+                    Operation::Checkpoint { revision, data } if *revision == index => {
+                        checkpoint::restore(data).map(|nodes| (index, nodes))
+                    }
+                    _ => None,
+                });
+        if let Some((revision, nodes)) = checkpoint {
+            self.checkpoints.insert(revision, nodes);
+        }
+
+        let (nodes, failed) = self.compute_nodes(self.num_operations());
+        self.nodes = nodes;
+        let failed = failed.or(invalid);
+        if let Some((index, _)) = failed {
+            self.journal.operations.truncate(index);
+            self.branches = Branches::read(&self.journal.operations).0;
+        }
+        self.grouped = recorded_groups(&self.journal.operations);
+        let (index, error) = failed?;
+        Some(BincError::ApplyFailed { index, error })
+    }
+
+    /// State after the first `end` operations, replayed from the closest checkpoint. Replay stops
+    /// at an operation that cannot be applied, which is returned with the reason.
+    fn compute_nodes(&mut self, end: usize) -> (NodeStore, Option<(usize, ApplyError)>) {
+        let (mut nodes, indices) = self.replay_start(end);
+
+        for index in indices {
+            let operation = &self.journal.operations[index];
+            if let Err(error) = operation.apply(&mut nodes) {
+                return (nodes, Some((index, error)));
+            }
+            keep_checkpoint(&mut self.checkpoints, index + 1, &nodes);
         }
+        (nodes, None)
+    }
+
+    /// The closest in-memory checkpoint on the way to the state after the first `end`
+    /// operations, or the empty state, and the operations to replay on it
+    fn replay_start(&self, end: usize) -> (NodeStore, Vec<usize>) {
+        let (start, indices) = branch::lineage(&self.journal.operations, end, |revision| {
+            self.checkpoints.contains_key(&revision)
+        });
+        let nodes = match self.checkpoints.get(&start) {
+            Some(nodes) => nodes.clone(),
+            None => NodeStore::new(),
+        };
+        (nodes, indices)
+    }
+
+    /// Apply an operation that was not created locally, failing if it does not fit the document
+    fn apply_checked(&mut self, operation: Operation) -> Result<(), BincError> {
+        if branch::is_branch_operation(&operation) {
+            let index = self.num_operations();
+            return self
+                .add_branch_operation(operation)
+                .map_err(|error| BincError::ApplyFailed { index, error });
+        }
+        let inverse = match operation.apply_with_inverse(&mut self.nodes) {
+            Ok(inverse) => inverse,
+            Err(error) => {
+                return Err(BincError::ApplyFailed {
+                    index: self.num_operations(),
+                    error,
+                });
+            }
+        };
+        self.add_applied(operation, inverse);
+        Ok(())
     }
 
-    pub fn read<T: Read>(file: &mut T) -> io::Result<Document> {
-        let journal = Journal::read(file)?;
-        Ok(Self::new(journal))
+    /// Add an operation that has already been applied to the nodes
+    fn add_applied(&mut self, operation: Operation, inverse: Inverse) {
+        self.advance_branch();
+        self.inverses.insert(self.num_operations(), inverse);
+        self.push(operation);
+        let revision = self.num_operations();
+        keep_checkpoint(&mut self.checkpoints, revision, &self.nodes);
     }
 
     fn rebuild(&mut self, end_revision: Option<usize>) {
-        self.nodes = compute_nodes(&self.journal, end_revision);
+        let end = end_revision.unwrap_or(self.num_operations());
+        self.nodes = self.compute_nodes(end).0;
+    }
+
+    /// Show the document as it was after the first `revision` operations. Adding an operation
+    /// continues from there, and the later operations can be redone.
+    pub fn jump_to_revision(&mut self, revision: usize) {
+        self.pending_reservation = None;
+        self.undo_revision = if revision < self.num_operations() {
+            Some(revision)
+        } else {
+            None
+        };
+        self.rebuild(self.undo_revision);
+    }
+
+    /// State after the first `revision` operations. Unlike `jump_to_revision` this leaves the
+    /// document and its undo state as they are. Replay stops at an operation that cannot be applied.
+    pub fn state_at(&self, revision: usize) -> NodeStore {
+        let end = revision.min(self.num_operations());
+        let (mut nodes, indices) = self.replay_start(end);
+        for index in indices {
+            if self.journal.operations[index].apply(&mut nodes).is_err() {
+                break;
+            }
+        }
+        nodes
+    }
+
+    /// Differences between the states after the first `from` and the first `to` operations
+    pub fn diff(&self, from: usize, to: usize) -> Vec<Difference> {
+        self.state_at(from).diff(&self.state_at(to))
+    }
+
+    /// Where the fields of a node were last set, by the operations that lead to the revision
+    /// shown. None if the node does not exist, or was not added by an operation, like the root.
+    pub fn blame(&self, node: NodeId) -> Option<Blame> {
+        if !self.nodes.exists(node) {
+            return None;
+        }
+        let (_, lineage) =
+            branch::lineage(&self.journal.operations, self.current_revision(), |_| false);
+        Origins::new(&self.journal.operations, &lineage).blame(node)
+    }
+
+    /// Every value an attribute of a node has had up to the revision shown, oldest first
+    pub fn attribute_history(
+        &self,
+        node: NodeId,
+        attribute: usize,
+    ) -> Vec<(Origin, AttributeValue)> {
+        let (_, lineage) =
+            branch::lineage(&self.journal.operations, self.current_revision(), |_| false);
+        Origins::new(&self.journal.operations, &lineage).attribute_history(node, attribute)
+    }
+
+    /// Revision the document had at `time`, judging by the timestamps of snapshots and sessions.
+    /// None if nothing is known to have been written by then.
+    pub fn revision_at(&self, time: DateTime<Utc>) -> Option<usize> {
+        self.journal
+            .operations
+            .iter()
+            .rposition(|operation| match operation {
+                Operation::Snapshot { time: Some(t), .. } | Operation::Session { time: t, .. } => {
+                    *t <= time
+                }
+                _ => false,
+            })
+            .map(|index| index + 1)
+    }
+
+    /// Add an operation holding the current state, so loading the file does not have to replay
+    /// the operations before it
+    pub fn add_checkpoint(&mut self) -> io::Result<()> {
+        self.continue_from_shown_revision();
+        let checkpoint = checkpoint::checkpoint_operation(&self.nodes, self.num_operations())?;
+        self.add_and_apply_always(checkpoint);
+        Ok(())
+    }
+
+    /// Number of operations after the last checkpoint operation in the journal
+    pub fn operations_since_checkpoint(&self) -> usize {
+        let start = self
+            .journal
+            .operations
+            .iter()
+            .rposition(|o| matches!(o, Operation::Checkpoint { .. }))
+            .map_or(0, |i| i + 1);
+        self.num_operations() - start
+    }
+
+    /// Number of operations after the last checksum in the journal
+    pub fn operations_since_checksum(&self) -> usize {
+        let start = self
+            .journal
+            .operations
+            .iter()
+            .rposition(|o| matches!(o, Operation::Checksum { .. }))
+            .map_or(0, |i| i + 1);
+        self.num_operations() - start
     }
 
     pub fn write<T: Write>(&self, w: &mut T) -> io::Result<()> {
@@ -73,95 +433,483 @@ impl Document {
         self.nodes.find_roots()
     }
 
-    pub fn add_and_apply_changes(&mut self, changes: Changes) -> &mut Self {
-        for change in changes.operations {
-            self.add_and_apply(change);
+    /// Apply all operations and add them to the journal as one transaction. If one of them does
+    /// not fit the document, none of them are added.
+    pub fn add_and_apply_changes(&mut self, changes: Changes) -> Result<(), ApplyError> {
+        let mut nodes = self.nodes.clone();
+        // The ids may come from the reservation that is added when continuing
+        if let Some(reservation) = &self.pending_reservation {
+            nodes.reserve_ids(reservation.clone())?;
+        }
+        let mut inverses = vec![];
+        for operation in &changes.operations {
+            inverses.push(operation.apply_with_inverse(&mut nodes)?);
+        }
+
+        self.continue_from_shown_revision();
+        self.nodes = nodes;
+        self.begin_transaction();
+        for (operation, inverse) in changes.operations.into_iter().zip(inverses) {
+            self.advance_branch();
+            self.inverses.insert(self.num_operations(), inverse);
+            self.push(operation);
         }
-        self
+        self.end_transaction();
+        let revision = self.num_operations();
+        keep_checkpoint(&mut self.checkpoints, revision, &self.nodes);
+        Ok(())
+    }
+
+    /// Apply an operation and add it to the journal. An operation that does not fit the
+    /// document is rejected without changing anything.
+    pub fn add_and_apply(&mut self, operation: Operation) -> Result<(), ApplyError> {
+        self.add_operation(operation, false)
     }
 
-    pub fn add_and_apply(&mut self, operation: Operation) {
+    /// Apply an operation made by typing in a field, as `add_and_apply`. Edits of the same field
+    /// within a second of each other are combined into one operation, so they undo together.
+    pub fn edit(&mut self, operation: Operation) -> Result<(), ApplyError> {
+        self.add_operation(operation, true)
+    }
+
+    fn add_operation(&mut self, operation: Operation, combine: bool) -> Result<(), ApplyError> {
+        if branch::is_branch_operation(&operation) {
+            return self.add_branch_operation(operation);
+        }
+        // Continuing from a shown revision adds operations, so check that this one fits first
         if self.undo_revision.is_some() {
-            self.journal
-                .operations
-                .truncate(self.undo_revision.unwrap() as usize);
-            self.undo_revision = None;
+            let mut nodes = self.nodes.clone();
+            if let Some(reservation) = &self.pending_reservation {
+                nodes.reserve_ids(reservation.clone())?;
+            }
+            operation.apply(&mut nodes)?;
+            self.continue_from_shown_revision();
         }
-        operation.apply(&mut self.nodes);
-        self.journal.add_operation(operation);
+        let inverse = operation.apply_with_inverse(&mut self.nodes)?;
 
-        /* let last_change = self.pending_changes.changes.last();
-        let combined_change = if last_change.is_some() {
-            change.combine_change(last_change.unwrap())
+        // The combined operation is undone with the inverse of the last one
+        let combined = combine
+            .then(|| self.combine_with_last_edit(&operation))
+            .flatten();
+        let index = if let Some((index, combined)) = combined {
+            self.journal.operations[index] = combined;
+            for revision in index + 1..=self.num_operations() {
+                self.checkpoints.remove(&revision);
+                keep_checkpoint(&mut self.checkpoints, revision, &self.nodes);
+            }
+            if self.transaction_depth > 0 && self.transaction_start.is_none() {
+                self.transaction_start = Some(index);
+            }
+            index
         } else {
-            None
+            self.add_applied(operation, inverse);
+            self.num_operations() - 1
         };
+        self.last_edit = combine.then(|| (index, Instant::now()));
+        Ok(())
+    }
+
+    /// The last operation combined with `operation`, and its index, if it can take the place of
+    /// both. This is the case for quick edits of the same field, when the last edit is an undo
+    /// step of its own, apart from the session it started and the end of its transaction, and
+    /// has not been saved.
+    fn combine_with_last_edit(&self, operation: &Operation) -> Option<(usize, Operation)> {
+        let (index, time) = self.last_edit?;
+        let end = match self.journal.operations.last() {
+            Some(Operation::Transaction { .. }) => self.num_operations() - 1,
+            _ => self.num_operations(),
+        };
+        let mut start = index;
+        while self.grouped.contains(&start)
+            && matches!(
+                self.journal.operations[start - 1],
+                Operation::Session { .. }
+            )
+        {
+            start -= 1;
+        }
+        let undo_step = !self.grouped.contains(&start) && self.transaction_start.is_none();
+        if index + 1 != end
+            || !undo_step
+            || self.undo_revision.is_some()
+            || time.elapsed() > COALESCE_INTERVAL
+            || self
+                .persisted_revision
+                .is_some_and(|revision| revision > index)
+        {
+            return None;
+        }
+        operation
+            .combine_operations(&self.journal.operations[index])
+            .map(|combined| (index, combined))
+    }
+
+    /// Start a group of operations that undo and redo as one step. Transactions can be nested,
+    /// the group ends with the outermost one.
+    pub fn begin_transaction(&mut self) {
+        self.transaction_depth += 1;
+    }
+
+    /// End the transaction started by `begin_transaction`
+    pub fn end_transaction(&mut self) {
+        self.transaction_depth = self.transaction_depth.saturating_sub(1);
+        if self.transaction_depth > 0 {
+            return;
+        }
+        if let Some(start) = self.transaction_start.take() {
+            // Recorded in the journal, so the transaction is still one step after loading. An
+            // edit combined into the end of the last transaction is already inside it.
+            let recorded = matches!(
+                self.journal.operations.last(),
+                Some(Operation::Transaction { revision }) if *revision <= start
+            );
+            if start + 1 < self.num_operations() && !recorded {
+                self.add_applied(Operation::Transaction { revision: start }, Inverse::Nothing);
+            }
+            self.grouped.extend(start + 1..self.num_operations());
+        }
+    }
+
+    /// Run `f` in a transaction, so the operations it adds undo and redo as one step
+    pub fn transaction<R>(&mut self, f: impl FnOnce(&mut Document) -> R) -> R {
+        self.begin_transaction();
+        let result = f(self);
+        self.end_transaction();
+        result
+    }
+
+    /// Add an operation that fits any document, such as a checksum or a name definition
+    fn add_and_apply_always(&mut self, operation: Operation) {
+        self.add_and_apply(operation)
+            .expect("Operation should apply to any document");
+    }
 
-        if let Some(combined_change) = combined_change {
-            self.pending_changes.changes.pop();
-            self.pending_changes.changes.push(combined_change);
+    /// Add an operation that creates, switches or deletes a branch, or that undoes or redoes.
+    /// The document shows the head of the current branch afterwards.
+    fn add_branch_operation(&mut self, operation: Operation) -> Result<(), ApplyError> {
+        self.branches.apply(self.num_operations(), &operation)?;
+        self.push(operation);
+        self.undo_revision = None;
+        self.pending_reservation = None;
+        self.rebuild(None);
+        Ok(())
+    }
+
+    /// Add an operation to the journal, as part of the open transaction if there is one
+    fn push(&mut self, operation: Operation) {
+        if self.transaction_depth > 0 && self.transaction_start.is_none() {
+            self.transaction_start = Some(self.num_operations());
+        }
+        self.journal.add_operation(operation);
+    }
+
+    /// The operation about to be added belongs to the current branch
+    fn advance_branch(&mut self) {
+        self.branches.advance(self.num_operations());
+    }
+
+    /// Continue from the revision shown by `jump_to_revision`, as a new operation is about to be
+    /// added. The operations after it are undone by an undo operation, so they can be redone.
+    fn continue_from_shown_revision(&mut self) {
+        let Some(revision) = self.undo_revision.take() else {
+            return;
+        };
+        // The nodes already show the revision
+        self.advance_branch();
+        self.push(Operation::Undo { revision });
+        if let Some(reservation) = self.pending_reservation.take() {
+            self.add_reservation(reservation);
+        }
+    }
+
+    /// Journal with the minimal operations that rebuild the current state. Other branches are
+    /// left out. Snapshots up to the current revision are kept at the end if `keep_snapshots` is set.
+    pub fn compacted_journal(&self, keep_snapshots: bool) -> Journal {
+        let snapshots: Vec<Operation> = if keep_snapshots {
+            let (_, indices) =
+                branch::lineage(&self.journal.operations, self.current_revision(), |_| false);
+            indices
+                .into_iter()
+                .map(|index| self.journal.operations[index].clone())
+                .collect()
         } else {
-            self.pending_changes.changes.push(change);
-        }*/
+            vec![]
+        };
+        compaction::compact(&self.nodes, &snapshots)
+    }
+
+    /// Add a session operation, unless `session` is already in effect for the next operation
+    pub fn start_session(&mut self, session: &Session) {
+        let current = self.journal.operations[..self.current_revision()]
+            .iter()
+            .rev()
+            .find_map(Session::from_operation);
+        if current.as_ref() != Some(session) {
+            self.add_and_apply_always(session.to_operation());
+        }
+        self.client = session.client;
+    }
+
+    /// The session operation `index` was made in, if it is known
+    pub fn provenance(&self, index: usize) -> Option<Session> {
+        if index >= self.num_operations() {
+            return None;
+        }
+        self.journal.operations[..=index]
+            .iter()
+            .rev()
+            .find_map(Session::from_operation)
+    }
+
+    /// All sessions in the journal, with the index of the operation that started them
+    pub fn sessions(&self) -> Vec<(usize, Session)> {
+        self.journal
+            .operations
+            .iter()
+            .enumerate()
+            .filter_map(|(index, o)| Session::from_operation(o).map(|s| (index, s)))
+            .collect()
+    }
+
+    /// Record that all operations have been written to the document's file
+    pub fn mark_persisted(&mut self) {
+        self.persisted_revision = Some(self.num_operations());
     }
 
-    pub fn append_and_apply<T: Read>(&mut self, r: &mut T) -> io::Result<()> {
-        let from = self.num_operations();
-        self.journal.append(r)?;
-        let to = self.num_operations();
+    /// Operations that have not been written to the document's file, or None if the whole
+    /// journal has to be written
+    pub fn unsaved_operations(&self) -> Option<&[Operation]> {
+        self.persisted_revision
+            .map(|revision| &self.journal.operations[revision..])
+    }
+
+    /// Read operations sent over the network and apply them. Operations before one that cannot
+    /// be applied are kept.
+    pub fn append_and_apply<T: Read>(&mut self, r: &mut T) -> Result<(), BincError> {
+        let mut received = Journal::new();
+        received.append(r)?;
 
-        for i in from..to {
-            let change = &self.journal.operations[i as usize];
-            change.apply(&mut self.nodes);
+        for operation in received.operations {
+            self.apply_checked(operation)?;
         }
 
         Ok(())
     }
 
+    /// Add a checksum covering all operations since the previous checksum
+    pub fn add_checksum(&mut self) -> io::Result<()> {
+        self.continue_from_shown_revision();
+        let checksum = self.journal.checksum_operation()?;
+        self.add_and_apply_always(checksum);
+        Ok(())
+    }
+
     pub fn num_operations(&self) -> usize {
         self.journal.operations.len()
     }
 
     pub fn can_undo(&self) -> bool {
-        self.num_operations() > 0
+        branch::lineage_back(&self.journal.operations, self.current_revision())
+            .next()
+            .is_some()
     }
 
     pub fn can_redo(&self) -> bool {
-        self.undo_revision.is_some()
+        self.undo_revision.is_some() || self.last_undo().is_some()
     }
 
+    /// Undo the last transaction or operation on the current branch by adding an undo operation.
+    /// If an earlier revision is shown, undo continues from it.
     pub fn undo(&mut self) {
-        self.undo_revision = match self.undo_revision {
-            Some(rev) => {
-                if rev == 0 {
-                    return;
-                }
-                Some(rev - 1)
-            }
-            None => {
-                if self.num_operations() == 0 {
-                    return;
+        self.continue_from_shown_revision();
+        let mut lineage =
+            branch::lineage_back(&self.journal.operations, self.num_operations()).peekable();
+        let Some(last) = lineage.next() else {
+            return;
+        };
+        // Checksums and checkpoints only describe the journal, so they are undone together with
+        // the step before them
+        let describes_journal = |index: &usize| {
+            matches!(
+                self.journal.operations[*index],
+                Operation::Checksum { .. } | Operation::Checkpoint { .. }
+            )
+        };
+        // Operations of the step, newest first
+        let mut step = vec![last];
+        let start = loop {
+            match lineage.peek() {
+                Some(index)
+                    if self.grouped.contains(&(index + 1))
+                        || step.iter().all(describes_journal) =>
+                {
+                    step.push(*index)
                 }
-                Some(self.num_operations() - 1)
+                Some(index) => break index + 1,
+                None => break 0,
             }
+            lineage.next();
         };
+        drop(lineage);
 
-        self.rebuild(self.undo_revision);
+        self.advance_branch();
+        self.push(Operation::Undo { revision: start });
+        if !self.revert(&step) {
+            self.rebuild(None);
+        }
     }
 
+    /// Redo the last undo by adding a redo operation. If an earlier revision is shown, redo goes
+    /// back to the latest one instead.
     pub fn redo(&mut self) {
-        self.undo_revision = match self.undo_revision {
-            Some(rev) => {
-                if rev + 1 >= self.num_operations() {
-                    None
-                } else {
-                    Some(rev + 1)
+        if self.undo_revision.is_some() {
+            self.jump_to_revision(self.num_operations());
+            return;
+        }
+        let Some((undo, revision)) = self.last_undo() else {
+            return;
+        };
+        // Operations undone by it, oldest first
+        let mut undone = vec![];
+        let mut exact = revision == 0;
+        for index in branch::lineage_back(&self.journal.operations, undo) {
+            if index < revision {
+                exact = index + 1 == revision;
+                break;
+            }
+            undone.push(index);
+        }
+        undone.reverse();
+
+        self.advance_branch();
+        self.push(Operation::Redo { revision: undo });
+        if !(exact && self.reapply(&undone)) {
+            self.rebuild(None);
+        }
+    }
+
+    /// Revision with everything that can be redone: the end of the journal while an earlier
+    /// revision is shown, otherwise the revision before the undo operations that have not been
+    /// redone. None if there is nothing to redo.
+    pub fn redo_revision(&self) -> Option<usize> {
+        if self.undo_revision.is_some() {
+            return Some(self.num_operations());
+        }
+        let mut redone = 0;
+        let mut revision = None;
+        for (index, operation) in self.journal.operations.iter().enumerate().rev() {
+            match operation {
+                Operation::Redo { .. } => redone += 1,
+                Operation::Undo { .. } if redone > 0 => redone -= 1,
+                Operation::Undo { .. } => revision = Some(index),
+                Operation::Snapshot { .. }
+                | Operation::Session { .. }
+                | Operation::Checksum { .. }
+                | Operation::Checkpoint { .. } => {}
+                _ => break,
+            }
+        }
+        revision
+    }
+
+    /// Index of the last undo operation that has not been redone, and the revision it went back
+    /// to. Undo operations can be redone until an operation other than undo, redo, a snapshot,
+    /// session, checksum or checkpoint is added.
+    fn last_undo(&self) -> Option<(usize, usize)> {
+        let mut redone = 0;
+        for (index, operation) in self.journal.operations.iter().enumerate().rev() {
+            match operation {
+                Operation::Redo { .. } => redone += 1,
+                Operation::Undo { .. } if redone > 0 => redone -= 1,
+                Operation::Undo { revision } => return Some((index, *revision)),
+                Operation::Snapshot { .. }
+                | Operation::Session { .. }
+                | Operation::Checksum { .. }
+                | Operation::Checkpoint { .. } => {}
+                _ => return None,
+            }
+        }
+        None
+    }
+
+    /// Undo operations with their inverses, in the given order. Nothing is changed and false is
+    /// returned if the inverse of one of them is not known.
+    fn revert(&mut self, indices: &[usize]) -> bool {
+        if !indices
+            .iter()
+            .all(|index| self.inverses.contains_key(index))
+        {
+            return false;
+        }
+        for index in indices {
+            self.inverses[index].apply(&mut self.nodes);
+        }
+        true
+    }
+
+    /// Apply operations of the journal again, keeping their inverses. False if one of them
+    /// cannot be applied, which leaves the nodes partly changed.
+    fn reapply(&mut self, indices: &[usize]) -> bool {
+        for index in indices {
+            match self.journal.operations[*index].apply_with_inverse(&mut self.nodes) {
+                Ok(inverse) => {
+                    self.inverses.entry(*index).or_insert(inverse);
                 }
+                Err(_) => return false,
             }
-            None => None,
+        }
+        true
+    }
+
+    /// The revision shown, which is before the end of the journal after `jump_to_revision`
+    pub fn current_revision(&self) -> usize {
+        self.undo_revision.unwrap_or(self.num_operations())
+    }
+
+    /// Branches in the order they were created
+    pub fn branches(&self) -> &[Branch] {
+        self.branches.list()
+    }
+
+    /// The branch new operations are added to
+    pub fn current_branch(&self) -> &Branch {
+        self.branches.current()
+    }
+
+    /// Start branch `name` at the state after the first `revision` operations, such as the
+    /// revision of a snapshot or the one shown, and continue on it.
+    /// The branch that was current keeps its operations.
+    pub fn create_branch(&mut self, name: &str, revision: usize) -> Result<(), ApplyError> {
+        if self.branches.get(name).is_some() {
+            return Err(ApplyError::BranchExists {
+                name: name.to_string(),
+            });
+        }
+        self.add_branch_operation(Operation::Branch {
+            name: name.to_string(),
+            revision,
+        })
+    }
+
+    /// Continue on the head of an existing branch
+    pub fn switch_branch(&mut self, name: &str) -> Result<(), ApplyError> {
+        let Some(branch) = self.branches.get(name) else {
+            return Err(ApplyError::UnknownBranch {
+                name: name.to_string(),
+            });
         };
+        let revision = branch.head;
+        self.add_branch_operation(Operation::Branch {
+            name: name.to_string(),
+            revision,
+        })
+    }
 
-        self.rebuild(self.undo_revision);
+    /// Delete a branch other than the current one. Its operations stay in the journal.
+    pub fn delete_branch(&mut self, name: &str) -> Result<(), ApplyError> {
+        self.add_branch_operation(Operation::DeleteBranch {
+            name: name.to_string(),
+        })
     }
 
     pub fn get_or_define_attribute_id(&mut self, key: &str) -> usize {
@@ -169,7 +917,7 @@ impl Document {
             Some(index) => index,
             None => {
                 let next_id = self.nodes.attribute_names.len();
-                self.add_and_apply(Operation::DefineAttributeName {
+                self.add_and_apply_always(Operation::DefineAttributeName {
                     id: next_id,
                     name: key.to_string(),
                 });
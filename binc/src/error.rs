@@ -0,0 +1,288 @@
+use crate::node_id::NodeId;
+use std::fmt::{Display, Formatter};
+use std::io;
+use std::string::FromUtf8Error;
+
+/// Errors from reading a binc journal
+#[derive(Debug)]
+pub enum BincError {
+    /// An error from the underlying reader
+    Io(io::Error),
+    /// The file does not start with the binc container id
+    BadMagic { found: u32 },
+    /// The container version is not supported by this implementation
+    UnsupportedVersion { found: u32 },
+    /// The data ends in the middle of the operation starting at `offset`
+    TruncatedOperation { offset: u64 },
+    /// A length in the operation starting at `offset` does not fit the data
+    InvalidVarint { offset: u64 },
+    /// A string in the operation starting at `offset` is not valid UTF-8
+    InvalidUtf8 { offset: u64 },
+    /// The content of the operation starting at `offset` does not match its type
+    InvalidOperation { offset: u64, message: String },
+    /// Operation number `index` could not be applied to the document
+    ApplyFailed { index: usize, error: ApplyError },
+    /// Operation number `index` has a type that is not part of binc version 1
+    NotV1 { index: usize, operation: u64 },
+}
+
+/// Reasons an operation cannot be applied to the nodes of a document
+#[derive(Debug, Clone, PartialEq)]
+pub enum ApplyError {
+    /// The node does not exist
+    UnknownNode { node: NodeId },
+    /// A node with the id already exists, or the id cannot be used for a node
+    DuplicateId { node: NodeId },
+    /// The parent of a new or moved node does not exist
+    ParentMissing { parent: NodeId },
+    /// A node cannot be moved into itself or one of its descendants
+    MoveIntoOwnSubtree { node: NodeId, parent: NodeId },
+    /// The index is past the end of the children of the parent
+    IndexOutOfRange { index: usize, count: usize },
+    /// The root node cannot be removed or moved
+    RootNode,
+    /// A type, attribute or tag id that has no name defined
+    UndefinedName { kind: NameKind, id: usize },
+    /// The node has no comment with the id
+    UnknownComment { node: NodeId, id: usize },
+    /// The comment is deleted, so it cannot be changed
+    DeletedComment { node: NodeId, id: usize },
+    /// A new comment has an id that is already used, or 0
+    InvalidCommentId { node: NodeId, id: usize },
+    /// No source has the id
+    UnknownSource { id: usize },
+    /// Some of the ids are used by nodes or reserved by another client
+    IdsInUse { start: usize, count: usize },
+    /// The id is too far above the nodes and reservations in use, or is not a valid node id
+    IdTooLarge { id: usize, limit: usize },
+    /// A branch cannot start at a revision after its own operation
+    UnknownRevision { revision: usize },
+    /// No branch has the name
+    UnknownBranch { name: String },
+    /// A branch with the name already exists
+    BranchExists { name: String },
+    /// The branch operations are added to cannot be deleted
+    CurrentBranch { name: String },
+}
+
+/// The dictionaries of names in a document
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NameKind {
+    Type,
+    Attribute,
+    Tag,
+}
+
+impl Display for NameKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NameKind::Type => write!(f, "Type"),
+            NameKind::Attribute => write!(f, "Attribute"),
+            NameKind::Tag => write!(f, "Tag"),
+        }
+    }
+}
+
+impl Display for ApplyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ApplyError::UnknownNode { node } => write!(f, "Node {} does not exist", node),
+            ApplyError::DuplicateId { node } => write!(f, "Node {} cannot be added", node),
+            ApplyError::ParentMissing { parent } => {
+                write!(f, "Parent node {} does not exist", parent)
+            }
+            ApplyError::MoveIntoOwnSubtree { node, parent } => {
+                write!(
+                    f,
+                    "Node {} cannot be moved into its descendant {}",
+                    node, parent
+                )
+            }
+            ApplyError::IndexOutOfRange { index, count } => {
+                write!(f, "Index {} is out of range for {} children", index, count)
+            }
+            ApplyError::RootNode => write!(f, "The root node cannot be removed or moved"),
+            ApplyError::UndefinedName { kind, id } => {
+                write!(f, "{} name {} is not defined", kind, id)
+            }
+            ApplyError::UnknownComment { node, id } => {
+                write!(f, "Comment {} does not exist on node {}", id, node)
+            }
+            ApplyError::DeletedComment { node, id } => {
+                write!(f, "Comment {} on node {} is deleted", id, node)
+            }
+            ApplyError::InvalidCommentId { node, id } => {
+                write!(f, "Comment id {} cannot be used on node {}", id, node)
+            }
+            ApplyError::UnknownSource { id } => write!(f, "Source {} does not exist", id),
+            ApplyError::IdsInUse { start, count } => {
+                write!(
+                    f,
+                    "Ids {}..{} are already in use",
+                    start,
+                    start.saturating_add(*count)
+                )
+            }
+            ApplyError::IdTooLarge { id, limit } => {
+                write!(f, "Id {} is too large, ids must be below {}", id, limit)
+            }
+            ApplyError::UnknownRevision { revision } => {
+                write!(f, "Revision {} does not exist yet", revision)
+            }
+            ApplyError::UnknownBranch { name } => write!(f, "Branch {} does not exist", name),
+            ApplyError::BranchExists { name } => write!(f, "Branch {} already exists", name),
+            ApplyError::CurrentBranch { name } => {
+                write!(f, "Branch {} is the current branch", name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ApplyError {}
+
+/// Reasons a query cannot be parsed. Positions are byte offsets in the query.
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryError {
+    Unexpected {
+        position: usize,
+        expected: &'static str,
+    },
+    UnterminatedString {
+        position: usize,
+    },
+}
+
+impl Display for QueryError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QueryError::Unexpected { position, expected } => {
+                write!(f, "Expected {} at position {}", expected, position)
+            }
+            QueryError::UnterminatedString { position } => {
+                write!(f, "String at position {} is not closed", position)
+            }
+        }
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+/// Decoded length that is larger than the data it refers to
+#[derive(Debug)]
+pub(crate) struct InvalidLength {
+    pub length: usize,
+}
+
+impl Display for InvalidLength {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Length {} exceeds the available data", self.length)
+    }
+}
+
+impl std::error::Error for InvalidLength {}
+
+impl BincError {
+    /// Errors from `Operation::read` have offsets relative to the start of the operation.
+    /// This moves them to where the operation starts in the file.
+    pub(crate) fn offset_by(self, base: u64) -> BincError {
+        match self {
+            BincError::TruncatedOperation { offset } => BincError::TruncatedOperation {
+                offset: offset + base,
+            },
+            BincError::InvalidVarint { offset } => BincError::InvalidVarint {
+                offset: offset + base,
+            },
+            BincError::InvalidUtf8 { offset } => BincError::InvalidUtf8 {
+                offset: offset + base,
+            },
+            BincError::InvalidOperation { offset, message } => BincError::InvalidOperation {
+                offset: offset + base,
+                message,
+            },
+            e => e,
+        }
+    }
+
+    /// Classify an error from reading the content of an operation
+    pub(crate) fn from_content_error(e: io::Error) -> BincError {
+        if let Some(inner) = e.get_ref() {
+            if inner.is::<FromUtf8Error>() {
+                return BincError::InvalidUtf8 { offset: 0 };
+            }
+            if inner.is::<InvalidLength>() {
+                return BincError::InvalidVarint { offset: 0 };
+            }
+        }
+        match e.kind() {
+            io::ErrorKind::UnexpectedEof => BincError::InvalidOperation {
+                offset: 0,
+                message: "Fields extend past the end of the operation".to_string(),
+            },
+            io::ErrorKind::InvalidData => BincError::InvalidOperation {
+                offset: 0,
+                message: e.to_string(),
+            },
+            _ => BincError::Io(e),
+        }
+    }
+}
+
+impl Display for BincError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BincError::Io(e) => write!(f, "{}", e),
+            BincError::BadMagic { found } => {
+                write!(f, "Not a binc file (container id {:#010x})", found)
+            }
+            BincError::UnsupportedVersion { found } => {
+                write!(f, "Unsupported container version {}", found)
+            }
+            BincError::TruncatedOperation { offset } => {
+                write!(f, "Truncated operation at offset {}", offset)
+            }
+            BincError::InvalidVarint { offset } => {
+                write!(f, "Invalid length in operation at offset {}", offset)
+            }
+            BincError::InvalidUtf8 { offset } => {
+                write!(f, "Invalid UTF-8 in operation at offset {}", offset)
+            }
+            BincError::InvalidOperation { offset, message } => {
+                write!(f, "Invalid operation at offset {}: {}", offset, message)
+            }
+            BincError::ApplyFailed { index, error } => {
+                write!(f, "Operation {} could not be applied: {}", index, error)
+            }
+            BincError::NotV1 { index, operation } => write!(
+                f,
+                "Operation {} has type {:#04x}, which is not part of binc version 1",
+                index, operation
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BincError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BincError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for BincError {
+    fn from(e: io::Error) -> Self {
+        BincError::Io(e)
+    }
+}
+
+impl From<BincError> for io::Error {
+    fn from(e: BincError) -> Self {
+        match e {
+            BincError::Io(e) => e,
+            BincError::TruncatedOperation { .. } => io::Error::new(io::ErrorKind::UnexpectedEof, e),
+            BincError::NotV1 { .. } => io::Error::new(io::ErrorKind::InvalidInput, e),
+            e => io::Error::new(io::ErrorKind::InvalidData, e),
+        }
+    }
+}
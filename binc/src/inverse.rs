@@ -0,0 +1,251 @@
+use crate::attributes::AttributeValue;
+use crate::comments::Comments;
+use crate::error::NameKind;
+use crate::name_dictionary::NameDictionary;
+use crate::node_id::NodeId;
+use crate::node_store::{Node, NodeStore};
+use crate::operation::Operation;
+use crate::sources::Sources;
+
+/// What an operation changes, so it can be undone without replaying the journal
+pub(crate) enum Inverse {
+    /// The operation does not change the nodes
+    Nothing,
+    /// Remove an added node, and the slots that were added for it
+    RemoveNode {
+        id: NodeId,
+        len: usize,
+    },
+    /// Put back a removed subtree. The first node is the root of the subtree.
+    RestoreSubtree {
+        index: usize,
+        nodes: Vec<Node>,
+    },
+    /// Move a node back to where it was
+    Place {
+        id: NodeId,
+        parent: NodeId,
+        index: usize,
+    },
+    SetName {
+        node: NodeId,
+        name: Option<String>,
+    },
+    SetType {
+        node: NodeId,
+        type_id: Option<usize>,
+    },
+    SetAttribute {
+        node: NodeId,
+        attribute: usize,
+        value: Option<AttributeValue>,
+    },
+    SetTags {
+        node: NodeId,
+        tags: Vec<usize>,
+    },
+    SetComments {
+        node: NodeId,
+        comments: Comments,
+    },
+    /// Restore a name definition and the size of the dictionary
+    DefineName {
+        kind: NameKind,
+        id: usize,
+        name: Option<String>,
+        len: usize,
+    },
+    /// Restore the sources, and the source lists of the nodes that changed
+    SetSources {
+        sources: Sources,
+        nodes: Vec<(NodeId, Vec<usize>)>,
+    },
+    /// Drop the reservations after the first `count`
+    TruncateReservations {
+        count: usize,
+    },
+}
+
+impl Inverse {
+    /// The inverse of `operation`, recorded from the nodes before it is applied. Only valid if
+    /// the operation can be applied to them.
+    pub(crate) fn before(operation: &Operation, nodes: &NodeStore) -> Inverse {
+        let live = |id: &NodeId| nodes.get(*id).filter(|node| node.id == *id);
+        match operation {
+            Operation::AddNode { id, .. } => Inverse::RemoveNode {
+                id: *id,
+                len: nodes.len(),
+            },
+            Operation::RemoveNode { id } => match live(id) {
+                Some(node) => Inverse::RestoreSubtree {
+                    index: child_index(nodes, node),
+                    nodes: nodes.subtree(*id),
+                },
+                None => Inverse::Nothing,
+            },
+            Operation::MoveNode { id, .. } => match live(id) {
+                Some(node) => Inverse::Place {
+                    id: *id,
+                    parent: node.parent,
+                    index: child_index(nodes, node),
+                },
+                None => Inverse::Nothing,
+            },
+            Operation::ReserveIds { .. } => Inverse::TruncateReservations {
+                count: nodes.id_reservations.len(),
+            },
+            Operation::SetType { node, .. } => Inverse::SetType {
+                node: *node,
+                type_id: live(node).and_then(|n| n.type_id),
+            },
+            Operation::SetName { node, .. } => Inverse::SetName {
+                node: *node,
+                name: live(node).and_then(|n| n.name.clone()),
+            },
+            Operation::DefineTypeName { id, .. } => {
+                define_name(NameKind::Type, &nodes.type_names, *id)
+            }
+            Operation::DefineAttributeName { id, .. } => {
+                define_name(NameKind::Attribute, &nodes.attribute_names, *id)
+            }
+            Operation::DefineTagName { id, .. } => {
+                define_name(NameKind::Tag, &nodes.tag_names, *id)
+            }
+            Operation::SetAttribute {
+                node, attribute, ..
+            } => Inverse::SetAttribute {
+                node: *node,
+                attribute: *attribute,
+                value: live(node).and_then(|n| n.attributes.get(*attribute).cloned()),
+            },
+            Operation::SetTag { node, .. } | Operation::RemoveTag { node, .. } => {
+                Inverse::SetTags {
+                    node: *node,
+                    tags: live(node).map(|n| n.tags.clone()).unwrap_or_default(),
+                }
+            }
+            Operation::AddComment { node, .. }
+            | Operation::EditComment { node, .. }
+            | Operation::DeleteComment { node, .. }
+            | Operation::ResolveComment { node, .. } => Inverse::SetComments {
+                node: *node,
+                comments: live(node).map(|n| n.comments.clone()).unwrap_or_default(),
+            },
+            Operation::AddSource { node, .. } => Inverse::SetSources {
+                sources: nodes.sources.clone(),
+                nodes: live(node)
+                    .map(|n| vec![(n.id, n.sources.clone())])
+                    .unwrap_or_default(),
+            },
+            Operation::UpdateSource { .. } => Inverse::SetSources {
+                sources: nodes.sources.clone(),
+                nodes: vec![],
+            },
+            Operation::RemoveSource { id } => Inverse::SetSources {
+                sources: nodes.sources.clone(),
+                nodes: nodes
+                    .nodes()
+                    .iter()
+                    .filter(|n| n.id.exists() && n.sources.contains(id))
+                    .map(|n| (n.id, n.sources.clone()))
+                    .collect(),
+            },
+            Operation::Snapshot { .. }
+            | Operation::Checksum { .. }
+            | Operation::Checkpoint { .. }
+            | Operation::Session { .. }
+            | Operation::Branch { .. }
+            | Operation::DeleteBranch { .. }
+            | Operation::Undo { .. }
+            | Operation::Redo { .. }
+            | Operation::Transaction { .. }
+            | Operation::UnknownOperation { .. } => Inverse::Nothing,
+        }
+    }
+
+    /// Undo the operation on the nodes it was applied to
+    pub(crate) fn apply(&self, nodes: &mut NodeStore) {
+        match self {
+            Inverse::Nothing => {}
+            Inverse::RemoveNode { id, len } => nodes.remove_added(*id, *len),
+            Inverse::RestoreSubtree {
+                index,
+                nodes: subtree,
+            } => nodes.restore_subtree(*index, subtree),
+            Inverse::Place { id, parent, index } => nodes.place(*id, *parent, *index),
+            Inverse::SetName { node, name } => {
+                if let Some(node) = nodes.get_mut(*node) {
+                    node.name = name.clone();
+                }
+            }
+            Inverse::SetType { node, type_id } => {
+                if let Some(node) = nodes.get_mut(*node) {
+                    node.type_id = *type_id;
+                }
+            }
+            Inverse::SetAttribute {
+                node,
+                attribute,
+                value,
+            } => {
+                if let Some(node) = nodes.get_mut(*node) {
+                    match value {
+                        Some(value) => node.attributes.set(*attribute, value.clone()),
+                        None => node.attributes.remove(*attribute),
+                    }
+                }
+            }
+            Inverse::SetTags { node, tags } => {
+                if let Some(node) = nodes.get_mut(*node) {
+                    node.tags = tags.clone();
+                }
+            }
+            Inverse::SetComments { node, comments } => {
+                if let Some(node) = nodes.get_mut(*node) {
+                    node.comments = comments.clone();
+                }
+            }
+            Inverse::DefineName {
+                kind,
+                id,
+                name,
+                len,
+            } => {
+                let names = match kind {
+                    NameKind::Type => &mut nodes.type_names,
+                    NameKind::Attribute => &mut nodes.attribute_names,
+                    NameKind::Tag => &mut nodes.tag_names,
+                };
+                names.restore(*id, name.as_deref(), *len);
+            }
+            Inverse::SetSources {
+                sources,
+                nodes: lists,
+            } => {
+                nodes.sources = sources.clone();
+                for (id, list) in lists {
+                    if let Some(node) = nodes.get_mut(*id) {
+                        node.sources = list.clone();
+                    }
+                }
+            }
+            Inverse::TruncateReservations { count } => nodes.id_reservations.truncate(*count),
+        }
+    }
+}
+
+fn child_index(nodes: &NodeStore, node: &Node) -> usize {
+    nodes
+        .get(node.parent)
+        .and_then(|parent| parent.get_child_index(node.id))
+        .unwrap_or(0)
+}
+
+fn define_name(kind: NameKind, names: &NameDictionary, id: usize) -> Inverse {
+    Inverse::DefineName {
+        kind,
+        id,
+        name: names.get(id).map(str::to_string),
+        len: names.len(),
+    }
+}
@@ -1,14 +1,32 @@
+use crate::branch;
 use crate::changes::Changes;
+use crate::checksum;
+use crate::checksum::Verification;
+use crate::compaction;
+use crate::conformance;
+use crate::conformance::V1Conversion;
+use crate::error::BincError;
+use crate::node_store::NodeStore;
 use crate::operation::Operation;
-use crate::readwrite::{ReadExt, WriteExt};
+use crate::readwrite::{OffsetReader, ReadExt, WriteExt};
+use crate::recovery;
+use crate::recovery::Recovery;
 use io::Write;
+use std::fs::{File, OpenOptions};
 use std::io;
-use std::io::Read;
+use std::io::{BufReader, BufWriter, Read};
+use std::path::Path;
 
 pub struct Journal {
     pub operations: Vec<Operation>,
 }
 
+impl Default for Journal {
+    fn default() -> Self {
+        Journal::new()
+    }
+}
+
 impl From<Changes> for Journal {
     fn from(changes: Changes) -> Journal {
         let mut r = Self::new();
@@ -20,6 +38,8 @@ impl From<Changes> for Journal {
 impl Journal {
     pub const CONTAINER_ID: u32 = u32::from_be_bytes(*b"binc");
     pub const CONTAINER_VERSION: u32 = 1;
+    /// Size of the container id and version at the start of a file
+    pub const HEADER_SIZE: u64 = 8;
 
     pub fn new() -> Journal {
         Journal {
@@ -37,6 +57,23 @@ impl Journal {
         }
     }
 
+    /// Create a checksum operation covering all operations since the previous checksum
+    pub fn checksum_operation(&self) -> io::Result<Operation> {
+        let start = self
+            .operations
+            .iter()
+            .rposition(|o| matches!(o, Operation::Checksum { .. }))
+            .map_or(0, |i| i + 1);
+        checksum::checksum_operation(&self.operations[start..])
+    }
+
+    /// Append a checksum covering all operations since the previous checksum
+    pub fn add_checksum(&mut self) -> io::Result<()> {
+        let checksum = self.checksum_operation()?;
+        self.add_operation(checksum);
+        Ok(())
+    }
+
     pub fn write<T: Write>(&self, w: &mut T) -> io::Result<()> {
         w.write_u32(Journal::CONTAINER_ID)?;
         w.write_u32(Journal::CONTAINER_VERSION)?;
@@ -47,27 +84,284 @@ impl Journal {
         Ok(())
     }
 
-    pub fn read<T: Read>(r: &mut T) -> io::Result<Journal> {
-        let mut repo = Journal::new();
+    /// Read a journal, failing on the first operation that cannot be read
+    pub fn read<T: Read>(r: &mut T) -> Result<Journal, BincError> {
+        match Self::read_lenient(r) {
+            (_, Some(e)) => Err(e),
+            (journal, None) => Ok(journal),
+        }
+    }
+
+    /// Read as many operations as possible. If reading stops early, the operations before the
+    /// failure are returned together with the error.
+    pub fn read_lenient<T: Read>(r: &mut T) -> (Journal, Option<BincError>) {
+        let mut journal = Journal::new();
+        let reader = match JournalReader::new(r) {
+            Ok(reader) => reader,
+            Err(e) => return (journal, Some(e)),
+        };
+        for item in reader {
+            match item {
+                Ok((_, operation)) => journal.add_operation(operation),
+                Err(e) => return (journal, Some(e)),
+            }
+        }
+        (journal, None)
+    }
+
+    /// Rewrite the journal as the minimal operations that rebuild its final state, which is the
+    /// head of the branch the journal ends on. Snapshots on that branch are kept at the end if
+    /// `keep_snapshots` is set. Replay stops at the first operation that cannot be applied.
+    pub fn compact(&self, keep_snapshots: bool) -> Journal {
+        let (_, indices) = branch::lineage(&self.operations, self.operations.len(), |_| false);
+        let mut nodes = NodeStore::new();
+        for index in &indices {
+            if self.operations[*index].apply(&mut nodes).is_err() {
+                break;
+            }
+        }
+        let snapshots: Vec<Operation> = if keep_snapshots {
+            indices
+                .iter()
+                .map(|index| self.operations[*index].clone())
+                .collect()
+        } else {
+            vec![]
+        };
+        compaction::compact(&nodes, &snapshots)
+    }
+
+    /// All operations that are not part of binc version 1, with their indices
+    pub fn non_v1_operations(&self) -> Vec<(usize, &Operation)> {
+        conformance::non_v1_operations(self)
+    }
+
+    /// Convert to binc version 1, downgrading or leaving out the operations that are not part of it
+    pub fn convert_to_v1(&self) -> V1Conversion {
+        conformance::convert_to_v1(self)
+    }
+
+    /// Walk a binc file and check each checksum against the bytes written since the previous one
+    pub fn verify<T: Read>(r: &mut T) -> io::Result<Verification> {
+        Self::read_header(r)?;
+        checksum::verify(r, Self::HEADER_SIZE)
+    }
+
+    /// Read as much as possible from a damaged file, skipping ranges that cannot be parsed
+    pub fn recover(data: &[u8]) -> Recovery {
+        recovery::recover(data)
+    }
+
+    /// Read a damaged file, replacing damaged ranges with verified ranges from another copy of it
+    pub fn recover_with_copy(data: &[u8], copy: &[u8]) -> Recovery {
+        recovery::recover_with_copy(data, copy)
+    }
+
+    fn read_header<T: Read>(r: &mut T) -> Result<(), BincError> {
         let container_id = r.read_u32()?;
         let container_version = r.read_u32()?;
 
         if container_id != Journal::CONTAINER_ID {
-            return Err(io::Error::from(io::ErrorKind::InvalidData));
+            return Err(BincError::BadMagic {
+                found: container_id,
+            });
         } else if container_version != Journal::CONTAINER_VERSION {
-            return Err(io::Error::from(io::ErrorKind::InvalidData));
+            return Err(BincError::UnsupportedVersion {
+                found: container_version,
+            });
+        }
+        Ok(())
+    }
+
+    /// Append operations without a file header, as sent over the network. Nothing is added if any
+    /// of them cannot be read. Offsets in errors are relative to the start of `r`.
+    pub fn append<T: Read>(&mut self, r: &mut T) -> Result<(), BincError> {
+        let operations = JournalReader::without_header(r, 0)
+            .map(|item| item.map(|(_, operation)| operation))
+            .collect::<Result<Vec<_>, _>>()?;
+        self.operations.extend(operations);
+        Ok(())
+    }
+}
+
+/// Reads the operations of a binc file one at a time, so they can be processed without holding
+/// the whole journal in memory.
+///
+/// Each item is the offset of the operation in the file and the operation. Iteration ends after
+/// the first error.
+pub struct JournalReader<R: Read> {
+    reader: OffsetReader<R>,
+    failed: bool,
+}
+
+impl<R: Read> JournalReader<R> {
+    /// Check the container header and start reading the operations after it
+    pub fn new(mut r: R) -> Result<JournalReader<R>, BincError> {
+        Journal::read_header(&mut r)?;
+        Ok(Self::without_header(r, Journal::HEADER_SIZE))
+    }
+
+    /// Read operations that are not preceded by a header, such as those sent over the network.
+    /// `offset` is the position of the first byte of `r`.
+    pub fn without_header(r: R, offset: u64) -> JournalReader<R> {
+        JournalReader {
+            reader: OffsetReader::new(r, offset),
+            failed: false,
         }
+    }
+
+    fn read_next(&mut self) -> Result<Option<(u64, Operation)>, BincError> {
+        if self.reader.is_at_end()? {
+            return Ok(None);
+        }
+        let offset = self.reader.position();
+        let operation = Operation::read(&mut self.reader).map_err(|e| e.offset_by(offset))?;
+        Ok(Some((offset, operation)))
+    }
+}
+
+impl<R: Read> Iterator for JournalReader<R> {
+    type Item = Result<(u64, Operation), BincError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let item = self.read_next().transpose();
+        self.failed = matches!(item, Some(Err(_)));
+        item
+    }
+}
 
-        while let Ok(change) = Operation::read(r) {
-            repo.add_operation(change);
+/// Appends operations to a binc file without rewriting what is already there. Only the operations
+/// it is given are written, so operations keep the same index in the file as in their journal.
+pub struct JournalWriter {
+    file: BufWriter<File>,
+    /// Number of operations in the file, including those written by this writer
+    operation_count: usize,
+    /// Hash of the operations since the last checksum in the file
+    hasher: blake3::Hasher,
+    /// Number of operations after the last checksum in the file
+    unchecked_count: usize,
+    sync: bool,
+    v1_only: bool,
+}
+
+impl JournalWriter {
+    /// Create a new file containing only the container header, replacing any existing file
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<JournalWriter> {
+        let mut file = File::create(path)?;
+        file.write_u32(Journal::CONTAINER_ID)?;
+        file.write_u32(Journal::CONTAINER_VERSION)?;
+        Ok(Self::new(file, 0, blake3::Hasher::new()))
+    }
+
+    /// Open an existing file for appending. The header and every operation are checked, so
+    /// nothing is appended after a damaged or partially written tail. As this reads the whole
+    /// file, keep the writer for later appends rather than opening it again.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<JournalWriter, BincError> {
+        let mut file = OpenOptions::new().read(true).append(true).open(path)?;
+
+        let mut operation_count = 0;
+        let mut unchecked_count = 0;
+        let mut hasher = blake3::Hasher::new();
+        for item in JournalReader::new(BufReader::new(&mut file))? {
+            let (_, operation) = item?;
+            if let Operation::Checksum { .. } = operation {
+                hasher = blake3::Hasher::new();
+                unchecked_count = 0;
+            } else {
+                operation.write(&mut hasher)?;
+                unchecked_count += 1;
+            }
+            operation_count += 1;
         }
 
-        Ok(repo)
+        let mut writer = Self::new(file, operation_count, hasher);
+        writer.unchecked_count = unchecked_count;
+        Ok(writer)
+    }
+
+    fn new(file: File, operation_count: usize, hasher: blake3::Hasher) -> JournalWriter {
+        JournalWriter {
+            file: BufWriter::new(file),
+            operation_count,
+            hasher,
+            unchecked_count: 0,
+            sync: false,
+            v1_only: false,
+        }
+    }
+
+    /// Make `flush` wait until the data has reached the disk
+    pub fn with_sync(mut self, sync: bool) -> JournalWriter {
+        self.sync = sync;
+        self
+    }
+
+    /// Refuse to write operations that are not part of binc version 1, which includes checksums
+    pub fn with_v1_only(mut self, v1_only: bool) -> JournalWriter {
+        self.v1_only = v1_only;
+        self
+    }
+
+    /// Number of operations in the file
+    pub fn operation_count(&self) -> usize {
+        self.operation_count
+    }
+
+    /// Number of operations after the last checksum in the file
+    pub fn operations_since_checksum(&self) -> usize {
+        self.unchecked_count
+    }
+
+    pub fn write_operation(&mut self, operation: &Operation) -> io::Result<()> {
+        if self.v1_only && !operation.is_v1() {
+            return Err(BincError::NotV1 {
+                index: self.operation_count,
+                operation: operation.operation_id(),
+            }
+            .into());
+        }
+
+        let mut data = vec![];
+        operation.write(&mut data)?;
+        self.file.write_all(&data)?;
+        self.operation_count += 1;
+
+        if let Operation::Checksum { .. } = operation {
+            self.hasher = blake3::Hasher::new();
+            self.unchecked_count = 0;
+        } else {
+            self.hasher.update(&data);
+            self.unchecked_count += 1;
+        }
+        Ok(())
+    }
+
+    pub fn write_operations<'a, I: IntoIterator<Item = &'a Operation>>(
+        &mut self,
+        operations: I,
+    ) -> io::Result<()> {
+        for operation in operations {
+            self.write_operation(operation)?;
+        }
+        Ok(())
+    }
+
+    /// Append a checksum covering the operations since the previous checksum
+    pub fn write_checksum(&mut self) -> io::Result<()> {
+        let checksum = Operation::Checksum {
+            data: self.hasher.finalize().as_bytes().to_vec(),
+        };
+        self.write_operation(&checksum)
     }
 
-    pub fn append<T: Read>(&mut self, mut r: &mut T) -> io::Result<()> {
-        while let Ok(operation) = Operation::read(&mut r) {
-            self.add_operation(operation);
+    /// Write buffered operations to the file, and to the disk if sync is enabled
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.sync {
+            self.file.get_ref().sync_data()?;
         }
         Ok(())
     }
@@ -1,14 +1,28 @@
 pub mod attributes;
+pub mod blame;
+pub mod branch;
 pub mod builder;
 pub mod changes;
+pub mod checkpoint;
+pub mod checksum;
 pub mod client;
 pub mod comments;
+pub mod compaction;
+pub mod conformance;
+pub mod diff;
 pub mod document;
+pub mod error;
+pub mod inverse;
 pub mod journal;
+pub mod merge;
 pub mod name_dictionary;
 pub mod network_protocol;
 pub mod node_id;
 pub mod node_store;
 pub mod operation;
+pub mod query;
 pub mod readwrite;
+pub mod recovery;
+pub mod session;
+pub mod sources;
 pub mod util;
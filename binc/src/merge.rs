@@ -0,0 +1,498 @@
+use crate::attributes::AttributeValue;
+use crate::branch;
+use crate::error::{ApplyError, BincError, NameKind};
+use crate::node_id::{IdReservation, NodeId};
+use crate::node_store::NodeStore;
+use crate::operation::Operation;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+
+/// A part of a node that both sides of a merge can change
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Field {
+    Name,
+    Type,
+    /// The attribute with the id used after the merge
+    Attribute(usize),
+    /// Parent and place among the siblings
+    Position,
+}
+
+/// An operation of ours that clashes with their changes. `index` is its position in `ours`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Conflict {
+    /// Both sides changed the field of the node to different values. Our value is kept.
+    BothChanged {
+        index: usize,
+        node: NodeId,
+        field: Field,
+    },
+    /// The operation does not fit after their changes, for example because they removed the
+    /// node. It is left out.
+    Rejected { index: usize, error: ApplyError },
+    /// We undid the operation, which is part of the common start. Only operations after theirs
+    /// are added, so it is kept.
+    Undone { index: usize },
+}
+
+/// Our operations rebased onto theirs
+pub struct Merge {
+    /// Operations to append to theirs
+    pub operations: Vec<Operation>,
+    pub conflicts: Vec<Conflict>,
+    /// State after their operations and the rebased ones
+    pub nodes: NodeStore,
+}
+
+/// Rebase `ours` onto `theirs`, which both start with the same `base_len` operations.
+///
+/// Our operations after the common start are changed to follow theirs:
+/// - Nodes and id reservations get new ids if they clash with ids they used
+/// - Names use their id if they defined the same name, and a new id if the id is taken
+/// - Comments get new ids if they added comments with the same id to the node
+/// - Added and moved nodes are placed after the same sibling as before, so child indices
+///   account for nodes they added, removed or moved
+///
+/// Only the operations that lead to the head of each side are used, so operations that were
+/// undone or are on another branch are left out, as are the branch, undo and redo operations
+/// themselves. Our checkpoints and checksums are left out too, as they describe the journal
+/// before the merge.
+///
+/// Panics if `base_len` is larger than the length of either sequence.
+pub fn merge(
+    base_len: usize,
+    ours: &[Operation],
+    theirs: &[Operation],
+) -> Result<Merge, BincError> {
+    let (_, their_lineage) = branch::lineage(theirs, theirs.len(), |_| false);
+    let (_, our_lineage) = branch::lineage(ours, ours.len(), |_| false);
+    let their_start = their_lineage.partition_point(|index| *index < base_len);
+    let our_start = our_lineage.partition_point(|index| *index < base_len);
+
+    let mut changed = HashSet::new();
+    let mut added = HashSet::new();
+    for index in &their_lineage[their_start..] {
+        let operation = &theirs[*index];
+        if let Some(key) = changed_field(operation) {
+            changed.insert(key);
+        }
+        if let Operation::AddNode { id, .. } = operation {
+            added.insert(*id);
+        }
+    }
+
+    let mut rebase = Rebase {
+        ours: replay(ours, &our_lineage[..our_start])?,
+        merged: replay(theirs, &their_lineage)?,
+        changed,
+        added,
+        nodes: HashMap::new(),
+        blocks: vec![],
+        names: HashMap::new(),
+        comments: HashMap::new(),
+        operations: vec![],
+        conflicts: vec![],
+    };
+    // Undoing part of the common start cannot be expressed by appending to theirs
+    let (_, base_lineage) = branch::lineage(ours, base_len, |_| false);
+    for index in base_lineage {
+        if our_lineage[..our_start].binary_search(&index).is_err() {
+            rebase.conflicts.push(Conflict::Undone { index });
+        }
+    }
+    for index in &our_lineage[our_start..] {
+        rebase.add(*index, &ours[*index]);
+    }
+
+    Ok(Merge {
+        operations: rebase.operations,
+        conflicts: rebase.conflicts,
+        nodes: rebase.merged,
+    })
+}
+
+/// Apply the operations at `indices`
+fn replay(operations: &[Operation], indices: &[usize]) -> Result<NodeStore, BincError> {
+    let mut nodes = NodeStore::new();
+    for index in indices {
+        operations[*index]
+            .apply(&mut nodes)
+            .map_err(|error| BincError::ApplyFailed {
+                index: *index,
+                error,
+            })?;
+    }
+    Ok(nodes)
+}
+
+struct Rebase {
+    /// Our state before the operation being rebased, used to find where nodes were placed
+    ours: NodeStore,
+    merged: NodeStore,
+    /// Fields they changed
+    changed: HashSet<(NodeId, Field)>,
+    /// Nodes they added
+    added: HashSet<NodeId>,
+    nodes: HashMap<NodeId, NodeId>,
+    /// Our reservations that were moved, with their new start
+    blocks: Vec<(IdReservation, usize)>,
+    names: HashMap<(NameKind, usize), usize>,
+    /// New comment ids by node and our comment id
+    comments: HashMap<(NodeId, usize), usize>,
+    operations: Vec<Operation>,
+    conflicts: Vec<Conflict>,
+}
+
+impl Rebase {
+    fn add(&mut self, index: usize, operation: &Operation) {
+        let rebased = self.rebase(operation);
+        // Our own operations are valid where they were made
+        let _ = operation.apply(&mut self.ours);
+        let Some(rebased) = rebased else {
+            return;
+        };
+
+        let field = changed_field(&rebased);
+        let before = field.map(|(node, field)| field_value(&self.merged, node, field));
+        if let Err(error) = rebased.apply(&mut self.merged) {
+            self.conflicts.push(Conflict::Rejected { index, error });
+            return;
+        }
+        if let (Some((node, field)), Some(before)) = (field, before) {
+            // Reported once, later changes of ours to the field are not conflicts of their own
+            if field_value(&self.merged, node, field) != before
+                && self.changed.remove(&(node, field))
+            {
+                self.conflicts
+                    .push(Conflict::BothChanged { index, node, field });
+            }
+        }
+        self.operations.push(rebased);
+    }
+
+    fn node(&self, id: NodeId) -> NodeId {
+        if let Some(mapped) = self.nodes.get(&id) {
+            return *mapped;
+        }
+        for (reservation, start) in &self.blocks {
+            if reservation.contains(id.index()) {
+                return NodeId::new(id.index() - reservation.start + start);
+            }
+        }
+        id
+    }
+
+    fn name(&self, kind: NameKind, id: usize) -> usize {
+        *self.names.get(&(kind, id)).unwrap_or(&id)
+    }
+
+    fn comment(&self, node: NodeId, id: usize) -> usize {
+        *self.comments.get(&(node, id)).unwrap_or(&id)
+    }
+
+    /// Id to define `name` with, or None if it is already defined and the definition can be left out
+    fn define(&mut self, kind: NameKind, id: usize, name: &str) -> Option<usize> {
+        let names = match kind {
+            NameKind::Type => &self.merged.type_names,
+            NameKind::Attribute => &self.merged.attribute_names,
+            NameKind::Tag => &self.merged.tag_names,
+        };
+        if let Some(existing) = names.get_index(name) {
+            self.names.insert((kind, id), existing);
+            return None;
+        }
+        let new_id = if names.get(id).is_some() {
+            names.len()
+        } else {
+            id
+        };
+        self.names.insert((kind, id), new_id);
+        Some(new_id)
+    }
+
+    /// Index that places `node` after the sibling it followed in our state
+    fn place(&self, node: NodeId, parent: NodeId, index: usize) -> usize {
+        let left = left_sibling(&self.ours, node, parent, index);
+        index_after(
+            &self.merged,
+            self.node(node),
+            self.node(parent),
+            left.map(|left| self.node(left)),
+            index,
+        )
+    }
+
+    fn rebase(&mut self, operation: &Operation) -> Option<Operation> {
+        let rebased = match operation {
+            Operation::ReserveIds {
+                client,
+                start,
+                count,
+            } => {
+                if self.merged.ids_available(*start, *count) {
+                    operation.clone()
+                } else {
+                    let new_start = self.merged.next_free_id();
+                    let reservation = IdReservation {
+                        client: *client,
+                        start: *start,
+                        count: *count,
+                    };
+                    self.blocks.push((reservation, new_start));
+                    Operation::ReserveIds {
+                        client: *client,
+                        start: new_start,
+                        count: *count,
+                    }
+                }
+            }
+            Operation::AddNode {
+                id,
+                parent,
+                index_in_parent,
+            } => {
+                let mut new_id = self.node(*id);
+                if self.merged.exists(new_id) || self.added.contains(&new_id) {
+                    new_id = NodeId::new(self.merged.next_free_id());
+                    self.nodes.insert(*id, new_id);
+                }
+                Operation::AddNode {
+                    id: new_id,
+                    parent: self.node(*parent),
+                    index_in_parent: self.place(*id, *parent, *index_in_parent),
+                }
+            }
+            Operation::MoveNode {
+                id,
+                new_parent,
+                index_in_new_parent,
+            } => Operation::MoveNode {
+                id: self.node(*id),
+                new_parent: self.node(*new_parent),
+                index_in_new_parent: self.place(*id, *new_parent, *index_in_new_parent),
+            },
+            Operation::RemoveNode { id } => Operation::RemoveNode { id: self.node(*id) },
+            Operation::SetType { node, type_id } => Operation::SetType {
+                node: self.node(*node),
+                type_id: self.name(NameKind::Type, *type_id),
+            },
+            Operation::SetName { node, name } => Operation::SetName {
+                node: self.node(*node),
+                name: name.clone(),
+            },
+            Operation::DefineTypeName { id, name } => Operation::DefineTypeName {
+                id: self.define(NameKind::Type, *id, name)?,
+                name: name.clone(),
+            },
+            Operation::DefineAttributeName { id, name } => Operation::DefineAttributeName {
+                id: self.define(NameKind::Attribute, *id, name)?,
+                name: name.clone(),
+            },
+            Operation::DefineTagName { id, name } => Operation::DefineTagName {
+                id: self.define(NameKind::Tag, *id, name)?,
+                name: name.clone(),
+            },
+            Operation::SetAttribute {
+                node,
+                attribute,
+                value,
+            } => Operation::SetAttribute {
+                node: self.node(*node),
+                attribute: self.name(NameKind::Attribute, *attribute),
+                value: value.clone(),
+            },
+            Operation::SetTag { node, tag } => Operation::SetTag {
+                node: self.node(*node),
+                tag: self.name(NameKind::Tag, *tag),
+            },
+            Operation::RemoveTag { node, tag } => Operation::RemoveTag {
+                node: self.node(*node),
+                tag: self.name(NameKind::Tag, *tag),
+            },
+            Operation::AddSource {
+                id,
+                node,
+                uri,
+                hash,
+                time,
+            } => Operation::AddSource {
+                id: *id,
+                node: self.node(*node),
+                uri: uri.clone(),
+                hash: hash.clone(),
+                time: *time,
+            },
+            Operation::AddComment {
+                node,
+                id,
+                comment,
+                author,
+                response_to,
+                time,
+            } => {
+                let node = self.node(*node);
+                let comments = self.merged.get(node).map(|n| &n.comments);
+                if let Some((id, comments)) =
+                    id.zip(comments).filter(|(id, c)| c.get(*id).is_some())
+                {
+                    self.comments.insert((node, id), comments.next_id());
+                }
+                Operation::AddComment {
+                    node,
+                    id: id.map(|id| self.comment(node, id)),
+                    comment: comment.clone(),
+                    author: author.clone(),
+                    response_to: self.comment(node, *response_to),
+                    time: *time,
+                }
+            }
+            Operation::EditComment {
+                node,
+                id,
+                comment,
+                time,
+            } => {
+                let node = self.node(*node);
+                Operation::EditComment {
+                    node,
+                    id: self.comment(node, *id),
+                    comment: comment.clone(),
+                    time: *time,
+                }
+            }
+            Operation::DeleteComment { node, id } => {
+                let node = self.node(*node);
+                Operation::DeleteComment {
+                    node,
+                    id: self.comment(node, *id),
+                }
+            }
+            Operation::ResolveComment { node, id, resolved } => {
+                let node = self.node(*node);
+                Operation::ResolveComment {
+                    node,
+                    id: self.comment(node, *id),
+                    resolved: *resolved,
+                }
+            }
+            Operation::Checkpoint { .. }
+            | Operation::Checksum { .. }
+            | Operation::Branch { .. }
+            | Operation::DeleteBranch { .. }
+            | Operation::Undo { .. }
+            | Operation::Redo { .. }
+            | Operation::Transaction { .. } => return None,
+            Operation::Snapshot { .. }
+            | Operation::Session { .. }
+            | Operation::UpdateSource { .. }
+            | Operation::RemoveSource { .. }
+            | Operation::UnknownOperation { .. } => operation.clone(),
+        };
+        Some(rebased)
+    }
+}
+
+/// The node and field an operation sets, if it is one that can conflict
+fn changed_field(operation: &Operation) -> Option<(NodeId, Field)> {
+    match operation {
+        Operation::SetName { node, .. } => Some((*node, Field::Name)),
+        Operation::SetType { node, .. } => Some((*node, Field::Type)),
+        Operation::SetAttribute {
+            node, attribute, ..
+        } => Some((*node, Field::Attribute(*attribute))),
+        Operation::MoveNode { id, .. } => Some((*id, Field::Position)),
+        _ => None,
+    }
+}
+
+#[derive(PartialEq)]
+enum FieldValue {
+    Name(Option<String>),
+    Type(Option<usize>),
+    Attribute(Option<AttributeValue>),
+    Position(Option<NodeId>, Option<usize>),
+}
+
+fn field_value(nodes: &NodeStore, node: NodeId, field: Field) -> FieldValue {
+    let n = nodes.get(node);
+    match field {
+        Field::Name => FieldValue::Name(n.and_then(|n| n.name.clone())),
+        Field::Type => FieldValue::Type(n.and_then(|n| n.type_id)),
+        Field::Attribute(attribute) => {
+            FieldValue::Attribute(n.and_then(|n| n.get_attribute(attribute).cloned()))
+        }
+        Field::Position => FieldValue::Position(
+            n.map(|n| n.parent),
+            n.and_then(|n| nodes.get(n.parent)?.get_child_index(node)),
+        ),
+    }
+}
+
+/// The sibling that an add or move of `node` to `index` in `parent` places it after
+fn left_sibling(nodes: &NodeStore, node: NodeId, parent: NodeId, index: usize) -> Option<NodeId> {
+    let children = &nodes.get(parent)?.children;
+    let others: Vec<NodeId> = children.iter().filter(|c| **c != node).copied().collect();
+    // A move within the same parent counts the index before the node is taken out
+    let index = match children.iter().position(|c| *c == node) {
+        Some(old) if index > old => index - 1,
+        _ => index,
+    };
+    index.checked_sub(1).and_then(|i| others.get(i).copied())
+}
+
+/// Index for an add or move of `node` to `parent` that places it right after `left`, or first if
+/// there is no sibling before it. If `left` is no longer a child, `fallback` is used.
+pub(crate) fn index_after(
+    nodes: &NodeStore,
+    node: NodeId,
+    parent: NodeId,
+    left: Option<NodeId>,
+    fallback: usize,
+) -> usize {
+    let Some(p) = nodes.get(parent) else {
+        return fallback;
+    };
+    let others: Vec<NodeId> = p.children.iter().filter(|c| **c != node).copied().collect();
+    let index = match left {
+        Some(left) => others
+            .iter()
+            .position(|c| *c == left)
+            .map_or(fallback.min(others.len()), |i| i + 1),
+        None => 0,
+    };
+    match p.children.iter().position(|c| *c == node) {
+        Some(old) if index >= old => index + 1,
+        _ => index,
+    }
+}
+
+impl Display for Field {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Field::Name => write!(f, "name"),
+            Field::Type => write!(f, "type"),
+            Field::Attribute(id) => write!(f, "attribute {}", id),
+            Field::Position => write!(f, "position"),
+        }
+    }
+}
+
+impl Display for Conflict {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Conflict::BothChanged { index, node, field } => write!(
+                f,
+                "Operation {} changes the {} of node {}, which was also changed by the other side",
+                index, field, node
+            ),
+            Conflict::Rejected { index, error } => {
+                write!(f, "Operation {} was left out: {}", index, error)
+            }
+            Conflict::Undone { index } => write!(
+                f,
+                "Operation {} was undone, but is kept as the other side builds on it",
+                index
+            ),
+        }
+    }
+}
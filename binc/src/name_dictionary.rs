@@ -1,4 +1,4 @@
-#[derive(Default)]
+#[derive(Default, Clone, PartialEq)]
 pub struct NameDictionary {
     names: Vec<Option<String>>,
 }
@@ -19,7 +19,23 @@ impl NameDictionary {
         self.names.iter().position(|x| x.as_deref() == Some(name))
     }
 
+    /// Defined names and their indices
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &str)> {
+        self.names
+            .iter()
+            .enumerate()
+            .filter_map(|(i, x)| x.as_deref().map(|name| (i, name)))
+    }
+
     pub fn len(&self) -> usize {
         self.names.len()
     }
+
+    /// Put back the name at `index` and the size from before a name was inserted
+    pub(crate) fn restore(&mut self, index: usize, name: Option<&str>, len: usize) {
+        if let Some(slot) = self.names.get_mut(index) {
+            *slot = name.map(str::to_string);
+        }
+        self.names.truncate(len);
+    }
 }
@@ -1,4 +1,5 @@
 use std::fmt::Display;
+use uuid::Uuid;
 
 #[derive(PartialEq, Clone, Copy, Debug, Eq, Hash)]
 pub struct NodeId {
@@ -48,9 +49,11 @@ impl Display for NodeId {
     }
 }
 
+/// Hands out node ids in order, from `start` up to but not including `end`
 #[derive(Debug, Clone)]
 pub struct NodeIdGenerator {
     next_id: usize,
+    end: usize,
 }
 
 impl Default for NodeIdGenerator {
@@ -61,12 +64,47 @@ impl Default for NodeIdGenerator {
 
 impl NodeIdGenerator {
     pub fn new() -> NodeIdGenerator {
-        NodeIdGenerator { next_id: 1 }
+        NodeIdGenerator::for_range(1, NodeId::NO_NODE_ID)
+    }
+
+    pub fn for_range(start: usize, end: usize) -> NodeIdGenerator {
+        NodeIdGenerator {
+            next_id: start.max(1),
+            end,
+        }
+    }
+
+    /// The id `next_id` will return, or None if the range is used up
+    pub fn peek(&self) -> Option<NodeId> {
+        (self.next_id < self.end).then(|| NodeId::new(self.next_id))
     }
 
     pub fn next_id(&mut self) -> NodeId {
-        let id = self.next_id;
+        let id = self.peek().expect("No node ids left in range");
         self.next_id += 1;
-        NodeId::new(id)
+        id
+    }
+}
+
+/// A range of node ids that a client has set aside for the nodes it adds, so writers whose
+/// reservations are in the same journal never pick the same id
+#[derive(Debug, Clone, PartialEq)]
+pub struct IdReservation {
+    pub client: Uuid,
+    pub start: usize,
+    pub count: usize,
+}
+
+impl IdReservation {
+    pub fn end(&self) -> usize {
+        self.start.saturating_add(self.count)
+    }
+
+    pub fn contains(&self, id: usize) -> bool {
+        (self.start..self.end()).contains(&id)
+    }
+
+    pub fn overlaps(&self, start: usize, end: usize) -> bool {
+        self.start < end && start < self.end()
     }
 }
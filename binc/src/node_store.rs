@@ -1,16 +1,27 @@
-use crate::attributes::{AttributeStore, AttributeValue};
+use crate::attributes::{AttributeStore, AttributeValue, F16, I24, U24};
 use crate::comments::Comments;
+use crate::diff::{self, Difference};
+use crate::error::{ApplyError, QueryError};
 use crate::name_dictionary::NameDictionary;
-use crate::node_id::NodeId;
+use crate::node_id::{IdReservation, NodeId};
+use crate::query::Query;
+use crate::sources::{Source, Sources};
+use uuid::Uuid;
 
 pub type NodeStore = FlatNodeStore;
 
-#[derive(Default)]
+/// How far above the nodes and reservations in use a new node or reservation may reach. Nodes
+/// are stored by id, so this keeps a damaged or hostile journal from allocating without bound.
+pub(crate) const MAX_ID_GAP: usize = 1 << 16;
+
+#[derive(Default, Clone)]
 pub struct FlatNodeStore {
     nodes: Vec<Node>,
     pub type_names: NameDictionary,
     pub attribute_names: NameDictionary,
     pub tag_names: NameDictionary,
+    pub sources: Sources,
+    pub id_reservations: Vec<IdReservation>,
 }
 
 impl FlatNodeStore {
@@ -23,6 +34,8 @@ impl FlatNodeStore {
             type_names: NameDictionary::default(),
             attribute_names: NameDictionary::default(),
             tag_names: NameDictionary::default(),
+            sources: Sources::default(),
+            id_reservations: vec![],
         }
     }
 
@@ -36,10 +49,64 @@ impl FlatNodeStore {
     }
 
     pub fn exists(&self, id: NodeId) -> bool {
-        self.nodes.get(id.index()).is_some()
+        self.nodes.get(id.index()).is_some_and(|n| n.id == id)
+    }
+
+    /// True if `ancestor` is `node` or one of its parents
+    pub fn is_ancestor(&self, ancestor: NodeId, node: NodeId) -> bool {
+        let mut current = node;
+        while current.exists() {
+            if current == ancestor {
+                return true;
+            }
+            current = match self.get(current) {
+                Some(n) => n.parent,
+                None => return false,
+            };
+        }
+        false
+    }
+
+    /// The node with the id, or an error if it does not exist
+    pub(crate) fn live_node_mut(&mut self, id: NodeId) -> Result<&mut Node, ApplyError> {
+        match self.nodes.get_mut(id.index()) {
+            Some(node) if node.id == id => Ok(node),
+            _ => Err(ApplyError::UnknownNode { node: id }),
+        }
     }
 
-    pub(crate) fn add(&mut self, id: NodeId, parent: NodeId, index_in_parent: usize) {
+    /// Number of children of `parent`, or an error if it does not exist
+    fn child_count(&self, parent: NodeId) -> Result<usize, ApplyError> {
+        match self.nodes.get(parent.index()) {
+            Some(node) if node.id == parent => Ok(node.children.len()),
+            _ => Err(ApplyError::ParentMissing { parent }),
+        }
+    }
+
+    pub(crate) fn add(
+        &mut self,
+        id: NodeId,
+        parent: NodeId,
+        index_in_parent: usize,
+    ) -> Result<(), ApplyError> {
+        if !id.exists() || id.is_root() || self.exists(id) {
+            return Err(ApplyError::DuplicateId { node: id });
+        }
+        let limit = self.id_limit();
+        if id.index() >= limit {
+            return Err(ApplyError::IdTooLarge {
+                id: id.index(),
+                limit,
+            });
+        }
+        let count = self.child_count(parent)?;
+        if index_in_parent > count {
+            return Err(ApplyError::IndexOutOfRange {
+                index: index_in_parent,
+                count,
+            });
+        }
+
         let i = id.index();
         let p = parent.index();
 
@@ -49,21 +116,111 @@ impl FlatNodeStore {
 
         self.nodes[i] = Node::new_with_id(id, parent);
         self.nodes[p].children.insert(index_in_parent, id.clone());
+        Ok(())
     }
 
-    pub(crate) fn delete_recursive(&mut self, id: NodeId) {
-        let i = id.index();
-        for c in self.nodes[i].children.clone() {
-            self.delete_recursive(c);
+    pub(crate) fn delete_recursive(&mut self, id: NodeId) -> Result<(), ApplyError> {
+        if id.is_root() {
+            return Err(ApplyError::RootNode);
         }
-        let p = self.nodes[i].parent.index();
+        let parent = self.live_node_mut(id)?.parent;
 
-        let position = self.nodes[p].get_child_index(id).expect("Node not found");
-        self.nodes[p].children.remove(position);
+        if let Some(p) = self.nodes.get_mut(parent.index()) {
+            p.children.retain(|x| *x != id);
+        }
+        self.delete_subtree(id);
+        Ok(())
+    }
+
+    fn delete_subtree(&mut self, id: NodeId) {
+        let i = id.index();
+        for c in std::mem::take(&mut self.nodes[i].children) {
+            self.delete_subtree(c);
+        }
         self.nodes[i] = Node::default();
     }
 
-    pub(crate) fn move_node(&mut self, id: NodeId, new_parent: NodeId, index_in_new_parent: usize) {
+    /// Copies of a node and all its descendants, starting with the node
+    pub(crate) fn subtree(&self, id: NodeId) -> Vec<Node> {
+        let mut subtree = vec![];
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            if let Some(node) = self.get(id) {
+                stack.extend(node.children.iter().rev());
+                subtree.push(node.clone());
+            }
+        }
+        subtree
+    }
+
+    /// Undo `add`: remove a node that has no children, and the slots that were added for it
+    pub(crate) fn remove_added(&mut self, id: NodeId, len: usize) {
+        if !self.exists(id) {
+            return;
+        }
+        let parent = self.nodes[id.index()].parent;
+        if let Some(p) = self.nodes.get_mut(parent.index()) {
+            p.children.retain(|x| *x != id);
+        }
+        self.nodes[id.index()] = Node::default();
+        if len < self.nodes.len() && self.nodes[len..].iter().all(|n| !n.id.exists()) {
+            self.nodes.truncate(len);
+        }
+    }
+
+    /// Undo `delete_recursive`: put back the nodes of a subtree, starting with its root, which
+    /// goes back to `index_in_parent`
+    pub(crate) fn restore_subtree(&mut self, index_in_parent: usize, subtree: &[Node]) {
+        let Some(root) = subtree.first() else {
+            return;
+        };
+        if let Some(p) = self.nodes.get_mut(root.parent.index()) {
+            p.children.insert(index_in_parent, root.id);
+        }
+        for node in subtree {
+            self.nodes[node.id.index()] = node.clone();
+        }
+    }
+
+    /// Undo `move_node`: put a node at exactly `index` among the children of `parent`
+    pub(crate) fn place(&mut self, id: NodeId, parent: NodeId, index: usize) {
+        if !self.exists(id) {
+            return;
+        }
+        let old_parent = self.nodes[id.index()].parent;
+        if let Some(p) = self.nodes.get_mut(old_parent.index()) {
+            p.children.retain(|x| *x != id);
+        }
+        if let Some(p) = self.nodes.get_mut(parent.index()) {
+            p.children.insert(index, id);
+        }
+        self.nodes[id.index()].parent = parent;
+    }
+
+    pub(crate) fn move_node(
+        &mut self,
+        id: NodeId,
+        new_parent: NodeId,
+        index_in_new_parent: usize,
+    ) -> Result<(), ApplyError> {
+        if id.is_root() {
+            return Err(ApplyError::RootNode);
+        }
+        self.live_node_mut(id)?;
+        let count = self.child_count(new_parent)?;
+        if self.is_ancestor(id, new_parent) {
+            return Err(ApplyError::MoveIntoOwnSubtree {
+                node: id,
+                parent: new_parent,
+            });
+        }
+        if index_in_new_parent > count {
+            return Err(ApplyError::IndexOutOfRange {
+                index: index_in_new_parent,
+                count,
+            });
+        }
+
         let i = id.index();
         let p1 = self.nodes[i].parent.index();
         let p2 = new_parent.index();
@@ -80,6 +237,7 @@ impl FlatNodeStore {
         self.nodes[p1].children.retain(|x| *x != id);
         self.nodes[p2].children.insert(insert_index, id.clone());
         self.nodes[i].parent = new_parent.clone();
+        Ok(())
     }
 
     pub fn get(&self, id: NodeId) -> Option<&Node> {
@@ -105,8 +263,139 @@ impl FlatNodeStore {
     pub(crate) fn len(&self) -> usize {
         self.nodes.len()
     }
+
+    /// Client that has reserved the id, if any
+    pub fn reserved_by(&self, id: NodeId) -> Option<Uuid> {
+        self.id_reservations
+            .iter()
+            .find(|r| r.contains(id.index()))
+            .map(|r| r.client)
+    }
+
+    /// Lowest id above all nodes and reservations
+    pub fn next_free_id(&self) -> usize {
+        self.id_reservations
+            .iter()
+            .map(|r| r.end())
+            .fold(self.nodes.len(), usize::max)
+    }
+
+    /// Ids of new nodes and the ends of new reservations must be below this
+    pub fn id_limit(&self) -> usize {
+        self.next_free_id()
+            .saturating_add(MAX_ID_GAP)
+            .min(NodeId::NO_NODE_ID)
+    }
+
+    /// True if no node uses the ids `start..start + count` and no client has reserved them
+    pub fn ids_available(&self, start: usize, count: usize) -> bool {
+        let end = start.saturating_add(count);
+        start != NodeId::ROOT_NODE_ID
+            && end <= NodeId::NO_NODE_ID
+            && !self.id_reservations.iter().any(|r| r.overlaps(start, end))
+            && !self
+                .nodes
+                .iter()
+                .skip(start)
+                .take(count)
+                .any(|n| n.id.exists())
+    }
+
+    pub(crate) fn reserve_ids(&mut self, reservation: IdReservation) -> Result<(), ApplyError> {
+        let limit = self.id_limit();
+        if reservation.end() > limit {
+            return Err(ApplyError::IdTooLarge {
+                id: reservation.end() - 1,
+                limit,
+            });
+        }
+        if !self.ids_available(reservation.start, reservation.count) {
+            return Err(ApplyError::IdsInUse {
+                start: reservation.start,
+                count: reservation.count,
+            });
+        }
+        self.id_reservations.push(reservation);
+        Ok(())
+    }
+
+    /// Define a source, and attach it to `node` unless it is `NodeId::NO_NODE`
+    pub(crate) fn add_source(&mut self, id: usize, node: NodeId, source: Source) {
+        self.sources.insert(id, source);
+        match self.get_mut(node) {
+            Some(node) if !node.sources.contains(&id) => node.sources.push(id),
+            _ => {}
+        }
+    }
+
+    pub(crate) fn remove_source(&mut self, id: usize) {
+        self.sources.remove(id);
+        for node in &mut self.nodes {
+            node.sources.retain(|x| *x != id);
+        }
+    }
+
+    /// All nodes that have the tag, in the order they are stored
+    pub fn nodes_with_tag(&self, tag: usize) -> Vec<NodeId> {
+        self.nodes
+            .iter()
+            .filter(|n| n.id.exists() && n.has_tag(tag))
+            .map(|n| n.id)
+            .collect()
+    }
+
+    /// All nodes that have the tag with the given name
+    pub fn nodes_with_tag_s(&self, tag: &str) -> Vec<NodeId> {
+        match self.tag_names.get_index(tag) {
+            Some(tag) => self.nodes_with_tag(tag),
+            None => vec![],
+        }
+    }
+
+    /// Differences that turn this state into `other`
+    pub fn diff(&self, other: &NodeStore) -> Vec<Difference> {
+        diff::diff(self, other)
+    }
+
+    /// Nodes that match a query, see `Query` for the syntax
+    pub fn select(&self, query: &str) -> Result<Vec<NodeId>, QueryError> {
+        Ok(Query::parse(query)?.select(self))
+    }
+
+    /// Sources of the node, or of the closest ancestor that has any
+    pub fn sources_for(&self, id: NodeId) -> Vec<(usize, &Source)> {
+        let mut current = id;
+        while let Some(node) = self.get(current) {
+            if !node.sources.is_empty() {
+                return node
+                    .sources
+                    .iter()
+                    .filter_map(|s| self.sources.get(*s).map(|source| (*s, source)))
+                    .collect();
+            }
+            current = node.parent;
+        }
+        vec![]
+    }
 }
 
+/// Stores are equal if they have the same nodes, names and reservations. Slots left by removed nodes are ignored.
+impl PartialEq for FlatNodeStore {
+    fn eq(&self, other: &Self) -> bool {
+        let live = |n: &&Node| n.id.exists();
+        self.nodes
+            .iter()
+            .filter(live)
+            .eq(other.nodes.iter().filter(live))
+            && self.type_names == other.type_names
+            && self.attribute_names == other.attribute_names
+            && self.tag_names == other.tag_names
+            && self.sources == other.sources
+            && self.id_reservations == other.id_reservations
+    }
+}
+
+#[derive(Clone, PartialEq)]
 pub struct Node {
     pub id: NodeId,
     pub name: Option<String>,
@@ -116,6 +405,8 @@ pub struct Node {
     pub attributes: AttributeStore,
     pub comments: Comments,
     pub tags: Vec<usize>,
+    /// Ids of the sources the node and its children were imported from
+    pub sources: Vec<usize>,
 }
 
 impl Default for Node {
@@ -129,6 +420,7 @@ impl Default for Node {
             attributes: AttributeStore::default(),
             comments: Comments::default(),
             tags: vec![],
+            sources: vec![],
         }
     }
 }
@@ -144,6 +436,7 @@ impl Node {
             attributes: AttributeStore::default(),
             comments: Comments::default(),
             tags: vec![],
+            sources: vec![],
         }
     }
 
@@ -163,14 +456,21 @@ impl Node {
         };
     }
 
+    /// Add a tag, unless the node already has it
     pub fn set_tag(&mut self, tag: usize) {
-        self.tags.push(tag);
+        if !self.has_tag(tag) {
+            self.tags.push(tag);
+        }
     }
 
     pub fn clear_tag(&mut self, tag: usize) {
         self.tags.retain(|x| *x != tag);
     }
 
+    pub fn has_tag(&self, tag: usize) -> bool {
+        self.tags.contains(&tag)
+    }
+
     pub fn get_name(&self) -> Option<&str> {
         self.name.as_deref()
     }
@@ -209,12 +509,120 @@ impl Node {
         }
     }
 
-    pub(crate) fn get_child_index(&self, id: NodeId) -> Option<usize> {
-        self.children.iter().position(|x| *x == id)
+    pub fn get_bool_array_attribute(&self, key: usize) -> Option<&[bool]> {
+        match self.attributes.get(key) {
+            Some(AttributeValue::BoolArray(a)) => Some(a),
+            _ => None,
+        }
+    }
+
+    pub fn get_string_array_attribute(&self, key: usize) -> Option<&[String]> {
+        match self.attributes.get(key) {
+            Some(AttributeValue::StringArray(a)) => Some(a),
+            _ => None,
+        }
+    }
+
+    pub fn get_uuid_array_attribute(&self, key: usize) -> Option<&[uuid::Uuid]> {
+        match self.attributes.get(key) {
+            Some(AttributeValue::UuidArray(a)) => Some(a),
+            _ => None,
+        }
+    }
+
+    pub fn get_u8_array_attribute(&self, key: usize) -> Option<&[u8]> {
+        match self.attributes.get(key) {
+            Some(AttributeValue::U8Array(a)) => Some(a),
+            _ => None,
+        }
+    }
+
+    pub fn get_u16_array_attribute(&self, key: usize) -> Option<&[u16]> {
+        match self.attributes.get(key) {
+            Some(AttributeValue::U16Array(a)) => Some(a),
+            _ => None,
+        }
+    }
+
+    pub fn get_u24_array_attribute(&self, key: usize) -> Option<&[U24]> {
+        match self.attributes.get(key) {
+            Some(AttributeValue::U24Array(a)) => Some(a),
+            _ => None,
+        }
+    }
+
+    pub fn get_u32_array_attribute(&self, key: usize) -> Option<&[u32]> {
+        match self.attributes.get(key) {
+            Some(AttributeValue::U32Array(a)) => Some(a),
+            _ => None,
+        }
+    }
+
+    pub fn get_u64_array_attribute(&self, key: usize) -> Option<&[u64]> {
+        match self.attributes.get(key) {
+            Some(AttributeValue::U64Array(a)) => Some(a),
+            _ => None,
+        }
+    }
+
+    pub fn get_i8_array_attribute(&self, key: usize) -> Option<&[i8]> {
+        match self.attributes.get(key) {
+            Some(AttributeValue::I8Array(a)) => Some(a),
+            _ => None,
+        }
     }
 
-    pub(crate) fn add_comment(&mut self, comment: &str, author: &str, response_to: usize) {
-        self.comments.add_comment(comment, author, response_to);
+    pub fn get_i16_array_attribute(&self, key: usize) -> Option<&[i16]> {
+        match self.attributes.get(key) {
+            Some(AttributeValue::I16Array(a)) => Some(a),
+            _ => None,
+        }
+    }
+
+    pub fn get_i24_array_attribute(&self, key: usize) -> Option<&[I24]> {
+        match self.attributes.get(key) {
+            Some(AttributeValue::I24Array(a)) => Some(a),
+            _ => None,
+        }
+    }
+
+    pub fn get_i32_array_attribute(&self, key: usize) -> Option<&[i32]> {
+        match self.attributes.get(key) {
+            Some(AttributeValue::I32Array(a)) => Some(a),
+            _ => None,
+        }
+    }
+
+    pub fn get_i64_array_attribute(&self, key: usize) -> Option<&[i64]> {
+        match self.attributes.get(key) {
+            Some(AttributeValue::I64Array(a)) => Some(a),
+            _ => None,
+        }
+    }
+
+    pub fn get_f16_array_attribute(&self, key: usize) -> Option<&[F16]> {
+        match self.attributes.get(key) {
+            Some(AttributeValue::F16Array(a)) => Some(a),
+            _ => None,
+        }
+    }
+
+    pub fn get_f32_array_attribute(&self, key: usize) -> Option<&[f32]> {
+        match self.attributes.get(key) {
+            Some(AttributeValue::F32Array(a)) => Some(a),
+            _ => None,
+        }
+    }
+
+    pub fn get_f64_array_attribute(&self, key: usize) -> Option<&[f64]> {
+        match self.attributes.get(key) {
+            Some(AttributeValue::F64Array(a)) => Some(a),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn get_child_index(&self, id: NodeId) -> Option<usize> {
+        self.children.iter().position(|x| *x == id)
     }
 }
 
@@ -234,7 +642,7 @@ mod tests {
     fn test_insert_and_get_node() {
         let mut store = FlatNodeStore::new();
         let node_id = NodeId::new(1);
-        store.add(node_id, NodeId::ROOT_NODE, 0);
+        store.add(node_id, NodeId::ROOT_NODE, 0).unwrap();
         assert!(store.get(node_id).is_some());
         assert!(store.get(node_id).expect("Node not found").parent == NodeId::ROOT_NODE);
         assert!(store.get(node_id).expect("Node not found").id == node_id);
@@ -251,12 +659,11 @@ mod tests {
     #[test]
     fn test_find_roots() {
         let mut store = FlatNodeStore::new();
-        let root_node = Node::new_with_id(NodeId::new(1), NodeId::ROOT_NODE);
-        store.nodes.push(root_node);
+        store.nodes.push(Node::default());
         let roots = store.find_roots();
         assert_eq!(roots.len(), 0);
         let node_id = NodeId::new(1);
-        store.add(node_id, NodeId::ROOT_NODE, 0);
+        store.add(node_id, NodeId::ROOT_NODE, 0).unwrap();
         let roots = store.find_roots();
         assert_eq!(roots.len(), 1);
         assert!(roots[0] == node_id);
@@ -267,11 +674,11 @@ mod tests {
         let mut store = FlatNodeStore::new();
         let id1 = NodeId::new(1);
         let id2 = NodeId::new(2);
-        store.add(id1, NodeId::ROOT_NODE, 0);
-        store.add(id2, id1, 0);
+        store.add(id1, NodeId::ROOT_NODE, 0).unwrap();
+        store.add(id2, id1, 0).unwrap();
         assert_eq!(store.get(id1).unwrap().parent, NodeId::ROOT_NODE);
         assert_eq!(store.get(id2).unwrap().parent, id1);
-        store.delete_recursive(id2);
+        store.delete_recursive(id2).unwrap();
         assert_eq!(store.nodes.len(), 3);
         assert_eq!(store.find_roots().len(), 1)
     }
@@ -281,9 +688,9 @@ mod tests {
         let mut store = FlatNodeStore::new();
         let id1 = NodeId::new(1);
         let id2 = NodeId::new(2);
-        store.add(id1, NodeId::ROOT_NODE, 0);
-        store.add(id2, id1, 0);
-        store.delete_recursive(id1);
+        store.add(id1, NodeId::ROOT_NODE, 0).unwrap();
+        store.add(id2, id1, 0).unwrap();
+        store.delete_recursive(id1).unwrap();
         assert_eq!(store.nodes.len(), 3);
         assert_eq!(store.find_roots().len(), 0)
     }
@@ -294,9 +701,9 @@ mod tests {
         let id1 = NodeId::new(1);
         let id2 = NodeId::new(2);
         let id3 = NodeId::new(3);
-        store.add(id1, NodeId::ROOT_NODE, 0);
-        store.add(id2, NodeId::ROOT_NODE, 1);
-        store.add(id3, NodeId::ROOT_NODE, 2);
-        store.move_node(id1, NodeId::ROOT_NODE, 3);
+        store.add(id1, NodeId::ROOT_NODE, 0).unwrap();
+        store.add(id2, NodeId::ROOT_NODE, 1).unwrap();
+        store.add(id3, NodeId::ROOT_NODE, 2).unwrap();
+        store.move_node(id1, NodeId::ROOT_NODE, 3).unwrap();
     }
 }
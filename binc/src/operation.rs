@@ -1,10 +1,17 @@
 use crate::attributes::{attribute_type, AttributeValue};
-use crate::node_id::NodeId;
+use crate::comments::Comments;
+use crate::error::{ApplyError, BincError, NameKind};
+use crate::inverse::Inverse;
+use crate::name_dictionary::NameDictionary;
+use crate::node_id::{IdReservation, NodeId};
 use crate::node_store::NodeStore;
 use crate::readwrite::{ReadExt, WriteExt};
+use crate::sources::Source;
+use chrono::{DateTime, Utc};
 use std::fmt::{Debug, Display, Formatter};
 use std::io;
 use std::io::{Read, Write};
+use uuid::Uuid;
 
 pub(crate) struct OperationIds;
 
@@ -23,9 +30,17 @@ impl OperationIds {
 
     // work in progress
     pub const DEFINE_TAG_NAME: u64 = 0x14;
+    pub const RESERVE_IDS: u64 = 0x15;
+    pub const BRANCH: u64 = 0x16;
+    pub const DELETE_BRANCH: u64 = 0x17;
+    pub const UNDO: u64 = 0x1A;
+    pub const REDO: u64 = 0x1B;
+    pub const TRANSACTION: u64 = 0x1C;
 
     pub const SNAPSHOT: u64 = 0x10;
     pub const CHECKSUM: u64 = 0x11;
+    pub const CHECKPOINT: u64 = 0x12;
+    pub const SESSION: u64 = 0x13;
 
     pub const ADD_TAG: u64 = 0x18;
     pub const REMOVE_TAG: u64 = 0x19;
@@ -35,6 +50,9 @@ impl OperationIds {
     pub const REMOVE_SOURCE: u64 = 0x23;
 
     pub const ADD_COMMENT: u64 = 0x31;
+    pub const EDIT_COMMENT: u64 = 0x32;
+    pub const DELETE_COMMENT: u64 = 0x33;
+    pub const RESOLVE_COMMENT: u64 = 0x34;
 
     pub const SET_UUID: u64 = 0x42;
     pub const SET_UINT8: u64 = 0x43;
@@ -67,6 +85,33 @@ impl OperationIds {
     pub const SET_FLOAT16_ARRAY: u64 = 0x6D;
     pub const SET_FLOAT32_ARRAY: u64 = 0x6E;
     pub const SET_FLOAT64_ARRAY: u64 = 0x6F;
+
+    /// True if the id is one of the operations defined above
+    pub fn is_known(id: u64) -> bool {
+        matches!(
+            id,
+            Self::ADD_NODE..=Self::SET_STRING
+                | Self::SNAPSHOT..=Self::SESSION
+                | Self::DEFINE_TAG_NAME
+                | Self::RESERVE_IDS
+                | Self::BRANCH
+                | Self::DELETE_BRANCH
+                | Self::UNDO
+                | Self::REDO
+                | Self::TRANSACTION
+                | Self::ADD_TAG
+                | Self::REMOVE_TAG
+                | Self::ADD_SOURCE..=Self::REMOVE_SOURCE
+                | Self::ADD_COMMENT..=Self::RESOLVE_COMMENT
+                | Self::SET_UUID..=Self::SET_FLOAT64
+                | Self::SET_BOOL_ARRAY..=Self::SET_FLOAT64_ARRAY
+        )
+    }
+
+    /// True if the id is part of version 1 of the binc specification
+    pub fn is_v1(id: u64) -> bool {
+        matches!(id, Self::ADD_NODE..=Self::SET_STRING)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -88,6 +133,31 @@ pub enum Operation {
     /// Remove a node from the document tree
     RemoveNode { id: NodeId },
 
+    /// Set aside `count` node ids starting at `start` for the nodes added by `client`
+    ReserveIds {
+        client: Uuid,
+        start: usize,
+        count: usize,
+    },
+
+    /// Continue on branch `name` from the state after the first `revision` operations, creating
+    /// the branch if it does not exist. The operations that follow belong to the branch.
+    Branch { name: String, revision: usize },
+
+    /// Delete a branch. Its operations stay in the journal.
+    DeleteBranch { name: String },
+
+    /// Undo the operations of the current branch after the first `revision` operations. The
+    /// state is the same as after them, and the undone operations stay in the journal.
+    Undo { revision: usize },
+
+    /// Redo the undo operation with index `revision`, going back to the state before it
+    Redo { revision: usize },
+
+    /// End a transaction. The operations after the first `revision` operations, up to this one,
+    /// undo and redo as one step.
+    Transaction { revision: usize },
+
     /// Set the type-id for a node
     SetType { node: NodeId, type_id: usize },
 
@@ -116,18 +186,74 @@ pub enum Operation {
     /// Remove a tag from a node
     RemoveTag { node: NodeId, tag: usize },
 
-    /// Add a named snapshot of the document
-    Snapshot { author: String, message: String },
+    /// Add a named snapshot of the document. The time is missing in files written before
+    /// snapshots had one.
+    Snapshot {
+        author: String,
+        message: String,
+        time: Option<DateTime<Utc>>,
+    },
 
     /// Add a checksum to the document up until this point. This can be used to verify the document is not corrupted
     Checksum { data: Vec<u8> },
 
-    /// Add a comment to a node
+    /// The complete state of the document after the first `revision` operations, so loading can
+    /// start here instead of replaying everything before it
+    Checkpoint { revision: usize, data: Vec<u8> },
+
+    /// Start a session. The operations that follow it were made by `author` using the client
+    /// with id `client`, in a session that started at `time`.
+    Session {
+        author: String,
+        time: DateTime<Utc>,
+        client: Uuid,
+    },
+
+    /// Define where content was imported from, and attach it to the root of the imported subtree.
+    /// `node` is `NodeId::NO_NODE` if the source is not attached to any node.
+    AddSource {
+        id: usize,
+        node: NodeId,
+        uri: String,
+        hash: Vec<u8>,
+        time: DateTime<Utc>,
+    },
+
+    /// Record that a source has been imported again
+    UpdateSource {
+        id: usize,
+        uri: String,
+        hash: Vec<u8>,
+        time: DateTime<Utc>,
+    },
+
+    /// Remove a source from the document and all nodes
+    RemoveSource { id: usize },
+
+    /// Add a comment to a node, optionally in response to another comment (0 if not a response).
+    /// Id and time are missing in files written before comments had them.
     AddComment {
         node: NodeId,
+        id: Option<usize>,
         comment: String,
         author: String,
         response_to: usize,
+        time: Option<DateTime<Utc>>,
+    },
+    /// Replace the text of a comment
+    EditComment {
+        node: NodeId,
+        id: usize,
+        comment: String,
+        time: DateTime<Utc>,
+    },
+    /// Delete the text of a comment. Responses to it are kept.
+    DeleteComment { node: NodeId, id: usize },
+    /// Mark a comment as resolved, or reopen it
+    ResolveComment {
+        node: NodeId,
+        id: usize,
+        resolved: bool,
     },
 
     /// Unknown change type. Since the size is known, the data can be read and written without knowing the type
@@ -139,88 +265,231 @@ impl Operation {
     // locate which ranges of the file are corrupted and automatically repair them using other sources.
     pub const HASH_ID: u32 = u32::from_be_bytes(*b"h@sH");
 
-    pub(crate) fn apply(&self, nodes: &mut NodeStore) {
+    /// Apply the operation to `nodes`. Nothing is changed if the operation does not fit them.
+    pub(crate) fn apply(&self, nodes: &mut NodeStore) -> Result<(), ApplyError> {
         match self {
             Operation::AddNode {
                 id,
                 parent,
                 index_in_parent,
-            } => {
-                nodes.add(*id, *parent, *index_in_parent as usize);
-            }
-            Operation::RemoveNode { id } => {
-                nodes.delete_recursive(*id);
-            }
+            } => nodes.add(*id, *parent, *index_in_parent as usize),
+            Operation::RemoveNode { id } => nodes.delete_recursive(*id),
+            Operation::ReserveIds {
+                client,
+                start,
+                count,
+            } => nodes.reserve_ids(IdReservation {
+                client: *client,
+                start: *start,
+                count: *count,
+            }),
             Operation::MoveNode {
                 id,
                 new_parent,
                 index_in_new_parent,
-            } => {
-                nodes.move_node(*id, *new_parent, *index_in_new_parent as usize);
-            }
+            } => nodes.move_node(*id, *new_parent, *index_in_new_parent as usize),
             Operation::SetType { node, type_id: id } => {
-                let x = nodes.get_mut(*node).expect("Node not found");
-                x.set_type(*id);
+                defined(&nodes.type_names, NameKind::Type, *id)?;
+                nodes.live_node_mut(*node)?.set_type(*id);
+                Ok(())
             }
             Operation::SetName { node, name } => {
-                let x = nodes.get_mut(*node).expect("Node not found");
-                x.set_name(name);
+                nodes.live_node_mut(*node)?.set_name(name);
+                Ok(())
             }
             Operation::DefineTypeName { id, name } => {
                 nodes.define_type_name(*id, name);
+                Ok(())
             }
             Operation::DefineAttributeName { id, name } => {
                 nodes.define_attribute_name(*id, name);
+                Ok(())
             }
             Operation::DefineTagName { id, name } => {
                 nodes.define_tag_name(*id, name);
+                Ok(())
             }
             Operation::SetTag { node, tag } => {
-                let x = nodes.get_mut(*node).expect("Node not found");
-                x.set_tag(*tag);
+                defined(&nodes.tag_names, NameKind::Tag, *tag)?;
+                nodes.live_node_mut(*node)?.set_tag(*tag);
+                Ok(())
             }
             Operation::RemoveTag { node, tag } => {
-                let x = nodes.get_mut(*node).expect("Node not found");
-                x.clear_tag(*tag);
+                defined(&nodes.tag_names, NameKind::Tag, *tag)?;
+                nodes.live_node_mut(*node)?.clear_tag(*tag);
+                Ok(())
             }
-            Operation::Snapshot {
-                author: _,
-                message: _,
-            } => {
+            Operation::Snapshot { .. } => {
                 // no-op
+                Ok(())
             }
             Operation::Checksum { data: _ } => {
                 // no-op
+                Ok(())
+            }
+            Operation::Checkpoint { .. } => {
+                // no-op, the state is already the same
+                Ok(())
+            }
+            Operation::Session { .. } => {
+                // no-op
+                Ok(())
+            }
+            Operation::Transaction { .. } => {
+                // no-op, the document groups the operations for undo
+                Ok(())
+            }
+            Operation::Branch { .. }
+            | Operation::DeleteBranch { .. }
+            | Operation::Undo { .. }
+            | Operation::Redo { .. } => {
+                // no-op, branches and undo are followed by the document
+                Ok(())
+            }
+            Operation::AddSource {
+                id,
+                node,
+                uri,
+                hash,
+                time,
+            } => {
+                if *node != NodeId::NO_NODE {
+                    nodes.live_node_mut(*node)?;
+                }
+                let source = Source {
+                    uri: uri.clone(),
+                    hash: hash.clone(),
+                    time: *time,
+                };
+                nodes.add_source(*id, *node, source);
+                Ok(())
+            }
+            Operation::UpdateSource {
+                id,
+                uri,
+                hash,
+                time,
+            } => {
+                if nodes.sources.get(*id).is_none() {
+                    return Err(ApplyError::UnknownSource { id: *id });
+                }
+                let source = Source {
+                    uri: uri.clone(),
+                    hash: hash.clone(),
+                    time: *time,
+                };
+                nodes.add_source(*id, NodeId::NO_NODE, source);
+                Ok(())
+            }
+            Operation::RemoveSource { id } => {
+                if nodes.sources.get(*id).is_none() {
+                    return Err(ApplyError::UnknownSource { id: *id });
+                }
+                nodes.remove_source(*id);
+                Ok(())
             }
             Operation::SetAttribute {
                 node,
                 attribute,
                 value,
             } => {
-                let x = nodes.get_mut(*node).expect("Node not found");
-                x.set_attribute(*attribute, value.clone());
+                defined(&nodes.attribute_names, NameKind::Attribute, *attribute)?;
+                nodes
+                    .live_node_mut(*node)?
+                    .set_attribute(*attribute, value.clone());
+                Ok(())
             }
             Operation::AddComment {
                 node,
+                id,
                 comment,
                 author,
                 response_to,
+                time,
+            } => {
+                let comments = &mut nodes.live_node_mut(*node)?.comments;
+                if let Some(id) = id.filter(|id| *id == 0 || comments.get(*id).is_some()) {
+                    return Err(ApplyError::InvalidCommentId { node: *node, id });
+                }
+                let known = *response_to == 0 || comments.get(*response_to).is_some();
+                // Files written before comments had ids may respond to anything, so those
+                // comments are kept as responses to nothing instead
+                let response_to = match (known, id) {
+                    (true, _) => *response_to,
+                    (false, None) => 0,
+                    (false, Some(_)) => {
+                        return Err(ApplyError::UnknownComment {
+                            node: *node,
+                            id: *response_to,
+                        });
+                    }
+                };
+                comments.add_comment(*id, comment, author, response_to, *time);
+                Ok(())
+            }
+            Operation::EditComment {
+                node,
+                id,
+                comment,
+                time,
             } => {
-                let x = nodes.get_mut(*node).expect("Node not found");
-                x.add_comment(comment, author, *response_to);
+                let comments = &mut nodes.live_node_mut(*node)?.comments;
+                undeleted_comment(comments, *node, *id)?;
+                comments.edit_comment(*id, comment, *time);
+                Ok(())
+            }
+            Operation::DeleteComment { node, id } => {
+                let comments = &mut nodes.live_node_mut(*node)?.comments;
+                undeleted_comment(comments, *node, *id)?;
+                comments.delete_comment(*id);
+                Ok(())
+            }
+            Operation::ResolveComment { node, id, resolved } => {
+                let comments = &mut nodes.live_node_mut(*node)?.comments;
+                if comments.get(*id).is_none() {
+                    return Err(ApplyError::UnknownComment {
+                        node: *node,
+                        id: *id,
+                    });
+                }
+                comments.set_resolved(*id, *resolved);
+                Ok(())
             }
             Operation::UnknownOperation {
                 operation: _,
                 data: _,
             } => {
                 // Do nothing
+                Ok(())
             }
         }
     }
 
-    pub(crate) fn read<T: Read>(r: &mut T) -> io::Result<Operation> {
-        let operation = r.read_length_flipped()? as u64;
-        let size = r.read_length()?;
+    /// Apply the operation to `nodes`, and return what undoes it
+    pub(crate) fn apply_with_inverse(&self, nodes: &mut NodeStore) -> Result<Inverse, ApplyError> {
+        let inverse = Inverse::before(self, nodes);
+        self.apply(nodes)?;
+        Ok(inverse)
+    }
+
+    /// Read one operation. Offsets in the returned errors are relative to the start of the operation.
+    pub(crate) fn read<T: Read>(r: &mut T) -> Result<Operation, BincError> {
+        let header_error = |e: io::Error| match e.kind() {
+            io::ErrorKind::UnexpectedEof => BincError::TruncatedOperation { offset: 0 },
+            io::ErrorKind::InvalidData => BincError::InvalidVarint { offset: 0 },
+            _ => BincError::Io(e),
+        };
+        let operation = r.read_length_flipped().map_err(header_error)? as u64;
+        let size = r.read_length().map_err(header_error)?;
+
+        // Only the bytes covered by the size belong to this operation
+        let content = r.read_vec(size).map_err(header_error)?;
+        // Bytes left after the known fields are fields added by a later version, and are skipped
+        Self::read_content(operation, size, &mut content.as_slice())
+            .map_err(BincError::from_content_error)
+    }
+
+    fn read_content(operation: u64, size: usize, r: &mut &[u8]) -> io::Result<Operation> {
         match operation {
             OperationIds::ADD_NODE => {
                 let id = r.read_id()?;
@@ -249,7 +518,16 @@ impl Operation {
             OperationIds::SNAPSHOT => {
                 let author = r.read_string()?;
                 let message = r.read_string()?;
-                Ok(Operation::Snapshot { author, message })
+                let time = if r.is_empty() {
+                    None
+                } else {
+                    Some(r.read_timestamp()?)
+                };
+                Ok(Operation::Snapshot {
+                    author,
+                    message,
+                    time,
+                })
             }
             OperationIds::CHECKSUM => {
                 let hash = r.read_u32()?;
@@ -262,6 +540,52 @@ impl Operation {
                 let data = r.read_bytes()?;
                 Ok(Operation::Checksum { data })
             }
+            OperationIds::CHECKPOINT => {
+                let revision = r.read_length()?;
+                let data = r.read_bytes()?;
+                Ok(Operation::Checkpoint { revision, data })
+            }
+            OperationIds::RESERVE_IDS => {
+                let client = r.read_uuid()?;
+                let start = r.read_length()?;
+                let count = r.read_length()?;
+                Ok(Operation::ReserveIds {
+                    client,
+                    start,
+                    count,
+                })
+            }
+            OperationIds::BRANCH => {
+                let name = r.read_string()?;
+                let revision = r.read_length()?;
+                Ok(Operation::Branch { name, revision })
+            }
+            OperationIds::DELETE_BRANCH => {
+                let name = r.read_string()?;
+                Ok(Operation::DeleteBranch { name })
+            }
+            OperationIds::UNDO => {
+                let revision = r.read_length()?;
+                Ok(Operation::Undo { revision })
+            }
+            OperationIds::REDO => {
+                let revision = r.read_length()?;
+                Ok(Operation::Redo { revision })
+            }
+            OperationIds::TRANSACTION => {
+                let revision = r.read_length()?;
+                Ok(Operation::Transaction { revision })
+            }
+            OperationIds::SESSION => {
+                let author = r.read_string()?;
+                let time = r.read_timestamp()?;
+                let client = r.read_uuid()?;
+                Ok(Operation::Session {
+                    author,
+                    time,
+                    client,
+                })
+            }
             OperationIds::SET_STRING => {
                 let node = r.read_id()?;
                 let attribute = r.read_length()?;
@@ -312,6 +636,16 @@ impl Operation {
                     value: AttributeValue::U16(value),
                 })
             }
+            OperationIds::SET_UINT24 => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_u24()?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::U24(value),
+                })
+            }
             OperationIds::SET_UINT32 => {
                 let node = r.read_id()?;
                 let attribute = r.read_length()?;
@@ -352,6 +686,16 @@ impl Operation {
                     value: AttributeValue::I16(value),
                 })
             }
+            OperationIds::SET_INT24 => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_i24()?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::I24(value),
+                })
+            }
             OperationIds::SET_INT32 => {
                 let node = r.read_id()?;
                 let attribute = r.read_length()?;
@@ -372,6 +716,16 @@ impl Operation {
                     value: AttributeValue::I64(value),
                 })
             }
+            OperationIds::SET_FLOAT16 => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_f16()?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::F16(value),
+                })
+            }
             OperationIds::SET_FLOAT32 => {
                 let node = r.read_id()?;
                 let attribute = r.read_length()?;
@@ -392,6 +746,166 @@ impl Operation {
                     value: AttributeValue::F64(value),
                 })
             }
+            OperationIds::SET_BOOL_ARRAY => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_bool_array()?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::BoolArray(value),
+                })
+            }
+            OperationIds::SET_STRING_ARRAY => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_string_array()?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::StringArray(value),
+                })
+            }
+            OperationIds::SET_UUID_ARRAY => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_uuid_array()?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::UuidArray(value),
+                })
+            }
+            OperationIds::SET_UINT8_ARRAY => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_u8_array()?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::U8Array(value),
+                })
+            }
+            OperationIds::SET_UINT16_ARRAY => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_u16_array()?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::U16Array(value),
+                })
+            }
+            OperationIds::SET_UINT24_ARRAY => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_u24_array()?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::U24Array(value),
+                })
+            }
+            OperationIds::SET_UINT32_ARRAY => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_u32_array()?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::U32Array(value),
+                })
+            }
+            OperationIds::SET_UINT64_ARRAY => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_u64_array()?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::U64Array(value),
+                })
+            }
+            OperationIds::SET_INT8_ARRAY => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_i8_array()?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::I8Array(value),
+                })
+            }
+            OperationIds::SET_INT16_ARRAY => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_i16_array()?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::I16Array(value),
+                })
+            }
+            OperationIds::SET_INT24_ARRAY => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_i24_array()?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::I24Array(value),
+                })
+            }
+            OperationIds::SET_INT32_ARRAY => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_i32_array()?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::I32Array(value),
+                })
+            }
+            OperationIds::SET_INT64_ARRAY => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_i64_array()?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::I64Array(value),
+                })
+            }
+            OperationIds::SET_FLOAT16_ARRAY => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_f16_array()?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::F16Array(value),
+                })
+            }
+            OperationIds::SET_FLOAT32_ARRAY => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_f32_array()?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::F32Array(value),
+                })
+            }
+            OperationIds::SET_FLOAT64_ARRAY => {
+                let node = r.read_id()?;
+                let attribute = r.read_length()?;
+                let value = r.read_f64_array()?;
+                Ok(Operation::SetAttribute {
+                    node,
+                    attribute,
+                    value: AttributeValue::F64Array(value),
+                })
+            }
             OperationIds::SET_NAME => {
                 let node = r.read_id()?;
                 let name = r.read_string()?;
@@ -420,18 +934,93 @@ impl Operation {
                 let name = r.read_string()?;
                 Ok(Operation::DefineTagName { id, name })
             }
+            OperationIds::ADD_TAG => {
+                let node = r.read_id()?;
+                let tag = r.read_length()?;
+                Ok(Operation::SetTag { node, tag })
+            }
+            OperationIds::REMOVE_TAG => {
+                let node = r.read_id()?;
+                let tag = r.read_length()?;
+                Ok(Operation::RemoveTag { node, tag })
+            }
+            OperationIds::ADD_SOURCE => {
+                let id = r.read_length()?;
+                let node = r.read_id()?;
+                let uri = r.read_string()?;
+                let hash = r.read_bytes()?;
+                let time = r.read_timestamp()?;
+                Ok(Operation::AddSource {
+                    id,
+                    node,
+                    uri,
+                    hash,
+                    time,
+                })
+            }
+            OperationIds::UPDATE_SOURCE => {
+                let id = r.read_length()?;
+                let uri = r.read_string()?;
+                let hash = r.read_bytes()?;
+                let time = r.read_timestamp()?;
+                Ok(Operation::UpdateSource {
+                    id,
+                    uri,
+                    hash,
+                    time,
+                })
+            }
+            OperationIds::REMOVE_SOURCE => {
+                let id = r.read_length()?;
+                Ok(Operation::RemoveSource { id })
+            }
             OperationIds::ADD_COMMENT => {
                 let node = r.read_id()?;
                 let comment = r.read_string()?;
                 let author = r.read_string()?;
                 let response_to = r.read_length()?;
+                let id = if r.is_empty() {
+                    None
+                } else {
+                    Some(r.read_length()?)
+                };
+                let time = if r.is_empty() {
+                    None
+                } else {
+                    Some(r.read_timestamp()?)
+                };
                 Ok(Operation::AddComment {
                     node,
+                    id,
                     comment,
                     author,
                     response_to,
+                    time,
                 })
             }
+            OperationIds::EDIT_COMMENT => {
+                let node = r.read_id()?;
+                let id = r.read_length()?;
+                let comment = r.read_string()?;
+                let time = r.read_timestamp()?;
+                Ok(Operation::EditComment {
+                    node,
+                    id,
+                    comment,
+                    time,
+                })
+            }
+            OperationIds::DELETE_COMMENT => {
+                let node = r.read_id()?;
+                let id = r.read_length()?;
+                Ok(Operation::DeleteComment { node, id })
+            }
+            OperationIds::RESOLVE_COMMENT => {
+                let node = r.read_id()?;
+                let id = r.read_length()?;
+                let resolved = r.read_bool()?;
+                Ok(Operation::ResolveComment { node, id, resolved })
+            }
             _ => {
                 let mut data = vec![0; size as usize];
                 r.read_exact(&mut data)?;
@@ -473,14 +1062,52 @@ impl Operation {
                 w.write_length(*index_in_new_parent)
             }
             Operation::RemoveNode { id } => w.write_id(id),
-            Operation::Snapshot { author, message } => {
+            Operation::Snapshot {
+                author,
+                message,
+                time,
+            } => {
                 w.write_string(author)?;
-                w.write_string(message)
+                w.write_string(message)?;
+                if let Some(time) = time {
+                    w.write_timestamp(time)?;
+                }
+                Ok(())
             }
             Operation::Checksum { data } => {
                 w.write_u32(Operation::HASH_ID)?;
                 w.write_bytes(data)
             }
+            Operation::Checkpoint { revision, data } => {
+                w.write_length(*revision)?;
+                w.write_bytes(data)
+            }
+            Operation::ReserveIds {
+                client,
+                start,
+                count,
+            } => {
+                w.write_uuid(client)?;
+                w.write_length(*start)?;
+                w.write_length(*count)
+            }
+            Operation::Branch { name, revision } => {
+                w.write_string(name)?;
+                w.write_length(*revision)
+            }
+            Operation::DeleteBranch { name } => w.write_string(name),
+            Operation::Undo { revision }
+            | Operation::Redo { revision }
+            | Operation::Transaction { revision } => w.write_length(*revision),
+            Operation::Session {
+                author,
+                time,
+                client,
+            } => {
+                w.write_string(author)?;
+                w.write_timestamp(time)?;
+                w.write_uuid(client)
+            }
             Operation::SetName { node, name: label } => {
                 w.write_id(node)?;
                 w.write_string(label)
@@ -522,33 +1149,109 @@ impl Operation {
                     AttributeValue::Uuid(u) => w.write_uuid(u),
                     AttributeValue::U8(u) => w.write_u8(*u),
                     AttributeValue::U16(u) => w.write_u16(*u),
-                    AttributeValue::U24(u) => w.write_bytes(u),
+                    AttributeValue::U24(u) => w.write_u24(*u),
                     AttributeValue::U32(u) => w.write_u32(*u),
                     AttributeValue::U64(u) => w.write_u64(*u),
                     AttributeValue::I8(u) => w.write_i8(*u),
                     AttributeValue::I16(u) => w.write_i16(*u),
-                    AttributeValue::I24(u) => w.write_bytes(u),
+                    AttributeValue::I24(u) => w.write_i24(*u),
                     AttributeValue::I32(u) => w.write_i32(*u),
                     AttributeValue::I64(u) => w.write_i64(*u),
+                    AttributeValue::F16(u) => w.write_f16(*u),
                     AttributeValue::F32(u) => w.write_f32(*u),
                     AttributeValue::F64(u) => w.write_f64(*u),
+                    AttributeValue::BoolArray(a) => w.write_bool_array(a),
+                    AttributeValue::StringArray(a) => w.write_string_array(a),
+                    AttributeValue::UuidArray(a) => w.write_uuid_array(a),
+                    AttributeValue::U8Array(a) => w.write_u8_array(a),
+                    AttributeValue::U16Array(a) => w.write_u16_array(a),
+                    AttributeValue::U24Array(a) => w.write_u24_array(a),
+                    AttributeValue::U32Array(a) => w.write_u32_array(a),
+                    AttributeValue::U64Array(a) => w.write_u64_array(a),
+                    AttributeValue::I8Array(a) => w.write_i8_array(a),
+                    AttributeValue::I16Array(a) => w.write_i16_array(a),
+                    AttributeValue::I24Array(a) => w.write_i24_array(a),
+                    AttributeValue::I32Array(a) => w.write_i32_array(a),
+                    AttributeValue::I64Array(a) => w.write_i64_array(a),
+                    AttributeValue::F16Array(a) => w.write_f16_array(a),
+                    AttributeValue::F32Array(a) => w.write_f32_array(a),
+                    AttributeValue::F64Array(a) => w.write_f64_array(a),
                 }
             }
             Operation::AddComment {
                 node,
+                id,
                 comment,
                 author,
                 response_to,
+                time,
             } => {
                 w.write_id(node)?;
                 w.write_string(comment)?;
                 w.write_string(author)?;
-                w.write_length(*response_to)
+                w.write_length(*response_to)?;
+                if let Some(id) = id {
+                    w.write_length(*id)?;
+                    if let Some(time) = time {
+                        w.write_timestamp(time)?;
+                    }
+                }
+                Ok(())
+            }
+            Operation::EditComment {
+                node,
+                id,
+                comment,
+                time,
+            } => {
+                w.write_id(node)?;
+                w.write_length(*id)?;
+                w.write_string(comment)?;
+                w.write_timestamp(time)
+            }
+            Operation::DeleteComment { node, id } => {
+                w.write_id(node)?;
+                w.write_length(*id)
+            }
+            Operation::ResolveComment { node, id, resolved } => {
+                w.write_id(node)?;
+                w.write_length(*id)?;
+                w.write_bool(*resolved)
+            }
+            Operation::AddSource {
+                id,
+                node,
+                uri,
+                hash,
+                time,
+            } => {
+                w.write_length(*id)?;
+                w.write_id(node)?;
+                w.write_string(uri)?;
+                w.write_bytes(hash)?;
+                w.write_timestamp(time)
+            }
+            Operation::UpdateSource {
+                id,
+                uri,
+                hash,
+                time,
+            } => {
+                w.write_length(*id)?;
+                w.write_string(uri)?;
+                w.write_bytes(hash)?;
+                w.write_timestamp(time)
             }
+            Operation::RemoveSource { id } => w.write_length(*id),
             Operation::UnknownOperation { operation: _, data } => w.write_all(data),
         }
     }
 
+    /// True if the operation is part of version 1 of the binc specification
+    pub fn is_v1(&self) -> bool {
+        OperationIds::is_v1(self.operation_id())
+    }
+
     pub(crate) fn operation_id(&self) -> u64 {
         match self {
             Operation::AddNode {
@@ -562,11 +1265,16 @@ impl Operation {
                 index_in_new_parent: _,
             } => OperationIds::MOVE_NODE,
             Operation::RemoveNode { id: _ } => OperationIds::REMOVE_NODE,
-            Operation::Snapshot {
-                author: _,
-                message: _,
-            } => OperationIds::SNAPSHOT,
+            Operation::Snapshot { .. } => OperationIds::SNAPSHOT,
             Operation::Checksum { data: _ } => OperationIds::CHECKSUM,
+            Operation::Checkpoint { .. } => OperationIds::CHECKPOINT,
+            Operation::Session { .. } => OperationIds::SESSION,
+            Operation::ReserveIds { .. } => OperationIds::RESERVE_IDS,
+            Operation::Branch { .. } => OperationIds::BRANCH,
+            Operation::DeleteBranch { .. } => OperationIds::DELETE_BRANCH,
+            Operation::Undo { .. } => OperationIds::UNDO,
+            Operation::Redo { .. } => OperationIds::REDO,
+            Operation::Transaction { .. } => OperationIds::TRANSACTION,
             Operation::SetName { node: _, name: _ } => OperationIds::SET_NAME,
             Operation::SetType {
                 node: _,
@@ -597,15 +1305,33 @@ impl Operation {
                 AttributeValue::I24(_) => OperationIds::SET_INT24,
                 AttributeValue::I32(_) => OperationIds::SET_INT32,
                 AttributeValue::I64(_) => OperationIds::SET_INT64,
+                AttributeValue::F16(_) => OperationIds::SET_FLOAT16,
                 AttributeValue::F32(_) => OperationIds::SET_FLOAT32,
                 AttributeValue::F64(_) => OperationIds::SET_FLOAT64,
+                AttributeValue::BoolArray(_) => OperationIds::SET_BOOL_ARRAY,
+                AttributeValue::StringArray(_) => OperationIds::SET_STRING_ARRAY,
+                AttributeValue::UuidArray(_) => OperationIds::SET_UUID_ARRAY,
+                AttributeValue::U8Array(_) => OperationIds::SET_UINT8_ARRAY,
+                AttributeValue::U16Array(_) => OperationIds::SET_UINT16_ARRAY,
+                AttributeValue::U24Array(_) => OperationIds::SET_UINT24_ARRAY,
+                AttributeValue::U32Array(_) => OperationIds::SET_UINT32_ARRAY,
+                AttributeValue::U64Array(_) => OperationIds::SET_UINT64_ARRAY,
+                AttributeValue::I8Array(_) => OperationIds::SET_INT8_ARRAY,
+                AttributeValue::I16Array(_) => OperationIds::SET_INT16_ARRAY,
+                AttributeValue::I24Array(_) => OperationIds::SET_INT24_ARRAY,
+                AttributeValue::I32Array(_) => OperationIds::SET_INT32_ARRAY,
+                AttributeValue::I64Array(_) => OperationIds::SET_INT64_ARRAY,
+                AttributeValue::F16Array(_) => OperationIds::SET_FLOAT16_ARRAY,
+                AttributeValue::F32Array(_) => OperationIds::SET_FLOAT32_ARRAY,
+                AttributeValue::F64Array(_) => OperationIds::SET_FLOAT64_ARRAY,
             },
-            Operation::AddComment {
-                node: _,
-                comment: _,
-                author: _,
-                response_to: _,
-            } => OperationIds::ADD_COMMENT,
+            Operation::AddComment { .. } => OperationIds::ADD_COMMENT,
+            Operation::EditComment { .. } => OperationIds::EDIT_COMMENT,
+            Operation::DeleteComment { .. } => OperationIds::DELETE_COMMENT,
+            Operation::ResolveComment { .. } => OperationIds::RESOLVE_COMMENT,
+            Operation::AddSource { .. } => OperationIds::ADD_SOURCE,
+            Operation::UpdateSource { .. } => OperationIds::UPDATE_SOURCE,
+            Operation::RemoveSource { .. } => OperationIds::REMOVE_SOURCE,
             Operation::UnknownOperation {
                 operation: operation,
                 data: _,
@@ -673,10 +1399,53 @@ impl Display for Operation {
                 id, new_parent, index_in_new_parent
             ),
             Operation::RemoveNode { id } => write!(f, "RemoveNode({})", id),
-            Operation::Snapshot { author, message } => {
-                write!(f, "Snapshot by {} ({})", author, message)
-            }
+            Operation::Snapshot {
+                author,
+                message,
+                time: Some(time),
+            } => write!(
+                f,
+                "Snapshot by {} at {} ({})",
+                author,
+                time.to_rfc3339(),
+                message
+            ),
+            Operation::Snapshot {
+                author,
+                message,
+                time: None,
+            } => write!(f, "Snapshot by {} ({})", author, message),
             Operation::Checksum { data } => write!(f, "Checksum({} bytes)", data.len()),
+            Operation::Checkpoint { revision, data } => {
+                write!(f, "Checkpoint({}, {} bytes)", revision, data.len())
+            }
+            Operation::Session {
+                author,
+                time,
+                client,
+            } => write!(
+                f,
+                "Session by {} at {} ({})",
+                author,
+                time.to_rfc3339(),
+                client
+            ),
+            Operation::ReserveIds {
+                client,
+                start,
+                count,
+            } => write!(
+                f,
+                "ReserveIds({}..{} for {})",
+                start,
+                start.saturating_add(*count),
+                client
+            ),
+            Operation::Branch { name, revision } => write!(f, "Branch({} from {})", name, revision),
+            Operation::DeleteBranch { name } => write!(f, "DeleteBranch({})", name),
+            Operation::Undo { revision } => write!(f, "Undo(to {})", revision),
+            Operation::Redo { revision } => write!(f, "Redo({})", revision),
+            Operation::Transaction { revision } => write!(f, "Transaction(from {})", revision),
             Operation::SetType { node, type_id } => write!(f, "SetType({}, {})", node, type_id),
             Operation::SetName { node, name: label } => write!(f, "SetLabel({}, {})", node, label),
             Operation::DefineTypeName { id, name } => write!(f, "SetTypeName({}, {})", id, name),
@@ -722,11 +1491,41 @@ impl Display for Operation {
                 comment,
                 author,
                 response_to,
+                ..
             } => write!(
                 f,
                 "AddComment({}, {} by {} in response to {})",
                 node, comment, author, response_to
             ),
+            Operation::EditComment {
+                node, id, comment, ..
+            } => write!(f, "EditComment({}, {}, {})", node, id, comment),
+            Operation::DeleteComment { node, id } => write!(f, "DeleteComment({}, {})", node, id),
+            Operation::ResolveComment { node, id, resolved } => {
+                write!(f, "ResolveComment({}, {}, {})", node, id, resolved)
+            }
+            Operation::AddSource { id, node, uri, .. } => {
+                write!(f, "AddSource({}, {}, {})", id, node, uri)
+            }
+            Operation::UpdateSource { id, uri, .. } => {
+                write!(f, "UpdateSource({}, {})", id, uri)
+            }
+            Operation::RemoveSource { id } => write!(f, "RemoveSource({})", id),
         }
     }
 }
+
+fn defined(names: &NameDictionary, kind: NameKind, id: usize) -> Result<(), ApplyError> {
+    match names.get(id) {
+        Some(_) => Ok(()),
+        None => Err(ApplyError::UndefinedName { kind, id }),
+    }
+}
+
+fn undeleted_comment(comments: &Comments, node: NodeId, id: usize) -> Result<(), ApplyError> {
+    match comments.get(id) {
+        Some(c) if c.deleted => Err(ApplyError::DeletedComment { node, id }),
+        Some(_) => Ok(()),
+        None => Err(ApplyError::UnknownComment { node, id }),
+    }
+}
@@ -0,0 +1,575 @@
+use crate::attributes::AttributeValue;
+use crate::error::QueryError;
+use crate::node_id::NodeId;
+use crate::node_store::{Node, NodeStore};
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+/// A selector for nodes, parsed from text such as
+/// `issue[status="open"][assignee~="kim"] > comment order by time desc`.
+///
+/// - `issue` matches nodes with that type, `*` any node
+/// - `#name` matches nodes with that name
+/// - `.tag` matches nodes with that tag
+/// - `[key]` matches nodes that have the attribute, `[key op value]` compares it. The operators
+///   are `=`, `!=`, `<`, `<=`, `>`, `>=`, `^=` (starts with), `$=` (ends with) and `~=`
+///   (contains, ignoring case). Values are compared as numbers if both sides are numbers.
+///   `!=` also matches nodes that do not have the attribute.
+/// - `@name` and `@type` can be used as keys for the name and type of the node
+/// - `a b` matches `b` nodes inside `a` nodes, `a > b` only the children
+/// - `order by key desc, key` sorts the nodes, which are in tree order otherwise
+///
+/// Names, values and keys with other characters than letters, digits, `_` and `-` are written in
+/// double quotes, with `\` before `"` and `\` inside them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Query {
+    /// The nodes to match from the outermost, and whether each is a child of the one before
+    steps: Vec<(Step, bool)>,
+    order: Vec<(Key, bool)>,
+}
+
+/// Conditions on a single node
+#[derive(Debug, Clone, PartialEq, Default)]
+struct Step {
+    type_name: Option<String>,
+    name: Option<String>,
+    tags: Vec<String>,
+    filters: Vec<Filter>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Filter {
+    key: Key,
+    /// None if the key only has to be present
+    comparison: Option<(Operator, String)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Key {
+    Name,
+    Type,
+    Attribute(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operator {
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    StartsWith,
+    EndsWith,
+    Contains,
+}
+
+/// A value of a node to compare or sort by
+enum Value<'a> {
+    Text(&'a str),
+    Attribute(&'a AttributeValue),
+}
+
+impl Query {
+    pub fn parse(text: &str) -> Result<Query, QueryError> {
+        Parser { text, position: 0 }.query()
+    }
+
+    /// The matching nodes, in tree order or the order of the query
+    pub fn select(&self, nodes: &NodeStore) -> Vec<NodeId> {
+        let mut selected: Vec<NodeId> = tree_order(nodes)
+            .into_iter()
+            .filter(|id| self.matches(nodes, *id))
+            .collect();
+        if !self.order.is_empty() {
+            selected.sort_by(|a, b| self.compare(nodes, *a, *b));
+        }
+        selected
+    }
+
+    /// True if the node matches the query. The root node never does.
+    pub fn matches(&self, nodes: &NodeStore, id: NodeId) -> bool {
+        let Some(node) = selectable(nodes, id) else {
+            return false;
+        };
+        self.matches_from(nodes, node, self.steps.len() - 1)
+    }
+
+    /// True if the node matches step `last`, and its ancestors the steps before it
+    fn matches_from(&self, nodes: &NodeStore, node: &Node, last: usize) -> bool {
+        let (step, child) = &self.steps[last];
+        if !step.matches(nodes, node) {
+            return false;
+        }
+        if last == 0 {
+            return true;
+        }
+        let mut ancestor = selectable(nodes, node.parent);
+        while let Some(node) = ancestor {
+            if self.matches_from(nodes, node, last - 1) {
+                return true;
+            }
+            if *child {
+                return false;
+            }
+            ancestor = selectable(nodes, node.parent);
+        }
+        false
+    }
+
+    fn compare(&self, nodes: &NodeStore, a: NodeId, b: NodeId) -> Ordering {
+        let (Some(a), Some(b)) = (nodes.get(a), nodes.get(b)) else {
+            return Ordering::Equal;
+        };
+        for (key, descending) in &self.order {
+            let ordering = match (key.value(nodes, a), key.value(nodes, b)) {
+                (Some(a), Some(b)) if *descending => compare_values(&b, &a),
+                (Some(a), Some(b)) => compare_values(&a, &b),
+                // Nodes without the value come last
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            };
+            if ordering != Ordering::Equal {
+                return ordering;
+            }
+        }
+        Ordering::Equal
+    }
+}
+
+impl FromStr for Query {
+    type Err = QueryError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Query::parse(s)
+    }
+}
+
+/// Write text as a quoted string for a query
+pub fn quote(text: &str) -> String {
+    let mut quoted = String::from('"');
+    for c in text.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+impl Step {
+    fn is_empty(&self) -> bool {
+        *self == Step::default()
+    }
+
+    fn matches(&self, nodes: &NodeStore, node: &Node) -> bool {
+        if let Some(type_name) = &self.type_name {
+            let type_id = nodes.type_names.get_index(type_name);
+            if type_id.is_none() || node.type_id != type_id {
+                return false;
+            }
+        }
+        if self.name.is_some() && node.get_name() != self.name.as_deref() {
+            return false;
+        }
+        let has_tag = |tag: &String| {
+            nodes
+                .tag_names
+                .get_index(tag)
+                .is_some_and(|tag| node.has_tag(tag))
+        };
+        self.tags.iter().all(has_tag) && self.filters.iter().all(|f| f.matches(nodes, node))
+    }
+}
+
+impl Filter {
+    fn matches(&self, nodes: &NodeStore, node: &Node) -> bool {
+        let value = self.key.value(nodes, node);
+        let Some((operator, expected)) = &self.comparison else {
+            return value.is_some();
+        };
+        let Some(value) = value else {
+            return *operator == Operator::NotEqual;
+        };
+
+        let numbers = value.as_f64().zip(expected.parse::<f64>().ok());
+        let text = value.text();
+        let ordering = match numbers {
+            Some((a, b)) => a.partial_cmp(&b),
+            None => Some(text.as_str().cmp(expected.as_str())),
+        };
+        match operator {
+            Operator::Equal => ordering == Some(Ordering::Equal),
+            Operator::NotEqual => ordering != Some(Ordering::Equal),
+            Operator::Less => ordering == Some(Ordering::Less),
+            Operator::LessOrEqual => ordering.is_some_and(|o| o != Ordering::Greater),
+            Operator::Greater => ordering == Some(Ordering::Greater),
+            Operator::GreaterOrEqual => ordering.is_some_and(|o| o != Ordering::Less),
+            Operator::StartsWith => text.starts_with(expected.as_str()),
+            Operator::EndsWith => text.ends_with(expected.as_str()),
+            Operator::Contains => text.to_lowercase().contains(&expected.to_lowercase()),
+        }
+    }
+}
+
+impl Key {
+    fn value<'a>(&self, nodes: &'a NodeStore, node: &'a Node) -> Option<Value<'a>> {
+        match self {
+            Key::Name => node.get_name().map(Value::Text),
+            Key::Type => node
+                .type_id
+                .and_then(|id| nodes.type_names.get(id))
+                .map(Value::Text),
+            Key::Attribute(name) => nodes
+                .attribute_names
+                .get_index(name)
+                .and_then(|id| node.get_attribute(id))
+                .map(Value::Attribute),
+        }
+    }
+}
+
+impl Value<'_> {
+    fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Text(_) => None,
+            Value::Attribute(value) => value.as_f64(),
+        }
+    }
+
+    fn text(&self) -> String {
+        match self {
+            Value::Text(text) => text.to_string(),
+            Value::Attribute(value) => value.to_string(),
+        }
+    }
+}
+
+fn compare_values(a: &Value, b: &Value) -> Ordering {
+    match (a.as_f64(), b.as_f64()) {
+        (Some(a), Some(b)) => a.total_cmp(&b),
+        _ => a.text().cmp(&b.text()),
+    }
+}
+
+/// The node with the id, unless it is the root or does not exist
+fn selectable(nodes: &NodeStore, id: NodeId) -> Option<&Node> {
+    if id == NodeId::ROOT_NODE || !nodes.exists(id) {
+        return None;
+    }
+    nodes.get(id)
+}
+
+/// All nodes below the root, parents before their children
+fn tree_order(nodes: &NodeStore) -> Vec<NodeId> {
+    let mut order = vec![];
+    let mut stack = vec![NodeId::ROOT_NODE];
+    while let Some(id) = stack.pop() {
+        if let Some(node) = nodes.get(id) {
+            if id != NodeId::ROOT_NODE {
+                order.push(id);
+            }
+            stack.extend(node.children.iter().rev());
+        }
+    }
+    order
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    /// Byte offset of the next character
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn query(&mut self) -> Result<Query, QueryError> {
+        self.skip_whitespace();
+        let mut steps = vec![(self.step()?, false)];
+        let mut order = vec![];
+        loop {
+            let whitespace = self.skip_whitespace();
+            if self.peek().is_none() {
+                break;
+            }
+            if whitespace && self.keywords(&["order", "by"]) {
+                order = self.order()?;
+                break;
+            }
+            let child = self.eat('>');
+            if !child && !whitespace {
+                return Err(self.unexpected("a selector"));
+            }
+            self.skip_whitespace();
+            steps.push((self.step()?, child));
+        }
+        if self.peek().is_some() {
+            return Err(self.unexpected("the end of the query"));
+        }
+        Ok(Query { steps, order })
+    }
+
+    fn step(&mut self) -> Result<Step, QueryError> {
+        let mut step = Step::default();
+        let any = self.eat('*');
+        if !any && self.peek().is_some_and(is_word_char) {
+            step.type_name = Some(self.word());
+        }
+        loop {
+            if self.eat('#') {
+                step.name = Some(self.text("a name")?);
+            } else if self.eat('.') {
+                step.tags.push(self.text("a tag")?);
+            } else if self.eat('[') {
+                step.filters.push(self.filter()?);
+            } else {
+                break;
+            }
+        }
+        if step.is_empty() && !any {
+            return Err(self.unexpected("a selector"));
+        }
+        Ok(step)
+    }
+
+    /// A filter after its opening bracket
+    fn filter(&mut self) -> Result<Filter, QueryError> {
+        self.skip_whitespace();
+        let key = self.key()?;
+        self.skip_whitespace();
+        let comparison = if self.eat(']') {
+            None
+        } else {
+            let operator = self.operator()?;
+            self.skip_whitespace();
+            let value = self.text("a value")?;
+            self.skip_whitespace();
+            if !self.eat(']') {
+                return Err(self.unexpected("]"));
+            }
+            Some((operator, value))
+        };
+        Ok(Filter { key, comparison })
+    }
+
+    fn key(&mut self) -> Result<Key, QueryError> {
+        let start = self.position;
+        if self.eat('@') {
+            return match self.word().as_str() {
+                "name" => Ok(Key::Name),
+                "type" => Ok(Key::Type),
+                _ => Err(QueryError::Unexpected {
+                    position: start,
+                    expected: "@name or @type",
+                }),
+            };
+        }
+        Ok(Key::Attribute(self.text("an attribute")?))
+    }
+
+    fn operator(&mut self) -> Result<Operator, QueryError> {
+        let operators = [
+            ("!=", Operator::NotEqual),
+            ("<=", Operator::LessOrEqual),
+            (">=", Operator::GreaterOrEqual),
+            ("^=", Operator::StartsWith),
+            ("$=", Operator::EndsWith),
+            ("~=", Operator::Contains),
+            ("=", Operator::Equal),
+            ("<", Operator::Less),
+            (">", Operator::Greater),
+        ];
+        for (symbol, operator) in operators {
+            if self.rest().starts_with(symbol) {
+                self.position += symbol.len();
+                return Ok(operator);
+            }
+        }
+        Err(self.unexpected("an operator"))
+    }
+
+    fn order(&mut self) -> Result<Vec<(Key, bool)>, QueryError> {
+        let mut order = vec![];
+        loop {
+            self.skip_whitespace();
+            let key = self.key()?;
+            let whitespace = self.skip_whitespace();
+            let descending = whitespace && self.keywords(&["desc"]);
+            if !descending && whitespace {
+                self.keywords(&["asc"]);
+            }
+            order.push((key, descending));
+            self.skip_whitespace();
+            if !self.eat(',') {
+                return Ok(order);
+            }
+        }
+    }
+
+    /// A word or a quoted string
+    fn text(&mut self, expected: &'static str) -> Result<String, QueryError> {
+        if !self.eat('"') {
+            let word = self.word();
+            if word.is_empty() {
+                return Err(self.unexpected(expected));
+            }
+            return Ok(word);
+        }
+        let start = self.position - 1;
+        let mut text = String::new();
+        let mut chars = self.rest().char_indices();
+        while let Some((offset, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.position += offset + 1;
+                    return Ok(text);
+                }
+                '\\' => match chars.next() {
+                    Some((_, escaped)) => text.push(escaped),
+                    None => break,
+                },
+                _ => text.push(c),
+            }
+        }
+        Err(QueryError::UnterminatedString { position: start })
+    }
+
+    fn word(&mut self) -> String {
+        let length = self
+            .rest()
+            .find(|c| !is_word_char(c))
+            .unwrap_or(self.rest().len());
+        let word = self.rest()[..length].to_string();
+        self.position += length;
+        word
+    }
+
+    /// Skip the keywords if they come next, separated by whitespace
+    fn keywords(&mut self, keywords: &[&str]) -> bool {
+        let start = self.position;
+        for (i, keyword) in keywords.iter().enumerate() {
+            if i > 0 && !self.skip_whitespace() {
+                break;
+            }
+            let at_keyword = self.rest().starts_with(keyword)
+                && !self.rest()[keyword.len()..].starts_with(is_word_char);
+            if !at_keyword {
+                break;
+            }
+            self.position += keyword.len();
+            if i == keywords.len() - 1 {
+                return true;
+            }
+        }
+        self.position = start;
+        false
+    }
+
+    /// True if there was whitespace
+    fn skip_whitespace(&mut self) -> bool {
+        let length = self
+            .rest()
+            .find(|c: char| !c.is_whitespace())
+            .unwrap_or(self.rest().len());
+        self.position += length;
+        length > 0
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.position += c.len_utf8();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn rest(&self) -> &str {
+        &self.text[self.position..]
+    }
+
+    fn unexpected(&self, expected: &'static str) -> QueryError {
+        QueryError::Unexpected {
+            position: self.position,
+            expected,
+        }
+    }
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '-'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let query =
+            Query::parse(r#"issue[status="open"] > comment.urgent order by @name desc, time"#)
+                .unwrap();
+        assert_eq!(query.steps.len(), 2);
+        assert!(query.steps[1].1);
+        assert_eq!(query.steps[1].0.tags, vec!["urgent".to_string()]);
+        assert_eq!(
+            query.order,
+            vec![
+                (Key::Name, true),
+                (Key::Attribute("time".to_string()), false)
+            ]
+        );
+
+        // A type named order is not the start of an order clause
+        let query = Query::parse("project order").unwrap();
+        assert_eq!(query.steps[1].0.type_name.as_deref(), Some("order"));
+        assert!(query.order.is_empty());
+
+        let query = Query::parse(r#"*#"a \"b\"" [@type^=is]"#).unwrap();
+        assert_eq!(query.steps[0].0.name.as_deref(), Some(r#"a "b""#));
+        assert_eq!(query.steps[1].0.filters[0].key, Key::Type);
+        assert_eq!(quote(r#"a "b""#), r#""a \"b\"""#);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |text: &str| Query::parse(text).unwrap_err();
+        assert_eq!(
+            error(""),
+            QueryError::Unexpected {
+                position: 0,
+                expected: "a selector"
+            }
+        );
+        assert_eq!(
+            error("issue[status"),
+            QueryError::Unexpected {
+                position: 12,
+                expected: "an operator"
+            }
+        );
+        assert_eq!(
+            error("issue >"),
+            QueryError::Unexpected {
+                position: 7,
+                expected: "a selector"
+            }
+        );
+        assert_eq!(
+            error(r#"#"open"#),
+            QueryError::UnterminatedString { position: 1 }
+        );
+        assert_eq!(
+            error("a[@size]"),
+            QueryError::Unexpected {
+                position: 2,
+                expected: "@name or @type"
+            }
+        );
+    }
+}
@@ -1,8 +1,14 @@
+use crate::attributes::{F16, I24, U24};
+use crate::error::InvalidLength;
 use crate::node_id::NodeId;
 use blake3::Hash;
+use chrono::{DateTime, Utc};
 use std::io::{self, Error, ErrorKind, Read, Write};
 use uuid::Uuid;
 
+/// Largest number of elements allocated up front when reading a length prefixed value
+pub(crate) const MAX_PREALLOCATION: usize = 4096;
+
 /// Extend `Write` with additional methods for writing primitive types.
 pub trait WriteExt: Write {
     fn write_byte(&mut self, byte: u8) -> io::Result<()> {
@@ -25,6 +31,14 @@ pub trait WriteExt: Write {
         self.write_all(&value.to_be_bytes())
     }
 
+    fn write_u24(&mut self, value: U24) -> io::Result<()> {
+        self.write_all(&value.to_be_bytes())
+    }
+
+    fn write_i24(&mut self, value: I24) -> io::Result<()> {
+        self.write_all(&value.to_be_bytes())
+    }
+
     fn write_u32(&mut self, value: u32) -> io::Result<()> {
         self.write_all(&value.to_be_bytes())
     }
@@ -41,6 +55,10 @@ pub trait WriteExt: Write {
         self.write_all(&value.to_be_bytes())
     }
 
+    fn write_f16(&mut self, value: F16) -> io::Result<()> {
+        self.write_u16(value.to_bits())
+    }
+
     fn write_f32(&mut self, value: f32) -> io::Result<()> {
         self.write_all(&value.to_be_bytes())
     }
@@ -183,20 +201,81 @@ pub trait WriteExt: Write {
         self.write_all(value.as_bytes())
     }
 
-    fn write_uuid_array(&mut self, value: &Vec<Uuid>) -> io::Result<()> {
-        self.write_length(value.len())?;
-        for id in value {
-            self.write_uuid(id)?;
+    /// Write the number of items followed by each item
+    fn write_array_with<T>(
+        &mut self,
+        items: &[T],
+        mut write: impl FnMut(&mut Self, &T) -> io::Result<()>,
+    ) -> io::Result<()> {
+        self.write_length(items.len())?;
+        for item in items {
+            write(self, item)?;
         }
         Ok(())
     }
 
-    fn write_string_array(&mut self, value: &Vec<String>) -> io::Result<()> {
-        self.write_length(value.len())?;
-        for s in value {
-            self.write_string(s)?;
-        }
-        Ok(())
+    fn write_uuid_array(&mut self, value: &[Uuid]) -> io::Result<()> {
+        self.write_array_with(value, |w, v| w.write_uuid(v))
+    }
+
+    fn write_string_array(&mut self, value: &[String]) -> io::Result<()> {
+        self.write_array_with(value, |w, v| w.write_string(v))
+    }
+
+    fn write_bool_array(&mut self, value: &[bool]) -> io::Result<()> {
+        self.write_array_with(value, |w, v| w.write_bool(*v))
+    }
+
+    fn write_u8_array(&mut self, value: &[u8]) -> io::Result<()> {
+        self.write_array_with(value, |w, v| w.write_u8(*v))
+    }
+
+    fn write_u16_array(&mut self, value: &[u16]) -> io::Result<()> {
+        self.write_array_with(value, |w, v| w.write_u16(*v))
+    }
+
+    fn write_u24_array(&mut self, value: &[U24]) -> io::Result<()> {
+        self.write_array_with(value, |w, v| w.write_u24(*v))
+    }
+
+    fn write_u32_array(&mut self, value: &[u32]) -> io::Result<()> {
+        self.write_array_with(value, |w, v| w.write_u32(*v))
+    }
+
+    fn write_u64_array(&mut self, value: &[u64]) -> io::Result<()> {
+        self.write_array_with(value, |w, v| w.write_u64(*v))
+    }
+
+    fn write_i8_array(&mut self, value: &[i8]) -> io::Result<()> {
+        self.write_array_with(value, |w, v| w.write_i8(*v))
+    }
+
+    fn write_i16_array(&mut self, value: &[i16]) -> io::Result<()> {
+        self.write_array_with(value, |w, v| w.write_i16(*v))
+    }
+
+    fn write_i24_array(&mut self, value: &[I24]) -> io::Result<()> {
+        self.write_array_with(value, |w, v| w.write_i24(*v))
+    }
+
+    fn write_i32_array(&mut self, value: &[i32]) -> io::Result<()> {
+        self.write_array_with(value, |w, v| w.write_i32(*v))
+    }
+
+    fn write_i64_array(&mut self, value: &[i64]) -> io::Result<()> {
+        self.write_array_with(value, |w, v| w.write_i64(*v))
+    }
+
+    fn write_f16_array(&mut self, value: &[F16]) -> io::Result<()> {
+        self.write_array_with(value, |w, v| w.write_f16(*v))
+    }
+
+    fn write_f32_array(&mut self, value: &[f32]) -> io::Result<()> {
+        self.write_array_with(value, |w, v| w.write_f32(*v))
+    }
+
+    fn write_f64_array(&mut self, value: &[f64]) -> io::Result<()> {
+        self.write_array_with(value, |w, v| w.write_f64(*v))
     }
 
     fn write_hash(&mut self, value: &Hash) -> io::Result<()> {
@@ -208,6 +287,11 @@ pub trait WriteExt: Write {
         self.write_length(value.len())?;
         self.write_all(value)
     }
+
+    /// Write a point in time as milliseconds since the Unix epoch
+    fn write_timestamp(&mut self, value: &DateTime<Utc>) -> io::Result<()> {
+        self.write_i64(value.timestamp_millis())
+    }
 }
 
 /// Implement `WriteExt` for all types that implement `Write`.
@@ -245,6 +329,18 @@ pub trait ReadExt: Read {
         Ok(i16::from_be_bytes(buf))
     }
 
+    fn read_u24(&mut self) -> io::Result<U24> {
+        let mut buf = [0; 3];
+        self.read_exact(&mut buf)?;
+        Ok(U24::from_be_bytes(buf))
+    }
+
+    fn read_i24(&mut self) -> io::Result<I24> {
+        let mut buf = [0; 3];
+        self.read_exact(&mut buf)?;
+        Ok(I24::from_be_bytes(buf))
+    }
+
     fn read_u32(&mut self) -> io::Result<u32> {
         let mut buf = [0; 4];
         self.read_exact(&mut buf)?;
@@ -269,6 +365,10 @@ pub trait ReadExt: Read {
         Ok(i64::from_be_bytes(buf))
     }
 
+    fn read_f16(&mut self) -> io::Result<F16> {
+        Ok(F16::from_bits(self.read_u16()?))
+    }
+
     fn read_f32(&mut self) -> io::Result<f32> {
         let mut buf = [0; 4];
         self.read_exact(&mut buf)?;
@@ -442,8 +542,7 @@ pub trait ReadExt: Read {
 
     fn read_string(&mut self) -> io::Result<String> {
         let length = self.read_length()? as usize;
-        let mut buf = vec![0; length];
-        self.read_exact(&mut buf)?;
+        let buf = self.read_vec(length)?;
         String::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
     }
 
@@ -457,22 +556,82 @@ pub trait ReadExt: Read {
         Uuid::from_slice(&buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
     }
 
-    fn read_uuid_array(&mut self) -> io::Result<Vec<Uuid>> {
-        let length = self.read_length()? as usize;
-        let mut uuids = Vec::with_capacity(length);
+    /// Read a number of items followed by the items. Little is allocated up front, as the number
+    /// may be damaged.
+    fn read_array_with<T>(
+        &mut self,
+        mut read: impl FnMut(&mut Self) -> io::Result<T>,
+    ) -> io::Result<Vec<T>> {
+        let length = self.read_length()?;
+        let mut items = Vec::with_capacity(length.min(MAX_PREALLOCATION));
         for _ in 0..length {
-            uuids.push(self.read_uuid()?);
+            items.push(read(self)?);
         }
-        Ok(uuids)
+        Ok(items)
+    }
+
+    fn read_uuid_array(&mut self) -> io::Result<Vec<Uuid>> {
+        self.read_array_with(|r| r.read_uuid())
     }
 
     fn read_string_array(&mut self) -> io::Result<Vec<String>> {
-        let length = self.read_length()? as usize;
-        let mut strings = Vec::with_capacity(length);
-        for _ in 0..length {
-            strings.push(self.read_string()?);
-        }
-        Ok(strings)
+        self.read_array_with(|r| r.read_string())
+    }
+
+    fn read_bool_array(&mut self) -> io::Result<Vec<bool>> {
+        self.read_array_with(|r| r.read_bool())
+    }
+
+    fn read_u8_array(&mut self) -> io::Result<Vec<u8>> {
+        self.read_array_with(|r| r.read_u8())
+    }
+
+    fn read_u16_array(&mut self) -> io::Result<Vec<u16>> {
+        self.read_array_with(|r| r.read_u16())
+    }
+
+    fn read_u24_array(&mut self) -> io::Result<Vec<U24>> {
+        self.read_array_with(|r| r.read_u24())
+    }
+
+    fn read_u32_array(&mut self) -> io::Result<Vec<u32>> {
+        self.read_array_with(|r| r.read_u32())
+    }
+
+    fn read_u64_array(&mut self) -> io::Result<Vec<u64>> {
+        self.read_array_with(|r| r.read_u64())
+    }
+
+    fn read_i8_array(&mut self) -> io::Result<Vec<i8>> {
+        self.read_array_with(|r| r.read_i8())
+    }
+
+    fn read_i16_array(&mut self) -> io::Result<Vec<i16>> {
+        self.read_array_with(|r| r.read_i16())
+    }
+
+    fn read_i24_array(&mut self) -> io::Result<Vec<I24>> {
+        self.read_array_with(|r| r.read_i24())
+    }
+
+    fn read_i32_array(&mut self) -> io::Result<Vec<i32>> {
+        self.read_array_with(|r| r.read_i32())
+    }
+
+    fn read_i64_array(&mut self) -> io::Result<Vec<i64>> {
+        self.read_array_with(|r| r.read_i64())
+    }
+
+    fn read_f16_array(&mut self) -> io::Result<Vec<F16>> {
+        self.read_array_with(|r| r.read_f16())
+    }
+
+    fn read_f32_array(&mut self) -> io::Result<Vec<f32>> {
+        self.read_array_with(|r| r.read_f32())
+    }
+
+    fn read_f64_array(&mut self) -> io::Result<Vec<f64>> {
+        self.read_array_with(|r| r.read_f64())
     }
 
     fn read_hash(&mut self) -> io::Result<Hash> {
@@ -483,8 +642,25 @@ pub trait ReadExt: Read {
 
     fn read_bytes(&mut self) -> io::Result<Vec<u8>> {
         let length = self.read_length()? as usize;
-        let mut buf = vec![0; length];
-        self.read_exact(&mut buf)?;
+        self.read_vec(length)
+    }
+
+    fn read_timestamp(&mut self) -> io::Result<DateTime<Utc>> {
+        let millis = self.read_i64()?;
+        DateTime::from_timestamp_millis(millis)
+            .ok_or_else(|| Error::new(ErrorKind::InvalidData, "Timestamp out of range"))
+    }
+
+    /// Read `length` bytes. The length is not trusted for the allocation, as it may come from damaged data.
+    fn read_vec(&mut self, length: usize) -> io::Result<Vec<u8>> {
+        let mut buf = Vec::with_capacity(length.min(MAX_PREALLOCATION));
+        Read::take(&mut *self, length as u64).read_to_end(&mut buf)?;
+        if buf.len() < length {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                InvalidLength { length },
+            ));
+        }
         Ok(buf)
     }
 }
@@ -492,6 +668,64 @@ pub trait ReadExt: Read {
 /// Implement `ReadExt` for all types that implement `Read`.
 impl<T: Read> ReadExt for T {}
 
+/// Reader that keeps track of its position and can tell whether the data has ended
+pub(crate) struct OffsetReader<R> {
+    inner: R,
+    position: u64,
+    peeked: Option<u8>,
+}
+
+impl<R: Read> OffsetReader<R> {
+    /// `position` is the offset of the first byte of `inner` in the file
+    pub fn new(inner: R, position: u64) -> OffsetReader<R> {
+        OffsetReader {
+            inner,
+            position,
+            peeked: None,
+        }
+    }
+
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// True if there is no more data, without consuming anything
+    pub fn is_at_end(&mut self) -> io::Result<bool> {
+        if self.peeked.is_some() {
+            return Ok(false);
+        }
+        let mut buf = [0; 1];
+        loop {
+            match self.inner.read(&mut buf) {
+                Ok(0) => return Ok(true),
+                Ok(_) => {
+                    self.peeked = Some(buf[0]);
+                    return Ok(false);
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl<R: Read> Read for OffsetReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let count = match self.peeked.take() {
+            Some(byte) => {
+                buf[0] = byte;
+                1
+            }
+            None => self.inner.read(buf)?,
+        };
+        self.position += count as u64;
+        Ok(count)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,249 @@
+use crate::checksum::ChecksumStatus;
+use crate::journal::Journal;
+use crate::operation::{Operation, OperationIds};
+use crate::readwrite::ReadExt;
+use std::ops::Range;
+
+/// Result of reading a damaged journal
+#[derive(Default)]
+pub struct Recovery {
+    /// All operations that could be read back, in order
+    pub journal: Journal,
+    /// Byte ranges of the file that could not be parsed and were skipped
+    pub skipped: Vec<Range<u64>>,
+    /// Byte ranges whose checksum did not match. Their operations are kept, but may contain damaged values.
+    pub corrupted: Vec<Range<u64>>,
+    /// Byte ranges that were replaced with the verified content of another copy
+    pub repaired: Vec<Range<u64>>,
+}
+
+impl Recovery {
+    /// True if everything was read, or all damage was repaired from another copy
+    pub fn is_complete(&self) -> bool {
+        self.skipped.iter().chain(self.corrupted.iter()).all(|r| {
+            self.repaired
+                .iter()
+                .any(|x| x.start <= r.start && r.end <= x.end)
+        })
+    }
+}
+
+/// Operations between two checksums
+struct Segment {
+    bytes: Range<u64>,
+    operations: Vec<Operation>,
+    /// Hash of the checksum that ends this segment, if there is one
+    hash: Option<Vec<u8>>,
+    skipped: Vec<Range<u64>>,
+    status: ChecksumStatus,
+}
+
+/// Read as much as possible from a possibly damaged binc file.
+///
+/// When an operation cannot be parsed the reader scans forward for the next checksum marker
+/// or plausible operation header and continues from there.
+pub(crate) fn recover(data: &[u8]) -> Recovery {
+    assemble(read_segments(data), None)
+}
+
+/// Like `recover`, but damaged ranges are replaced with the same ranges from a second copy of the
+/// journal, if they pass checksum verification there
+pub(crate) fn recover_with_copy(data: &[u8], copy: &[u8]) -> Recovery {
+    assemble(read_segments(data), Some(read_segments(copy)))
+}
+
+fn assemble(segments: Vec<Segment>, copy: Option<Vec<Segment>>) -> Recovery {
+    let mut recovery = Recovery::default();
+    // Index of the copy segment matching the last good anchor in the primary
+    let mut copy_position = 0;
+
+    for segment in &segments {
+        recovery.skipped.extend(segment.skipped.iter().cloned());
+        if segment.status == ChecksumStatus::Corrupted {
+            recovery.corrupted.push(segment.bytes.clone());
+        }
+
+        let damaged = !segment.skipped.is_empty() || segment.status == ChecksumStatus::Corrupted;
+        let replacement = match &copy {
+            Some(copy) if damaged => find_replacement(segment, copy, copy_position),
+            _ => None,
+        };
+
+        if let Some(range) = replacement {
+            let copy = copy.as_ref().expect("Copy must exist");
+            for s in &copy[range.clone()] {
+                recovery
+                    .journal
+                    .operations
+                    .extend(s.operations.iter().cloned());
+            }
+            recovery.repaired.push(segment.bytes.clone());
+            copy_position = range.end;
+        } else {
+            recovery
+                .journal
+                .operations
+                .extend(segment.operations.iter().cloned());
+            // Keep the copy aligned with the last intact checksum
+            let anchor = match (&copy, &segment.hash) {
+                (Some(copy), Some(hash)) => copy[copy_position..]
+                    .iter()
+                    .position(|s| s.hash.as_ref() == Some(hash)),
+                _ => None,
+            };
+            if let Some(i) = anchor {
+                copy_position += i + 1;
+            }
+        }
+    }
+
+    recovery
+}
+
+/// Find the segments of the copy that cover the same operations as a damaged segment
+fn find_replacement(segment: &Segment, copy: &[Segment], from: usize) -> Option<Range<usize>> {
+    let candidates = &copy[from.min(copy.len())..];
+    let end = match &segment.hash {
+        // The checksum ending the damaged segment is intact, so the copy must have the same one
+        Some(hash) => {
+            from + candidates
+                .iter()
+                .position(|s| s.hash.as_ref() == Some(hash))?
+                + 1
+        }
+        // The damaged segment runs to the end of the file
+        None => copy.len(),
+    };
+
+    let replacement = &copy[from..end];
+    let usable = !replacement.is_empty()
+        && replacement.iter().all(|s| {
+            s.skipped.is_empty()
+                && (s.status == ChecksumStatus::Valid
+                    || (segment.hash.is_none() && s.status == ChecksumStatus::Unchecked))
+        });
+
+    if usable {
+        Some(from..end)
+    } else {
+        None
+    }
+}
+
+fn read_segments(data: &[u8]) -> Vec<Segment> {
+    let mut segments = vec![];
+    let mut position = Journal::HEADER_SIZE as usize;
+    let mut segment = new_segment(position);
+
+    let header_valid = data.len() >= position && {
+        let mut header = &data[..position];
+        header.read_u32().ok() == Some(Journal::CONTAINER_ID)
+            && header.read_u32().ok() == Some(Journal::CONTAINER_VERSION)
+    };
+    if !header_valid {
+        segment.bytes.start = 0;
+        segment.skipped.push(0..position.min(data.len()) as u64);
+    }
+
+    // Bytes of the current segment that were actually parsed, to hash against its checksum
+    let mut hasher = blake3::Hasher::new();
+
+    while position < data.len() {
+        let (operation, end) = match read_operation(data, position) {
+            Some(x) => x,
+            None => {
+                let next = resynchronize(data, position + 1);
+                segment.skipped.push(position as u64..next as u64);
+                position = next;
+                continue;
+            }
+        };
+
+        if let Operation::Checksum { data: hash } = &operation {
+            let matches = segment.skipped.is_empty()
+                && hasher.finalize().as_bytes().as_slice() == hash.as_slice();
+            segment.status = if matches {
+                ChecksumStatus::Valid
+            } else {
+                ChecksumStatus::Corrupted
+            };
+            segment.hash = Some(hash.clone());
+            segment.bytes.end = end as u64;
+            segment.operations.push(operation);
+            segments.push(segment);
+
+            segment = new_segment(end);
+            hasher = blake3::Hasher::new();
+        } else {
+            hasher.update(&data[position..end]);
+            segment.operations.push(operation);
+        }
+        position = end;
+    }
+
+    if !segment.operations.is_empty() || !segment.skipped.is_empty() {
+        segment.bytes.end = data.len() as u64;
+        segments.push(segment);
+    }
+
+    segments
+}
+
+fn new_segment(start: usize) -> Segment {
+    Segment {
+        bytes: start as u64..start as u64,
+        operations: vec![],
+        hash: None,
+        skipped: vec![],
+        status: ChecksumStatus::Unchecked,
+    }
+}
+
+/// Read one operation at `position`, requiring that its content matches the size in its header
+fn read_operation(data: &[u8], position: usize) -> Option<(Operation, usize)> {
+    let mut header = &data[position..];
+    header.read_length_flipped().ok()?;
+    let size = header.read_length().ok()?;
+    let content_start = data.len() - header.len();
+    let end = content_start
+        .checked_add(size)
+        .filter(|end| *end <= data.len())?;
+
+    let mut cursor = &data[position..end];
+    let operation = Operation::read(&mut cursor).ok()?;
+    if cursor.is_empty() {
+        Some((operation, end))
+    } else {
+        None
+    }
+}
+
+/// Find the first position at or after `from` where the data looks like the start of an operation
+fn resynchronize(data: &[u8], from: usize) -> usize {
+    (from..data.len())
+        .find(|p| is_checksum_marker(data, *p) || is_plausible_operation(data, *p))
+        .unwrap_or(data.len())
+}
+
+fn is_checksum_marker(data: &[u8], position: usize) -> bool {
+    matches!(
+        read_operation(data, position),
+        Some((Operation::Checksum { .. }, _))
+    )
+}
+
+/// A known operation that parses exactly, followed by another one that does too (or the end of the file)
+fn is_plausible_operation(data: &[u8], position: usize) -> bool {
+    let known = (&data[position..])
+        .read_length_flipped()
+        .is_ok_and(|id| OperationIds::is_known(id as u64));
+    if !known {
+        return false;
+    }
+
+    match read_operation(data, position) {
+        Some((_, end)) if end == data.len() => true,
+        Some((_, end)) => read_operation(data, end).is_some(),
+        None => false,
+    }
+}
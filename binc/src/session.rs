@@ -0,0 +1,55 @@
+use crate::operation::Operation;
+use crate::util::current_time;
+use chrono::{DateTime, Utc};
+use std::fmt::{Display, Formatter};
+use uuid::Uuid;
+
+/// Who made a sequence of operations, and when
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    pub author: String,
+    /// When the session started
+    pub time: DateTime<Utc>,
+    /// Id of the program instance the operations were made with
+    pub client: Uuid,
+}
+
+impl Session {
+    /// Start a session now, with a new client id
+    pub fn new(author: &str) -> Session {
+        Session {
+            author: author.to_string(),
+            time: current_time(),
+            client: Uuid::new_v4(),
+        }
+    }
+
+    pub fn from_operation(operation: &Operation) -> Option<Session> {
+        match operation {
+            Operation::Session {
+                author,
+                time,
+                client,
+            } => Some(Session {
+                author: author.clone(),
+                time: *time,
+                client: *client,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn to_operation(&self) -> Operation {
+        Operation::Session {
+            author: self.author.clone(),
+            time: self.time,
+            client: self.client,
+        }
+    }
+}
+
+impl Display for Session {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self.author, self.time.to_rfc3339())
+    }
+}
@@ -0,0 +1,68 @@
+use crate::util::current_time;
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+/// Where imported content came from
+#[derive(Debug, Clone, PartialEq)]
+pub struct Source {
+    pub uri: String,
+    /// Blake3 hash of the imported content
+    pub hash: Vec<u8>,
+    /// When the content was imported
+    pub time: DateTime<Utc>,
+}
+
+impl Source {
+    /// Describe content that is imported now
+    pub fn from_content(uri: &str, content: &[u8]) -> Source {
+        Source {
+            uri: uri.to_string(),
+            hash: blake3::hash(content).as_bytes().to_vec(),
+            time: current_time(),
+        }
+    }
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self.uri, self.time.to_rfc3339())
+    }
+}
+
+/// All sources of a document, by id
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Sources {
+    sources: BTreeMap<usize, Source>,
+}
+
+impl Sources {
+    pub fn get(&self, id: usize) -> Option<&Source> {
+        self.sources.get(&id)
+    }
+
+    /// Id that is not used by any source
+    pub fn next_id(&self) -> usize {
+        self.sources.keys().next_back().map_or(0, |id| id + 1)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &Source)> {
+        self.sources.iter().map(|(id, source)| (*id, source))
+    }
+
+    pub fn len(&self) -> usize {
+        self.sources.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+
+    pub(crate) fn insert(&mut self, id: usize, source: Source) {
+        self.sources.insert(id, source);
+    }
+
+    pub(crate) fn remove(&mut self, id: usize) {
+        self.sources.remove(&id);
+    }
+}
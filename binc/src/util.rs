@@ -0,0 +1,16 @@
+use chrono::{DateTime, NaiveDate, Utc};
+
+/// The current time, with the millisecond precision that timestamps are stored with
+pub fn current_time() -> DateTime<Utc> {
+    let now = Utc::now().timestamp_millis();
+    DateTime::from_timestamp_millis(now).expect("Current time should be in range")
+}
+
+/// Parse an RFC 3339 time, or a date (YYYY-MM-DD) meaning the start of that day in UTC
+pub fn parse_time(text: &str) -> Option<DateTime<Utc>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Some(time.with_timezone(&Utc));
+    }
+    let date = NaiveDate::parse_from_str(text, "%Y-%m-%d").ok()?;
+    Some(date.and_hms_opt(0, 0, 0)?.and_utc())
+}
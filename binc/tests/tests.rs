@@ -6,9 +6,22 @@ mod tests {
 
     use binc::journal::*;
 
+    use binc::attributes::{AttributeValue, F16, I24, U24};
+    use binc::builder::NodeBuilder;
     use binc::changes::Changes;
+    use binc::checksum::ChecksumStatus;
+    use binc::diff;
+    use binc::diff::Difference;
     use binc::document::*;
+    use binc::error::{ApplyError, BincError, NameKind};
+    use binc::merge::{merge, Conflict, Field};
     use binc::node_id::{NodeId, NodeIdGenerator};
+    use binc::operation::Operation;
+    use binc::query::{quote, Query};
+    use binc::readwrite::WriteExt;
+    use binc::session::Session;
+    use binc::sources::Source;
+    use binc::util::parse_time;
 
     #[test]
     fn test_create_example_document() {
@@ -54,6 +67,992 @@ mod tests {
         assert_eq!(doc.find_roots().len(), 1)
     }
 
+    #[test]
+    fn save_and_load_array_attributes() {
+        let id = NodeId::new(1);
+        let mut changes = Changes::new();
+        changes.add_node(id, NodeId::ROOT_NODE, 0);
+        changes.set_string_s(id, "name", "buffer");
+        changes.set_f32_array(id, 0, &[0.25, -0.5, 1.0]);
+        changes.set_string_array(id, 0, &["a".to_string(), "b".to_string()]);
+        changes.set_i64_array(id, 0, &[i64::MIN, 0, i64::MAX]);
+        changes.set_bool_array(id, 0, &[true, false]);
+        let repo = Journal::from(changes);
+
+        let mut buf = Vec::<u8>::new();
+        repo.write(&mut buf).unwrap();
+        let repo2 = Journal::read(&mut Cursor::new(buf)).unwrap();
+        assert_eq!(repo.operations.len(), repo2.operations.len());
+        for (a, b) in repo.operations.iter().zip(repo2.operations.iter()) {
+            assert_eq!(a.to_string(), b.to_string());
+        }
+
+        let doc = Document::new(repo2);
+        let node = doc.nodes.get(id).unwrap();
+        assert_eq!(node.get_bool_array_attribute(0), Some(&[true, false][..]));
+    }
+
+    fn round_trip_attribute(value: AttributeValue) {
+        let id = NodeId::new(1);
+        let mut changes = Changes::new();
+        changes.add_node(id, NodeId::ROOT_NODE, 0);
+        changes.set_attribute_s(id, "value", value.clone());
+        let repo = Journal::from(changes);
+
+        let mut buf = Vec::<u8>::new();
+        repo.write(&mut buf).unwrap();
+        let repo2 = Journal::read(&mut Cursor::new(buf)).unwrap();
+        assert_eq!(repo.operations.len(), repo2.operations.len());
+
+        let doc = Document::new(repo2);
+        let read_back = doc.nodes.get(id).unwrap().get_attribute(0);
+        assert_eq!(read_back, Some(&value), "{} did not round-trip", value);
+    }
+
+    #[test]
+    fn save_and_load_scalar_attributes() {
+        let values = [
+            AttributeValue::String("text".to_string()),
+            AttributeValue::Bool(true),
+            AttributeValue::Uuid(uuid::Uuid::new_v4()),
+            AttributeValue::U8(200),
+            AttributeValue::U16(60000),
+            AttributeValue::U24(U24::try_from(0xAB_CDEFu32).unwrap()),
+            AttributeValue::U32(4_000_000_000),
+            AttributeValue::U64(u64::MAX),
+            AttributeValue::I8(-100),
+            AttributeValue::I16(-30000),
+            AttributeValue::I24(I24::try_from(-0x7F_0102).unwrap()),
+            AttributeValue::I32(i32::MIN),
+            AttributeValue::I64(i64::MIN),
+            AttributeValue::F16(F16::from_f32(-1.5)),
+            AttributeValue::F32(3.25),
+            AttributeValue::F64(std::f64::consts::E),
+        ];
+        for value in values {
+            round_trip_attribute(value);
+        }
+    }
+
+    #[test]
+    fn save_and_load_narrow_arrays() {
+        round_trip_attribute(AttributeValue::U24Array(vec![U24::MIN, U24::MAX]));
+        round_trip_attribute(AttributeValue::I24Array(vec![I24::MIN, I24::MAX]));
+        round_trip_attribute(AttributeValue::F16Array(vec![
+            F16::from_f32(0.5),
+            F16::from_f32(-65504.0),
+        ]));
+
+        // The typed setters and getters
+        let id = NodeId::new(1);
+        let halves = [F16::from_f32(0.5)];
+        let mut changes = Changes::new();
+        changes
+            .add_node(id, NodeId::ROOT_NODE, 0)
+            .set_string_s(id, "u24", "")
+            .set_string_s(id, "i24", "")
+            .set_string_s(id, "f16", "")
+            .set_u24_array(id, 0, &[U24::MAX])
+            .set_i24_array(id, 1, &[I24::MIN])
+            .set_f16_array(id, 2, &halves);
+        let document = Document::new(Journal::from(changes));
+        let node = document.nodes.get(id).unwrap();
+        assert_eq!(node.get_u24_array_attribute(0), Some(&[U24::MAX][..]));
+        assert_eq!(node.get_i24_array_attribute(1), Some(&[I24::MIN][..]));
+        assert_eq!(node.get_f16_array_attribute(2), Some(&halves[..]));
+        assert_eq!(node.get_u24_array_attribute(1), None);
+    }
+
+    #[test]
+    fn verify_checksums() {
+        let mut repo = create_example_journal();
+        repo.add_checksum().unwrap();
+        let mut changes = Changes::new();
+        changes.set_string_s(NodeId::new(2), "name", "other value");
+        repo.add_operations(changes);
+        repo.add_checksum().unwrap();
+        let mut changes = Changes::new();
+        changes.remove_node(NodeId::new(2));
+        repo.add_operations(changes);
+
+        let mut buf = Vec::<u8>::new();
+        repo.write(&mut buf).unwrap();
+
+        let verification = Journal::verify(&mut buf.as_slice()).unwrap();
+        let statuses: Vec<_> = verification.ranges.iter().map(|r| r.status).collect();
+        assert_eq!(
+            statuses,
+            [
+                ChecksumStatus::Valid,
+                ChecksumStatus::Valid,
+                ChecksumStatus::Unchecked
+            ]
+        );
+        assert_eq!(verification.ranges[0].operations, 0..5);
+        assert!(verification.is_valid());
+
+        // Corrupt the string in the second range, keeping the file parseable
+        let position = buf
+            .windows(5)
+            .position(|w| w == b"other")
+            .expect("value should be in file");
+        buf[position] = b'O';
+        let verification = Journal::verify(&mut buf.as_slice()).unwrap();
+        assert!(!verification.is_valid());
+        assert_eq!(verification.corrupted().count(), 1);
+        assert_eq!(verification.ranges[1].status, ChecksumStatus::Corrupted);
+        assert_eq!(verification.ranges[0].status, ChecksumStatus::Valid);
+    }
+
+    fn create_checksummed_file() -> (Journal, Vec<u8>) {
+        let mut repo = Journal::new();
+        let mut generator = NodeIdGenerator::new();
+        for i in 0..4 {
+            let id = generator.next_id();
+            let mut changes = Changes::new();
+            changes.add_node(id, NodeId::ROOT_NODE, i);
+            changes.set_name(id, &format!("node {}", i));
+            changes.set_string(id, 0, "some attribute value");
+            repo.add_operations(changes);
+            repo.add_checksum().unwrap();
+        }
+        let mut buf = Vec::<u8>::new();
+        repo.write(&mut buf).unwrap();
+        (repo, buf)
+    }
+
+    fn operation_strings(journal: &Journal) -> Vec<String> {
+        journal.operations.iter().map(|o| o.to_string()).collect()
+    }
+
+    #[test]
+    fn recover_undamaged_file() {
+        let (repo, buf) = create_checksummed_file();
+        let recovery = Journal::recover(&buf);
+        assert!(recovery.skipped.is_empty());
+        assert!(recovery.corrupted.is_empty());
+        assert_eq!(
+            operation_strings(&recovery.journal),
+            operation_strings(&repo)
+        );
+    }
+
+    #[test]
+    fn recover_after_damage() {
+        let (repo, mut buf) = create_checksummed_file();
+        let position = buf
+            .windows(6)
+            .position(|w| w == b"node 1")
+            .expect("name should be in file");
+        // Smash the name and the header of the following operation
+        for b in &mut buf[position..position + 9] {
+            *b = 0xEE;
+        }
+
+        // Reading normally fails, and leniently loses everything after the damage
+        assert!(Journal::read(&mut buf.as_slice()).is_err());
+        let (truncated, error) = Journal::read_lenient(&mut buf.as_slice());
+        assert!(error.is_some());
+        assert!(truncated.operations.len() < repo.operations.len());
+
+        let recovery = Journal::recover(&buf);
+        assert_eq!(recovery.skipped.len(), 1);
+        assert_eq!(recovery.corrupted.len(), 1);
+        assert!(!recovery.is_complete());
+        let recovered = operation_strings(&recovery.journal);
+        // Only operations inside the damaged checksum range are lost
+        assert!(recovered.len() < repo.operations.len());
+        assert!(recovered.len() >= repo.operations.len() - 3);
+        assert!(recovered.contains(&"SetLabel(4, node 3)".to_string()));
+
+        let (_, copy) = create_checksummed_file();
+        let repaired = Journal::recover_with_copy(&buf, &copy);
+        assert!(repaired.is_complete());
+        assert_eq!(repaired.repaired.len(), 1);
+        assert_eq!(
+            operation_strings(&repaired.journal),
+            operation_strings(&repo)
+        );
+    }
+
+    fn example_file() -> Vec<u8> {
+        let mut buf = Vec::<u8>::new();
+        create_example_journal().write(&mut buf).unwrap();
+        buf
+    }
+
+    #[test]
+    fn read_bad_header() {
+        let mut buf = example_file();
+        buf[0] = b'B';
+        assert!(matches!(
+            Journal::read(&mut buf.as_slice()),
+            Err(BincError::BadMagic { .. })
+        ));
+
+        let mut buf = example_file();
+        buf[7] = 9;
+        assert!(matches!(
+            Journal::read(&mut buf.as_slice()),
+            Err(BincError::UnsupportedVersion { found: 9 })
+        ));
+    }
+
+    #[test]
+    fn read_truncated_file() {
+        let buf = example_file();
+        let truncated = &buf[..buf.len() - 3];
+
+        // The last operation is the string attribute, which starts after the name definition
+        let mut last = Vec::<u8>::new();
+        let mut journal = create_example_journal();
+        journal.operations.pop();
+        journal.write(&mut last).unwrap();
+        let offset = last.len() as u64;
+
+        match Journal::read(&mut &truncated[..]) {
+            Err(BincError::TruncatedOperation { offset: o }) => assert_eq!(o, offset),
+            Err(e) => panic!("Unexpected error {}", e),
+            Ok(_) => panic!("Truncated file should not be read"),
+        }
+
+        let (partial, error) = Journal::read_lenient(&mut &truncated[..]);
+        assert!(matches!(error, Some(BincError::TruncatedOperation { .. })));
+        assert_eq!(operation_strings(&partial), operation_strings(&journal));
+    }
+
+    #[test]
+    fn read_invalid_content() {
+        let buf = example_file();
+        let position = buf
+            .windows(8)
+            .position(|w| w == b"my value")
+            .expect("value should be in file");
+
+        let mut invalid_utf8 = buf.clone();
+        invalid_utf8[position] = 0xFF;
+        assert!(matches!(
+            Journal::read(&mut invalid_utf8.as_slice()),
+            Err(BincError::InvalidUtf8 { .. })
+        ));
+
+        // A string length far beyond the operation must fail without allocating it
+        let mut invalid_length = buf.clone();
+        invalid_length[position - 1] = 0xFF;
+        assert!(matches!(
+            Journal::read(&mut invalid_length.as_slice()),
+            Err(BincError::InvalidVarint { .. })
+        ));
+    }
+
+    #[test]
+    fn read_operation_that_cannot_be_applied() {
+        let mut generator = NodeIdGenerator::new();
+        let id = generator.next_id();
+        let missing = generator.next_id();
+
+        let mut changes = Changes::new();
+        changes.add_node(id, NodeId::ROOT_NODE, 0);
+        changes.set_name(id, "ok");
+        changes.set_name(missing, "lost");
+        changes.add_node(generator.next_id(), id, 0);
+        let mut buf = Vec::<u8>::new();
+        Journal::from(changes).write(&mut buf).unwrap();
+
+        match Document::read(&mut buf.as_slice()) {
+            Err(BincError::ApplyFailed { index, .. }) => assert_eq!(index, 2),
+            Err(e) => panic!("Unexpected error {}", e),
+            Ok(_) => panic!("Operation on a missing node should fail"),
+        }
+
+        let (document, error) = Document::read_lenient(&mut buf.as_slice());
+        assert!(matches!(
+            error,
+            Some(BincError::ApplyFailed { index: 2, .. })
+        ));
+        assert_eq!(document.num_operations(), 2);
+        assert_eq!(document.find_roots().len(), 1);
+    }
+
+    #[test]
+    fn read_ids_far_above_the_others() {
+        let mut changes = Changes::new();
+        changes.add_node(NodeId::new(1 << 40), NodeId::ROOT_NODE, 0);
+        let mut buf = Vec::<u8>::new();
+        Journal::from(changes).write(&mut buf).unwrap();
+        assert!(matches!(
+            Document::read(&mut buf.as_slice()),
+            Err(BincError::ApplyFailed {
+                index: 0,
+                error: ApplyError::IdTooLarge { .. }
+            })
+        ));
+
+        let mut journal = Journal::new();
+        journal.add_operation(Operation::ReserveIds {
+            client: uuid::Uuid::new_v4(),
+            start: 1,
+            count: 1 << 40,
+        });
+        journal.add_operation(Operation::AddNode {
+            id: NodeId::new(1 << 39),
+            parent: NodeId::ROOT_NODE,
+            index_in_parent: 0,
+        });
+        let mut buf = Vec::<u8>::new();
+        journal.write(&mut buf).unwrap();
+        assert!(matches!(
+            Document::read(&mut buf.as_slice()),
+            Err(BincError::ApplyFailed {
+                index: 0,
+                error: ApplyError::IdTooLarge { .. }
+            })
+        ));
+    }
+
+    #[test]
+    fn stream_operations() {
+        let journal = create_example_journal();
+        let buf = example_file();
+
+        let items: Vec<(u64, String)> = JournalReader::new(buf.as_slice())
+            .unwrap()
+            .map(|item| item.map(|(offset, operation)| (offset, operation.to_string())))
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            items.iter().map(|(_, o)| o.clone()).collect::<Vec<_>>(),
+            operation_strings(&journal)
+        );
+
+        // Each offset is where the operation starts in the file
+        let mut expected = Journal::HEADER_SIZE;
+        for (operation, (offset, _)) in journal.operations.iter().zip(&items) {
+            assert_eq!(*offset, expected);
+            let mut written = Vec::<u8>::new();
+            operation.write(&mut written).unwrap();
+            expected += written.len() as u64;
+        }
+        assert_eq!(expected, buf.len() as u64);
+
+        // Iteration stops after an error
+        let truncated = &buf[..buf.len() - 3];
+        let mut reader = JournalReader::new(truncated).unwrap();
+        assert_eq!(reader.by_ref().filter(|x| x.is_ok()).count(), 4);
+        assert!(reader.next().is_none());
+
+        assert!(matches!(
+            JournalReader::new(&buf[1..]),
+            Err(BincError::BadMagic { .. })
+        ));
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("binc-{}-{}.binc", name, std::process::id()))
+    }
+
+    #[test]
+    fn append_with_journal_writer() {
+        let path = temp_path("append");
+        let journal = create_example_journal();
+
+        let mut writer = JournalWriter::create(&path).unwrap();
+        writer.write_operations(&journal.operations[..2]).unwrap();
+        writer.flush().unwrap();
+        drop(writer);
+
+        let mut writer = JournalWriter::open(&path).unwrap().with_sync(true);
+        assert_eq!(writer.operation_count(), 2);
+        writer.write_operation(&journal.operations[2]).unwrap();
+        assert_eq!(writer.operations_since_checksum(), 3);
+        writer.write_checksum().unwrap();
+        assert_eq!(writer.operations_since_checksum(), 0);
+        writer.write_operations(&journal.operations[3..]).unwrap();
+        writer.write_checksum().unwrap();
+        writer.flush().unwrap();
+        assert_eq!(writer.operation_count(), 7);
+        drop(writer);
+
+        let data = fs::read(&path).unwrap();
+        let read = Journal::read(&mut data.as_slice()).unwrap();
+        let strings = operation_strings(&read);
+        assert_eq!(strings.len(), 7);
+        assert!(strings[3].starts_with("Checksum"));
+        assert!(strings[6].starts_with("Checksum"));
+        let verification = Journal::verify(&mut data.as_slice()).unwrap();
+        assert!(verification.is_valid());
+        assert_eq!(verification.corrupted().count(), 0);
+        assert_eq!(
+            JournalWriter::open(&path)
+                .unwrap()
+                .operations_since_checksum(),
+            0
+        );
+
+        // A partially written operation at the end must not be appended to
+        fs::write(&path, &data[..data.len() - 3]).unwrap();
+        assert!(matches!(
+            JournalWriter::open(&path),
+            Err(BincError::TruncatedOperation { .. })
+        ));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn unsaved_operations() {
+        let buf = example_file();
+        let mut document = Document::read(&mut buf.as_slice()).unwrap();
+        assert_eq!(document.unsaved_operations().map(|o| o.len()), Some(0));
+        assert_eq!(document.persisted_revision, Some(5));
+
+        let id = document.next_id();
+        let mut changes = Changes::new();
+        changes.add_node(id, NodeId::ROOT_NODE, 0);
+        document.add_and_apply_changes(changes).unwrap();
+        // The reservation of the id and the new node
+        assert_eq!(document.unsaved_operations().map(|o| o.len()), Some(2));
+        document.mark_persisted();
+        assert_eq!(document.unsaved_operations().map(|o| o.len()), Some(0));
+
+        // Undoing past the saved state appends to the file
+        document.undo();
+        document.undo();
+        document
+            .add_and_apply(Operation::RemoveNode { id: NodeId::new(2) })
+            .unwrap();
+        assert_eq!(document.unsaved_operations().map(|o| o.len()), Some(3));
+    }
+
+    fn create_edited_journal() -> Journal {
+        let mut generator = NodeIdGenerator::new();
+        let a = generator.next_id();
+        let b = generator.next_id();
+        let c = generator.next_id();
+
+        let mut changes = Changes::new();
+        changes
+            .add_node(a, NodeId::ROOT_NODE, 0)
+            .add_node(b, NodeId::ROOT_NODE, 1)
+            .add_node(c, a, 0)
+            .set_type_s(a, "folder")
+            .set_name(a, "first")
+            .set_name(a, "second")
+            .set_string_s(b, "text", "draft")
+            .set_string_s(b, "text", "final")
+            .set_attribute_s(c, "done", AttributeValue::Bool(true))
+            .add_tag_s(b, "important")
+            .add_tag_s(c, "important")
+            .move_node(b, a, 0)
+            .remove_node(c);
+        let mut journal = Journal::from(changes);
+        journal.add_operation(Operation::Snapshot {
+            author: "me".to_string(),
+            message: "first draft".to_string(),
+            time: parse_time("2026-01-01"),
+        });
+        let mut comments = Changes::new();
+        comments
+            .add_comment(a, 1, "looks god", "you", None)
+            .add_comment(a, 2, "agreed", "me", Some(1))
+            .edit_comment(a, 1, "looks good")
+            .add_comment(a, 3, "spam", "them", None)
+            .delete_comment(a, 3)
+            .resolve_comment(a, 1, true);
+        journal.operations.extend(comments.operations);
+        journal.add_operation(Operation::SetName {
+            node: b,
+            name: "child".to_string(),
+        });
+        journal
+    }
+
+    #[test]
+    fn compact_journal() {
+        // The same journal for both, as edited comments get the current time
+        let document = Document::new(create_edited_journal());
+        let journal = &document.journal;
+
+        let compacted = journal.compact(false);
+        assert!(compacted.operations.len() < journal.operations.len());
+        let strings = operation_strings(&compacted);
+        assert!(!strings.iter().any(|s| s.contains("draft")));
+        assert!(!strings.iter().any(|s| s.contains("first")));
+        assert!(document.nodes == Document::new(compacted).nodes);
+
+        // Snapshots are kept at the end, and the result survives a round trip
+        let compacted = document.compacted_journal(true);
+        assert!(operation_strings(&compacted)
+            .last()
+            .unwrap()
+            .contains("first draft"));
+        let mut buf = Vec::<u8>::new();
+        compacted.write(&mut buf).unwrap();
+        let read = Document::read(&mut buf.as_slice()).unwrap();
+        assert!(document.nodes == read.nodes);
+
+        // Compacting again changes nothing
+        assert_eq!(
+            operation_strings(&read.compacted_journal(true)),
+            operation_strings(&compacted)
+        );
+    }
+
+    #[test]
+    fn compact_nodes_far_apart() {
+        // Each id is in reach when added, but the tree order puts the low id first
+        let mut changes = Changes::new();
+        for id in [60000, 120000, 180000, 5] {
+            changes.add_node(NodeId::new(id), NodeId::ROOT_NODE, 0);
+        }
+        changes.remove_node(NodeId::new(60000));
+        let document = Document::new(Journal::from(changes));
+        assert_eq!(document.find_roots().len(), 3);
+
+        let mut buf = Vec::<u8>::new();
+        document.compacted_journal(false).write(&mut buf).unwrap();
+        let read = Document::read(&mut buf.as_slice()).unwrap();
+        assert!(document.nodes == read.nodes);
+    }
+
+    #[test]
+    fn load_from_checkpoint() {
+        let mut document = Document::new(create_edited_journal());
+        document.add_checkpoint().unwrap();
+        let id = NodeId::new(10);
+        let mut changes = Changes::new();
+        changes
+            .add_node(id, NodeId::ROOT_NODE, 0)
+            .set_name(id, "after");
+        document.add_and_apply_changes(changes).unwrap();
+
+        let mut buf = Vec::<u8>::new();
+        document.write(&mut buf).unwrap();
+        let read = Document::read(&mut buf.as_slice()).unwrap();
+        assert!(read.nodes == document.nodes);
+
+        // Operations before the checkpoint are not replayed, so one that cannot be applied
+        // does not matter
+        let mut journal = Journal::read(&mut buf.as_slice()).unwrap();
+        journal.operations[4] = Operation::SetName {
+            node: NodeId::new(100),
+            name: "missing".to_string(),
+        };
+        let mut buf = Vec::<u8>::new();
+        journal.write(&mut buf).unwrap();
+        let read = Document::read(&mut buf.as_slice()).unwrap();
+        assert!(read.nodes == document.nodes);
+
+        // A damaged checkpoint is ignored and the whole journal replayed
+        let mut journal = Journal::read(&mut buf.as_slice()).unwrap();
+        let position = journal
+            .operations
+            .iter()
+            .position(|o| matches!(o, Operation::Checkpoint { .. }))
+            .unwrap();
+        journal.operations[position] = Operation::Checkpoint {
+            revision: position,
+            data: vec![0xFF; 5],
+        };
+        let mut buf = Vec::<u8>::new();
+        journal.write(&mut buf).unwrap();
+        assert!(matches!(
+            Document::read(&mut buf.as_slice()),
+            Err(BincError::ApplyFailed { index: 4, .. })
+        ));
+    }
+
+    fn create_long_journal() -> Journal {
+        let mut generator = NodeIdGenerator::new();
+        let mut changes = Changes::new();
+        let mut ids = vec![];
+        for i in 0..700 {
+            if i % 7 == 3 && ids.len() > 2 {
+                changes.remove_node(ids.remove(i % ids.len()));
+            } else {
+                let id = generator.next_id();
+                changes.add_node(id, NodeId::ROOT_NODE, 0);
+                changes.set_name(id, &format!("node {}", i));
+                ids.push(id);
+            }
+        }
+        Journal::from(changes)
+    }
+
+    #[test]
+    fn undo_and_jump_with_checkpoints() {
+        let mut document = Document::new(create_long_journal());
+        let count = document.num_operations();
+        assert!(count > 1000);
+
+        for revision in [0, 1, 255, 256, 257, 600, count - 1, count] {
+            document.jump_to_revision(revision);
+            let mut journal = create_long_journal();
+            journal.operations.truncate(revision);
+            assert!(document.nodes == Document::new(journal).nodes);
+        }
+
+        document.jump_to_revision(513);
+        document.undo();
+        document.undo();
+        document.redo();
+        let mut journal = create_long_journal();
+        journal.operations.truncate(512);
+        assert!(document.nodes == Document::new(journal).nodes);
+    }
+
+    #[test]
+    fn save_and_load_sources() {
+        let mut generator = NodeIdGenerator::new();
+        let imported = generator.next_id();
+        let child = generator.next_id();
+        let other = generator.next_id();
+        let first = Source::from_content("file:///a.xml", b"<a/>");
+        let second = Source::from_content("https://issues.example.com/1", b"{}");
+
+        let mut changes = Changes::new();
+        changes
+            .add_node(imported, NodeId::ROOT_NODE, 0)
+            .add_node(child, imported, 0)
+            .add_node(other, NodeId::ROOT_NODE, 1)
+            .add_source(0, imported, &first)
+            .add_source(1, other, &second)
+            .add_source(2, NodeId::NO_NODE, &second)
+            .remove_source(1);
+        let updated = Source::from_content("file:///a.xml", b"<a><b/></a>");
+        changes.update_source(0, &updated);
+
+        let mut buf = Vec::<u8>::new();
+        Journal::from(changes).write(&mut buf).unwrap();
+        let document = Document::read(&mut buf.as_slice()).unwrap();
+
+        let sources = document.nodes.sources_for(child);
+        assert_eq!(sources.len(), 1);
+        assert_eq!(sources[0].0, 0);
+        assert_eq!(*sources[0].1, updated);
+        assert!(document.nodes.sources_for(other).is_empty());
+        assert!(document.nodes.sources.get(1).is_none());
+        assert_eq!(document.nodes.sources.len(), 2);
+        assert_eq!(document.nodes.sources.next_id(), 3);
+
+        let compacted = Document::new(document.compacted_journal(false));
+        assert!(compacted.nodes == document.nodes);
+    }
+
+    #[test]
+    fn save_and_load_tags() {
+        let mut generator = NodeIdGenerator::new();
+        let a = generator.next_id();
+        let b = generator.next_id();
+        let c = generator.next_id();
+
+        let mut changes = Changes::new();
+        changes
+            .add_node(a, NodeId::ROOT_NODE, 0)
+            .add_node(b, NodeId::ROOT_NODE, 1)
+            .add_node(c, b, 0)
+            .add_tag_s(a, "todo")
+            .add_tag_s(a, "todo")
+            .add_tag_s(b, "todo")
+            .add_tag_s(b, "done")
+            .add_tag_s(c, "done")
+            .remove_tag_s(b, "todo");
+
+        let mut buf = Vec::<u8>::new();
+        Journal::from(changes).write(&mut buf).unwrap();
+        let document = Document::read(&mut buf.as_slice()).unwrap();
+        let nodes = &document.nodes;
+
+        let todo = nodes.tag_names.get_index("todo").unwrap();
+        let done = nodes.tag_names.get_index("done").unwrap();
+        assert_eq!(nodes.get(a).unwrap().tags, vec![todo]);
+        assert!(!nodes.get(b).unwrap().has_tag(todo));
+        assert!(nodes.get(b).unwrap().has_tag(done));
+        assert_eq!(nodes.nodes_with_tag(todo), vec![a]);
+        assert_eq!(nodes.nodes_with_tag_s("done"), vec![b, c]);
+        assert!(nodes.nodes_with_tag_s("unknown").is_empty());
+
+        let compacted = Document::new(document.compacted_journal(false));
+        assert!(compacted.nodes == document.nodes);
+    }
+
+    #[test]
+    fn comment_threads() {
+        let mut generator = NodeIdGenerator::new();
+        let a = generator.next_id();
+
+        let mut changes = Changes::new();
+        changes
+            .add_node(a, NodeId::ROOT_NODE, 0)
+            .add_comment(a, 1, "Is this right?", "me", None)
+            .add_comment(a, 2, "Yes", "you", Some(1))
+            .add_comment(a, 3, "Are you sure?", "me", Some(2))
+            .edit_comment(a, 2, "No")
+            .delete_comment(a, 3)
+            .resolve_comment(a, 1, true)
+            .resolve_comment(a, 1, false)
+            .resolve_comment(a, 2, true);
+
+        let mut buf = Vec::<u8>::new();
+        Journal::from(changes).write(&mut buf).unwrap();
+        let document = Document::read(&mut buf.as_slice()).unwrap();
+        let comments = &document.nodes.get(a).unwrap().comments;
+
+        let threads = comments.threads();
+        assert_eq!(threads.len(), 1);
+        let question = threads[0].comment;
+        assert!(!question.resolved);
+        assert!(question.time.is_some());
+        let answer = &threads[0].replies[0];
+        assert_eq!(answer.comment.text, "No");
+        assert!(answer.comment.resolved);
+        assert!(answer.comment.edited >= answer.comment.time);
+        assert!(answer.replies[0].comment.deleted);
+        assert_eq!(comments.next_id(), 4);
+
+        let compacted = Document::new(document.compacted_journal(false));
+        assert!(compacted.nodes == document.nodes);
+    }
+
+    #[test]
+    fn invalid_comment_operations() {
+        let a = NodeId::new(1);
+        let add = |changes: &mut Changes| {
+            changes
+                .add_node(a, NodeId::ROOT_NODE, 0)
+                .add_comment(a, 1, "first", "me", None);
+        };
+
+        let mut missing_response = Changes::new();
+        add(&mut missing_response);
+        missing_response.add_comment(a, 2, "reply", "me", Some(5));
+        let mut duplicate_id = Changes::new();
+        add(&mut duplicate_id);
+        duplicate_id.add_comment(a, 1, "again", "me", None);
+        let mut edit_deleted = Changes::new();
+        add(&mut edit_deleted);
+        edit_deleted.delete_comment(a, 1).edit_comment(a, 1, "back");
+
+        for changes in [missing_response, duplicate_id, edit_deleted] {
+            let mut buf = Vec::<u8>::new();
+            Journal::from(changes).write(&mut buf).unwrap();
+            assert!(matches!(
+                Document::read(&mut buf.as_slice()),
+                Err(BincError::ApplyFailed { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn read_comment_without_id() {
+        // Comments are written without id and time, as before comments had them
+        let a = NodeId::new(1);
+        let mut changes = Changes::new();
+        changes.add_node(a, NodeId::ROOT_NODE, 0);
+        let mut journal = Journal::from(changes);
+        for response_to in [0, 1, 7] {
+            journal.add_operation(Operation::AddComment {
+                node: a,
+                id: None,
+                comment: "old".to_string(),
+                author: "me".to_string(),
+                response_to,
+                time: None,
+            });
+        }
+
+        let mut buf = Vec::<u8>::new();
+        journal.write(&mut buf).unwrap();
+        let document = Document::read(&mut buf.as_slice()).unwrap();
+        let comments = &document.nodes.get(a).unwrap().comments;
+        assert_eq!(comments.get(1).unwrap().response_to, None);
+        assert_eq!(comments.get(2).unwrap().response_to, Some(1));
+        // Old files may respond to comments that do not exist
+        assert_eq!(comments.get(3).unwrap().response_to, None);
+        assert!(comments.get(1).unwrap().time.is_none());
+    }
+
+    #[test]
+    fn read_operation_with_unknown_fields() {
+        // An add node operation with two bytes of fields from a later version
+        let mut content = Vec::<u8>::new();
+        content.write_id(&NodeId::new(1)).unwrap();
+        content.write_id(&NodeId::ROOT_NODE).unwrap();
+        content.write_length(0).unwrap();
+        content.extend([0xAB, 0xCD]);
+        let mut buf = Vec::<u8>::new();
+        buf.write_u32(Journal::CONTAINER_ID).unwrap();
+        buf.write_u32(Journal::CONTAINER_VERSION).unwrap();
+        // The id of add node
+        buf.write_length_flipped(0x01).unwrap();
+        buf.write_length(content.len()).unwrap();
+        buf.extend(content);
+        let set_name = Operation::SetName {
+            node: NodeId::new(1),
+            name: "a".to_string(),
+        };
+        set_name.write(&mut buf).unwrap();
+
+        let document = Document::read(&mut buf.as_slice()).unwrap();
+        assert_eq!(document.num_operations(), 2);
+        let node = document.nodes.get(NodeId::new(1)).unwrap();
+        assert_eq!(node.get_name(), Some("a"));
+    }
+
+    #[test]
+    fn session_provenance() {
+        let a = NodeId::new(1);
+        let b = NodeId::new(2);
+        let first = Session::new("me");
+        let second = Session::new("you");
+
+        let mut document = Document::default();
+        let mut changes = Changes::new();
+        changes.add_node(a, NodeId::ROOT_NODE, 0);
+        document.add_and_apply_changes(changes).unwrap();
+        document.start_session(&first);
+        document
+            .add_and_apply(Operation::SetName {
+                node: a,
+                name: "a".to_string(),
+            })
+            .unwrap();
+        document.start_session(&first);
+        document
+            .add_and_apply(Operation::AddNode {
+                id: b,
+                parent: a,
+                index_in_parent: 0,
+            })
+            .unwrap();
+        document.start_session(&second);
+        document
+            .add_and_apply(Operation::RemoveNode { id: b })
+            .unwrap();
+
+        assert_eq!(document.num_operations(), 6);
+        assert_eq!(document.provenance(0), None);
+        assert_eq!(document.provenance(3), Some(first.clone()));
+        assert_eq!(document.provenance(4), Some(second.clone()));
+        assert_eq!(document.provenance(5), Some(second.clone()));
+        assert_eq!(document.provenance(6), None);
+
+        let mut buf = Vec::<u8>::new();
+        document.write(&mut buf).unwrap();
+        let read = Document::read(&mut buf.as_slice()).unwrap();
+        assert_eq!(
+            read.sessions(),
+            vec![(1, first.clone()), (4, second.clone())]
+        );
+
+        // Undo operations are part of the session they were made in
+        document.undo();
+        document.undo();
+        document.start_session(&second);
+        assert_eq!(document.num_operations(), 8);
+        assert_eq!(document.provenance(7), Some(second));
+    }
+
+    #[test]
+    fn time_based_checkout() {
+        let mut journal = create_long_journal();
+        let snapshot = |message: &str, time: Option<&str>| Operation::Snapshot {
+            author: "me".to_string(),
+            message: message.to_string(),
+            time: time.and_then(parse_time),
+        };
+        journal.operations.insert(10, snapshot("untimed", None));
+        journal
+            .operations
+            .insert(300, snapshot("january", Some("2026-01-01T12:00:00Z")));
+        journal
+            .operations
+            .insert(700, snapshot("february", Some("2026-02-01")));
+
+        let mut buf = Vec::<u8>::new();
+        journal.write(&mut buf).unwrap();
+        let mut document = Document::read(&mut buf.as_slice()).unwrap();
+        let count = document.num_operations();
+        document.jump_to_revision(count - 1);
+
+        let at = |text: &str| document.revision_at(parse_time(text).unwrap());
+        assert_eq!(at("2025-12-31"), None);
+        assert_eq!(at("2026-01-01"), None);
+        assert_eq!(at("2026-01-01T12:00:00Z"), Some(301));
+        assert_eq!(at("2026-01-15"), Some(301));
+        assert_eq!(at("2026-03-01"), Some(701));
+
+        let january = document.state_at(301);
+        assert_eq!(document.undo_revision, Some(count - 1));
+        for revision in [0, 11, 256, 301, 701, count] {
+            let mut other = Document::read(&mut buf.as_slice()).unwrap();
+            other.jump_to_revision(revision);
+            assert!(document.state_at(revision) == other.nodes);
+        }
+        assert!(january == document.state_at(301));
+        assert!(january != document.nodes);
+        assert!(operation_strings(&document.journal)[10].ends_with("(untimed)"));
+    }
+
+    #[test]
+    fn convert_to_v1() {
+        let a = NodeId::new(1);
+        let mut changes = Changes::new();
+        changes
+            .add_node(a, NodeId::ROOT_NODE, 0)
+            .set_name(a, "a")
+            .set_attribute_s(a, "count", AttributeValue::U32(42))
+            .set_attribute_s(a, "done", AttributeValue::Bool(true))
+            .add_tag_s(a, "todo");
+        let mut journal = Journal::from(changes);
+        journal.add_checksum().unwrap();
+
+        let indices = |operations: &[(usize, &Operation)]| -> Vec<usize> {
+            operations.iter().map(|(index, _)| *index).collect()
+        };
+        assert_eq!(indices(&journal.non_v1_operations()), vec![3, 6, 7, 8]);
+
+        let conversion = journal.convert_to_v1();
+        assert_eq!(conversion.downgraded, vec![3]);
+        assert_eq!(conversion.removed, vec![6, 7, 8]);
+        assert!(conversion.journal.non_v1_operations().is_empty());
+
+        let original = Document::new(journal);
+        let converted = Document::new(conversion.journal);
+        let count = converted.nodes.attribute_names.get_index("count").unwrap();
+        let node = converted.nodes.get(a).unwrap();
+        assert_eq!(node.get_string_attribute(count), Some("42"));
+        assert_eq!(node.get_name(), original.nodes.get(a).unwrap().get_name());
+        assert!(node.tags.is_empty());
+    }
+
+    #[test]
+    fn write_v1_only() {
+        let path = temp_path("v1");
+        let a = NodeId::new(1);
+        let mut changes = Changes::new();
+        changes
+            .add_node(a, NodeId::ROOT_NODE, 0)
+            .set_name(a, "a")
+            .set_string_s(a, "text", "b");
+
+        let mut writer = JournalWriter::create(&path).unwrap().with_v1_only(true);
+        writer.write_operations(&changes.operations).unwrap();
+        let tag = Operation::DefineTagName {
+            id: 0,
+            name: "todo".to_string(),
+        };
+        let error = writer.write_operation(&tag).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidInput);
+        assert!(writer.write_checksum().is_err());
+        writer.flush().unwrap();
+        drop(writer);
+
+        let journal = Journal::read(&mut fs::read(&path).unwrap().as_slice()).unwrap();
+        assert_eq!(journal.operations.len(), 4);
+        assert!(journal.non_v1_operations().is_empty());
+        fs::remove_file(&path).unwrap();
+    }
+
     fn read_file(path: &str) -> Vec<u8> {
         let mut file = File::open(path).unwrap();
         let mut buf = Vec::<u8>::new();
@@ -61,6 +1060,1000 @@ mod tests {
         buf
     }
 
+    #[test]
+    fn reject_invalid_operations() {
+        let a = NodeId::new(1);
+        let b = NodeId::new(2);
+        let missing = NodeId::new(9);
+        let mut document = Document::default();
+        let mut changes = Changes::new();
+        changes
+            .add_node(a, NodeId::ROOT_NODE, 0)
+            .add_node(b, a, 0)
+            .set_name(b, "b");
+        document.add_and_apply_changes(changes).unwrap();
+        let nodes = document.nodes.clone();
+        let count = document.num_operations();
+
+        let rejected = [
+            (
+                Operation::SetName {
+                    node: missing,
+                    name: "x".to_string(),
+                },
+                ApplyError::UnknownNode { node: missing },
+            ),
+            (
+                Operation::AddNode {
+                    id: b,
+                    parent: NodeId::ROOT_NODE,
+                    index_in_parent: 0,
+                },
+                ApplyError::DuplicateId { node: b },
+            ),
+            (
+                Operation::AddNode {
+                    id: missing,
+                    parent: NodeId::new(8),
+                    index_in_parent: 0,
+                },
+                ApplyError::ParentMissing {
+                    parent: NodeId::new(8),
+                },
+            ),
+            (
+                Operation::MoveNode {
+                    id: a,
+                    new_parent: b,
+                    index_in_new_parent: 0,
+                },
+                ApplyError::MoveIntoOwnSubtree { node: a, parent: b },
+            ),
+            (
+                Operation::AddNode {
+                    id: missing,
+                    parent: a,
+                    index_in_parent: 2,
+                },
+                ApplyError::IndexOutOfRange { index: 2, count: 1 },
+            ),
+            (
+                Operation::SetType {
+                    node: a,
+                    type_id: 3,
+                },
+                ApplyError::UndefinedName {
+                    kind: NameKind::Type,
+                    id: 3,
+                },
+            ),
+            (
+                Operation::RemoveNode {
+                    id: NodeId::ROOT_NODE,
+                },
+                ApplyError::RootNode,
+            ),
+        ];
+        for (operation, error) in rejected {
+            assert_eq!(document.add_and_apply(operation), Err(error));
+            assert!(document.nodes == nodes);
+            assert_eq!(document.num_operations(), count);
+        }
+
+        // A set of changes is applied completely or not at all
+        let mut changes = Changes::new();
+        changes.set_name(a, "a").remove_node(missing);
+        assert!(document.add_and_apply_changes(changes).is_err());
+        assert!(document.nodes == nodes);
+        assert_eq!(document.num_operations(), count);
+    }
+
+    #[test]
+    fn read_invalid_operation() {
+        let mut changes = Changes::new();
+        changes
+            .add_node(NodeId::new(1), NodeId::ROOT_NODE, 0)
+            .move_node(NodeId::new(1), NodeId::new(1), 0);
+        let mut buf = Vec::<u8>::new();
+        Journal::from(changes).write(&mut buf).unwrap();
+        assert!(matches!(
+            Document::read(&mut buf.as_slice()),
+            Err(BincError::ApplyFailed {
+                index: 1,
+                error: ApplyError::MoveIntoOwnSubtree { .. }
+            })
+        ));
+    }
+
+    #[test]
+    fn node_ids_after_load() {
+        let mut changes = Changes::new();
+        changes
+            .add_node(NodeId::new(1), NodeId::ROOT_NODE, 0)
+            .add_node(NodeId::new(2), NodeId::ROOT_NODE, 1)
+            .set_name(NodeId::new(2), "kept");
+        let mut buf = Vec::<u8>::new();
+        Journal::from(changes).write(&mut buf).unwrap();
+
+        let mut document = Document::read(&mut buf.as_slice()).unwrap();
+        let id = document.next_id();
+        assert!(id.index() > 2);
+        let mut changes = Changes::new();
+        changes.add_node(id, NodeId::ROOT_NODE, 0);
+        document.add_and_apply_changes(changes).unwrap();
+        assert_eq!(document.find_roots().len(), 3);
+        assert_eq!(
+            document.nodes.get(NodeId::new(2)).unwrap().name.as_deref(),
+            Some("kept")
+        );
+
+        // The reservation is undone with the node added after it, which gives it up, so the
+        // next id reserves again
+        document.undo();
+        assert_eq!(document.find_roots().len(), 2);
+        assert_eq!(document.nodes.reserved_by(id), None);
+        assert_eq!(document.next_id(), id);
+        assert_eq!(document.nodes.reserved_by(id), Some(document.client));
+
+        // Asking for an id while an earlier revision is shown leaves the journal as it is, until
+        // the document continues from that revision
+        let mut document = Document::read(&mut buf.as_slice()).unwrap();
+        document.jump_to_revision(1);
+        let count = document.num_operations();
+        let id = document.next_id();
+        assert_eq!(document.num_operations(), count);
+        assert_eq!(document.current_revision(), 1);
+        let mut changes = Changes::new();
+        changes.add_node(id, NodeId::ROOT_NODE, 0);
+        document.add_and_apply_changes(changes).unwrap();
+        assert_eq!(document.find_roots().len(), 2);
+        assert_eq!(document.nodes.reserved_by(id), Some(document.client));
+        let mut saved = Vec::<u8>::new();
+        document.write(&mut saved).unwrap();
+        assert!(Document::read(&mut saved.as_slice()).unwrap().nodes == document.nodes);
+
+        document.jump_to_revision(1);
+        let id = document.next_id();
+        document
+            .add_and_apply(Operation::AddNode {
+                id,
+                parent: NodeId::ROOT_NODE,
+                index_in_parent: 0,
+            })
+            .unwrap();
+        assert_eq!(document.nodes.reserved_by(id), Some(document.client));
+        assert!(document.state_at(document.num_operations()) == document.nodes);
+    }
+
+    #[test]
+    fn concurrent_id_reservations() {
+        let mut changes = Changes::new();
+        changes.add_node(NodeId::new(1), NodeId::ROOT_NODE, 0);
+        let mut shared = Vec::<u8>::new();
+        Journal::from(changes).write(&mut shared).unwrap();
+        let mut first = Document::read(&mut shared.as_slice()).unwrap();
+        let mut second = Document::read(&mut shared.as_slice()).unwrap();
+
+        // The second writer sees the reservation of the first before adding its own nodes
+        let a = first.next_id();
+        let mut received = Vec::<u8>::new();
+        for operation in first.unsaved_operations().unwrap() {
+            operation.write(&mut received).unwrap();
+        }
+        second.append_and_apply(&mut received.as_slice()).unwrap();
+        let b = second.next_id();
+        assert_ne!(a, b);
+        assert_eq!(second.nodes.reserved_by(a), Some(first.client));
+        assert_eq!(second.nodes.reserved_by(b), Some(second.client));
+
+        // A reservation that overlaps one that is already made is rejected
+        let conflicting = Operation::ReserveIds {
+            client: second.client,
+            start: a.index(),
+            count: 1,
+        };
+        assert!(matches!(
+            first.add_and_apply(conflicting),
+            Err(ApplyError::IdsInUse { .. })
+        ));
+
+        // Reservations survive compaction
+        let compacted = Document::new(second.compacted_journal(false));
+        assert!(compacted.nodes == second.nodes);
+    }
+
+    #[test]
+    fn merge_divergent_journals() {
+        let (a, b, c) = (NodeId::new(1), NodeId::new(2), NodeId::new(3));
+        let mut changes = Changes::new();
+        changes
+            .add_node(a, NodeId::ROOT_NODE, 0)
+            .add_node(b, NodeId::ROOT_NODE, 1)
+            .add_node(c, NodeId::ROOT_NODE, 2);
+        let mut base = Vec::<u8>::new();
+        Journal::from(changes).write(&mut base).unwrap();
+        let mut ours = Document::read(&mut base.as_slice()).unwrap();
+        let mut theirs = Document::read(&mut base.as_slice()).unwrap();
+        let base_len = ours.num_operations();
+
+        // Both sides reserve the same ids and use the same attribute id for different names
+        let n = ours.next_id();
+        let mut changes = Changes::new();
+        changes
+            .add_node(n, NodeId::ROOT_NODE, 1)
+            .set_string_s(a, "owner", "me")
+            .set_name(b, "ours")
+            .move_node(c, NodeId::ROOT_NODE, 0)
+            .add_comment(a, 1, "ours", "me", None);
+        ours.add_and_apply_changes(changes).unwrap();
+
+        let m = theirs.next_id();
+        assert_eq!(n, m);
+        let mut changes = Changes::new();
+        changes
+            .add_node(m, NodeId::ROOT_NODE, 0)
+            .set_string_s(a, "priority", "high")
+            .set_name(b, "theirs")
+            .remove_node(c)
+            .add_comment(a, 1, "theirs", "you", None);
+        theirs.add_and_apply_changes(changes).unwrap();
+
+        let merged = merge(
+            base_len,
+            &ours.journal.operations,
+            &theirs.journal.operations,
+        )
+        .unwrap();
+        assert_eq!(
+            merged.conflicts,
+            vec![
+                Conflict::BothChanged {
+                    index: base_len + 4,
+                    node: b,
+                    field: Field::Name
+                },
+                Conflict::Rejected {
+                    index: base_len + 5,
+                    error: ApplyError::UnknownNode { node: c }
+                },
+            ]
+        );
+
+        // Our node is added after `a` with a new id, next to theirs
+        let nodes = &merged.nodes;
+        let roots = nodes.find_roots();
+        assert_eq!(roots.len(), 4);
+        assert_eq!(roots[0], m);
+        assert_eq!(roots[1], a);
+        assert_eq!(roots[3], b);
+        assert_ne!(roots[2], m);
+
+        let node_a = nodes.get(a).unwrap();
+        let attribute = |name: &str| nodes.attribute_names.get_index(name).unwrap();
+        assert_eq!(node_a.get_string_attribute(attribute("owner")), Some("me"));
+        assert_eq!(
+            node_a.get_string_attribute(attribute("priority")),
+            Some("high")
+        );
+        assert_eq!(node_a.comments.get(2).unwrap().text, "ours");
+        assert_eq!(nodes.get(b).unwrap().get_name(), Some("ours"));
+
+        // Appending the rebased operations to theirs gives the same state
+        let mut journal = theirs.journal;
+        journal.operations.extend(merged.operations);
+        assert!(Document::new(journal).nodes == merged.nodes);
+    }
+
+    #[test]
+    fn merge_after_undo() {
+        let (a, b) = (NodeId::new(1), NodeId::new(2));
+        let mut changes = Changes::new();
+        changes
+            .add_node(a, NodeId::ROOT_NODE, 0)
+            .add_node(b, NodeId::ROOT_NODE, 1);
+        let mut base = Vec::<u8>::new();
+        Journal::from(changes).write(&mut base).unwrap();
+        let mut ours = Document::read(&mut base.as_slice()).unwrap();
+        let mut theirs = Document::read(&mut base.as_slice()).unwrap();
+        let base_len = ours.num_operations();
+
+        let x = ours.next_id();
+        let mut changes = Changes::new();
+        changes.add_node(x, a, 0);
+        ours.add_and_apply_changes(changes).unwrap();
+        ours.undo();
+        let mut changes = Changes::new();
+        changes.set_name(a, "ours");
+        ours.add_and_apply_changes(changes).unwrap();
+
+        let mut changes = Changes::new();
+        changes.set_name(a, "theirs").set_name(b, "theirs");
+        theirs.add_and_apply_changes(changes).unwrap();
+        theirs.undo();
+
+        let merged = merge(
+            base_len,
+            &ours.journal.operations,
+            &theirs.journal.operations,
+        )
+        .unwrap();
+        assert_eq!(merged.conflicts, vec![]);
+        let nodes = &merged.nodes;
+        assert!(!nodes.exists(x));
+        assert!(nodes.get(a).unwrap().children.is_empty());
+        assert_eq!(nodes.get(a).unwrap().get_name(), Some("ours"));
+        assert_eq!(nodes.get(b).unwrap().get_name(), None);
+
+        let mut journal = theirs.journal;
+        journal.operations.extend(merged.operations);
+        assert!(Document::new(journal).nodes == merged.nodes);
+
+        // Undoing part of the common start is reported, as their operations build on it
+        let mut ours = Document::read(&mut base.as_slice()).unwrap();
+        ours.undo();
+        let theirs = Journal::read(&mut base.as_slice()).unwrap();
+        let merged = merge(base_len, &ours.journal.operations, &theirs.operations).unwrap();
+        assert_eq!(merged.conflicts, vec![Conflict::Undone { index: 1 }]);
+        assert!(merged.nodes.exists(b));
+    }
+
+    #[test]
+    fn diff_document_states() {
+        let (a, b, c, d, e, f) = (
+            NodeId::new(1),
+            NodeId::new(2),
+            NodeId::new(3),
+            NodeId::new(4),
+            NodeId::new(5),
+            NodeId::new(6),
+        );
+        let mut changes = Changes::new();
+        changes
+            .add_node(a, NodeId::ROOT_NODE, 0)
+            .add_node(b, NodeId::ROOT_NODE, 1)
+            .add_node(c, NodeId::ROOT_NODE, 2)
+            .add_node(d, NodeId::ROOT_NODE, 3)
+            .add_node(e, a, 0)
+            .set_name(c, "old")
+            .set_string_s(a, "status", "open")
+            .set_string_s(a, "owner", "me")
+            .add_tag_s(a, "urgent");
+        let mut document = Document::new(Journal::from(changes));
+        let before = document.num_operations();
+
+        let mut changes = Changes::new();
+        changes
+            .remove_node(b)
+            .move_node(d, NodeId::ROOT_NODE, 0)
+            .move_node(e, NodeId::ROOT_NODE, 3)
+            .add_node(f, a, 0)
+            .set_name(f, "new")
+            .set_name(c, "renamed")
+            .remove_tag(a, 0);
+        document.add_and_apply_changes(changes).unwrap();
+        document.set_node_type(c, "task").unwrap();
+        document.set_node_tag(c, "later").unwrap();
+        document
+            .set_node_attribute_s(a, "status", "closed")
+            .unwrap();
+
+        let differences = document.diff(before, document.num_operations());
+        let expected = [
+            Difference::NodeMoved {
+                node: d,
+                old_parent: NodeId::ROOT_NODE,
+                old_index: 3,
+                new_parent: NodeId::ROOT_NODE,
+                new_index: 0,
+            },
+            Difference::AttributeChanged {
+                node: a,
+                attribute: "status".to_string(),
+                old: Some(AttributeValue::String("open".to_string())),
+                new: Some(AttributeValue::String("closed".to_string())),
+            },
+            Difference::TagRemoved {
+                node: a,
+                tag: "urgent".to_string(),
+            },
+            Difference::NodeAdded {
+                node: f,
+                parent: a,
+                index: 0,
+            },
+            Difference::NameChanged {
+                node: f,
+                old: None,
+                new: Some("new".to_string()),
+            },
+            Difference::NameChanged {
+                node: c,
+                old: Some("old".to_string()),
+                new: Some("renamed".to_string()),
+            },
+            Difference::TypeChanged {
+                node: c,
+                old: None,
+                new: Some("task".to_string()),
+            },
+            Difference::TagAdded {
+                node: c,
+                tag: "later".to_string(),
+            },
+            Difference::NodeMoved {
+                node: e,
+                old_parent: a,
+                old_index: 0,
+                new_parent: NodeId::ROOT_NODE,
+                new_index: 3,
+            },
+            Difference::NodeRemoved { node: b },
+        ];
+        assert_eq!(differences, expected);
+
+        // The differences turned into changes lead to the same state
+        let mut journal = Journal::new();
+        journal
+            .operations
+            .extend_from_slice(&document.journal.operations[..before]);
+        let mut rebuilt = Document::new(journal);
+        let (changes, left_out) = diff::to_changes(&rebuilt.nodes, &differences);
+        assert!(left_out.is_empty());
+        rebuilt.add_and_apply_changes(changes).unwrap();
+        assert!(rebuilt.nodes.diff(&document.nodes).is_empty());
+    }
+
+    #[test]
+    fn diff_to_changes_leaves_out_cleared_values() {
+        let mut document = Document::default();
+        let node = document.add_node(NodeId::ROOT_NODE).unwrap();
+        let mut journal = Journal::new();
+        journal
+            .operations
+            .extend_from_slice(&document.journal.operations);
+        let cleared = Document::new(journal);
+        document.set_node_name(node, "named").unwrap();
+        document.set_node_attribute_s(node, "note", "kept").unwrap();
+        document.set_node_tag(node, "later").unwrap();
+
+        // No operation clears a name or an attribute, and the removed node is not in the document
+        let mut differences = document.nodes.diff(&cleared.nodes);
+        let missing = Difference::NodeRemoved {
+            node: NodeId::new(99),
+        };
+        differences.push(missing.clone());
+        let (changes, left_out) = diff::to_changes(&document.nodes, &differences);
+        assert_eq!(
+            left_out,
+            vec![
+                Difference::NameChanged {
+                    node,
+                    old: Some("named".to_string()),
+                    new: None,
+                },
+                Difference::AttributeChanged {
+                    node,
+                    attribute: "note".to_string(),
+                    old: Some(AttributeValue::String("kept".to_string())),
+                    new: None,
+                },
+                missing,
+            ]
+        );
+        document.add_and_apply_changes(changes).unwrap();
+        assert!(document.nodes.get(node).unwrap().tags.is_empty());
+    }
+
+    #[test]
+    fn branches() {
+        let mut document = Document::default();
+        let a = document.add_node(NodeId::ROOT_NODE).unwrap();
+        let fork = document.num_operations();
+
+        document.create_branch("feature", fork).unwrap();
+        assert_eq!(
+            document.create_branch("feature", fork),
+            Err(ApplyError::BranchExists {
+                name: "feature".to_string()
+            })
+        );
+        let b = document.add_node(NodeId::ROOT_NODE).unwrap();
+        assert_eq!(document.current_branch().name, "feature");
+
+        document.switch_branch("main").unwrap();
+        assert!(document.nodes.exists(a) && !document.nodes.exists(b));
+        let c = document.add_node(a).unwrap();
+
+        // Undoing and editing on a branch keeps the operations of the others
+        document.switch_branch("feature").unwrap();
+        assert!(document.nodes.exists(b) && !document.nodes.exists(c));
+        document.undo();
+        assert!(!document.nodes.exists(b));
+        document.redo();
+        assert!(document.nodes.exists(b));
+        document.undo();
+        document.set_node_name(a, "renamed").unwrap();
+        assert!(!document.nodes.exists(b));
+
+        // Branches are read back from the file
+        let mut buf = vec![];
+        document.write(&mut buf).unwrap();
+        let mut document = Document::read(&mut buf.as_slice()).unwrap();
+        let names: Vec<&str> = document
+            .branches()
+            .iter()
+            .map(|branch| branch.name.as_str())
+            .collect();
+        assert_eq!(names, ["main", "feature"]);
+        assert_eq!(document.current_branch().name, "feature");
+        assert_eq!(document.current_branch().head, document.num_operations());
+        assert_eq!(document.nodes.get(a).unwrap().get_name(), Some("renamed"));
+        assert!(!document.nodes.exists(b) && !document.nodes.exists(c));
+
+        assert_eq!(
+            document.delete_branch("feature"),
+            Err(ApplyError::CurrentBranch {
+                name: "feature".to_string()
+            })
+        );
+        document.switch_branch("main").unwrap();
+        assert!(document.nodes.exists(c));
+        assert_eq!(document.nodes.get(a).unwrap().get_name(), None);
+        document.delete_branch("feature").unwrap();
+        assert_eq!(document.branches().len(), 1);
+        assert_eq!(
+            document.switch_branch("feature"),
+            Err(ApplyError::UnknownBranch {
+                name: "feature".to_string()
+            })
+        );
+
+        // Compaction keeps the current branch only
+        let compacted = Document::new(document.compacted_journal(false));
+        assert!(compacted.nodes.exists(c) && !compacted.nodes.exists(b));
+    }
+
+    #[test]
+    fn transactions() {
+        let mut document = Document::default();
+        let a = document
+            .transaction(|document| {
+                let a = document.add_node(NodeId::ROOT_NODE)?;
+                document.set_node_name(a, "a")?;
+                document.set_node_attribute_s(a, "status", "open")?;
+                Ok::<NodeId, ApplyError>(a)
+            })
+            .unwrap();
+
+        let b = document.next_id();
+        let mut changes = Changes::new();
+        changes
+            .add_node(b, NodeId::ROOT_NODE, 1)
+            .set_name(b, "b")
+            .set_type_s(b, "task");
+        document.add_and_apply_changes(changes).unwrap();
+
+        // Typing in a name field combines into one operation
+        let set_name = |name: &str| Operation::SetName {
+            node: b,
+            name: name.to_string(),
+        };
+        let count = document.num_operations();
+        for name in ["b2", "b23", "b234"] {
+            document.edit(set_name(name)).unwrap();
+        }
+        assert_eq!(document.num_operations(), count + 1);
+        assert_eq!(document.nodes.get(b).unwrap().get_name(), Some("b234"));
+
+        // Saved operations are not changed
+        document.mark_persisted();
+        document.edit(set_name("b2345")).unwrap();
+        assert_eq!(document.num_operations(), count + 2);
+
+        // Other changes are never combined
+        document.set_node_name(b, "b23456").unwrap();
+        document.set_node_name(b, "b2345").unwrap();
+        assert_eq!(document.num_operations(), count + 4);
+        document.undo();
+        document.undo();
+
+        document.undo();
+        document.undo();
+        assert_eq!(document.nodes.get(b).unwrap().get_name(), Some("b"));
+        document.undo();
+        assert!(!document.nodes.exists(b));
+        assert!(document.nodes.exists(a));
+        document.undo();
+        assert!(!document.nodes.exists(a));
+
+        document.redo();
+        let attribute = document.nodes.attribute_names.get_index("status").unwrap();
+        let node = document.nodes.get(a).unwrap();
+        assert_eq!(node.get_name(), Some("a"));
+        assert_eq!(node.get_string_attribute(attribute), Some("open"));
+        document.redo();
+        assert_eq!(document.nodes.get(b).unwrap().get_name(), Some("b"));
+        document.redo();
+        document.redo();
+        assert_eq!(document.undo_revision, None);
+        assert_eq!(document.nodes.get(b).unwrap().get_name(), Some("b2345"));
+    }
+
+    #[test]
+    fn transactions_after_reading() {
+        let mut document = Document::default();
+        let a = document
+            .transaction(|document| {
+                let a = document.add_node(NodeId::ROOT_NODE)?;
+                document.set_node_name(a, "a")?;
+                document.set_node_attribute_s(a, "status", "open")?;
+                Ok::<NodeId, ApplyError>(a)
+            })
+            .unwrap();
+        let b = document.add_node(NodeId::ROOT_NODE).unwrap();
+        let mut buf = Vec::<u8>::new();
+        document.write(&mut buf).unwrap();
+
+        // The transaction is recorded in the file, so it is still one step
+        let mut read = Document::read(&mut buf.as_slice()).unwrap();
+        read.undo();
+        assert!(!read.nodes.exists(b));
+        assert!(read.nodes.exists(a));
+        read.undo();
+        assert!(read.find_roots().is_empty());
+        assert!(!read.can_undo());
+
+        read.redo();
+        assert_eq!(read.nodes.get(a).unwrap().get_name(), Some("a"));
+        assert!(!read.nodes.exists(b));
+    }
+
+    #[test]
+    fn undo_with_inverse_operations() {
+        let mut document = Document::default();
+        let time = parse_time("2024-05-01T10:00:00Z").unwrap();
+        let client = document.client;
+        let (a, b, c, d) = (
+            NodeId::new(1),
+            NodeId::new(2),
+            NodeId::new(3),
+            NodeId::new(4),
+        );
+        let operations = vec![
+            Operation::ReserveIds {
+                client,
+                start: 1,
+                count: 16,
+            },
+            Operation::AddNode {
+                id: a,
+                parent: NodeId::ROOT_NODE,
+                index_in_parent: 0,
+            },
+            Operation::AddNode {
+                id: b,
+                parent: NodeId::ROOT_NODE,
+                index_in_parent: 1,
+            },
+            Operation::AddNode {
+                id: c,
+                parent: a,
+                index_in_parent: 0,
+            },
+            Operation::AddNode {
+                id: d,
+                parent: c,
+                index_in_parent: 0,
+            },
+            Operation::DefineTypeName {
+                id: 0,
+                name: "task".to_string(),
+            },
+            Operation::SetType {
+                node: c,
+                type_id: 0,
+            },
+            Operation::SetName {
+                node: c,
+                name: "first".to_string(),
+            },
+            Operation::SetName {
+                node: d,
+                name: "second".to_string(),
+            },
+            Operation::DefineAttributeName {
+                id: 0,
+                name: "status".to_string(),
+            },
+            Operation::SetAttribute {
+                node: c,
+                attribute: 0,
+                value: AttributeValue::String("open".to_string()),
+            },
+            Operation::SetAttribute {
+                node: c,
+                attribute: 0,
+                value: AttributeValue::String("done".to_string()),
+            },
+            Operation::DefineTagName {
+                id: 0,
+                name: "urgent".to_string(),
+            },
+            Operation::SetTag { node: d, tag: 0 },
+            Operation::RemoveTag { node: d, tag: 0 },
+            Operation::AddComment {
+                node: c,
+                id: Some(1),
+                comment: "Looks good".to_string(),
+                author: "kim".to_string(),
+                response_to: 0,
+                time: Some(time),
+            },
+            Operation::ResolveComment {
+                node: c,
+                id: 1,
+                resolved: true,
+            },
+            Operation::AddSource {
+                id: 0,
+                node: c,
+                uri: "file:///notes.txt".to_string(),
+                hash: vec![1, 2, 3],
+                time,
+            },
+            Operation::MoveNode {
+                id: d,
+                new_parent: b,
+                index_in_new_parent: 0,
+            },
+            Operation::MoveNode {
+                id: a,
+                new_parent: NodeId::ROOT_NODE,
+                index_in_new_parent: 1,
+            },
+            Operation::RemoveSource { id: 0 },
+            Operation::RemoveNode { id: a },
+        ];
+        for operation in operations {
+            document.add_and_apply(operation).unwrap();
+        }
+
+        let same_as_replay =
+            |document: &Document| document.nodes == document.state_at(document.current_revision());
+        while document.can_undo() {
+            document.undo();
+            assert!(same_as_replay(&document), "{}", document.current_revision());
+        }
+        assert!(document.nodes.nodes() == document.state_at(0).nodes());
+        while document.can_redo() {
+            document.redo();
+            assert!(same_as_replay(&document), "{}", document.current_revision());
+        }
+        assert!(!document.nodes.exists(a));
+        assert_eq!(document.nodes.get(d).unwrap().parent, b);
+    }
+
+    #[test]
+    fn undo_after_checksum() {
+        let mut document = Document::default();
+        let a = document.add_node(NodeId::ROOT_NODE).unwrap();
+        document.set_node_name(a, "a").unwrap();
+        document.add_checksum().unwrap();
+        document.add_checkpoint().unwrap();
+
+        // Undo goes past the checksum and checkpoint added when saving
+        document.undo();
+        assert_eq!(document.nodes.get(a).unwrap().get_name(), None);
+        document.redo();
+        assert_eq!(document.nodes.get(a).unwrap().get_name(), Some("a"));
+    }
+
+    #[test]
+    fn persistent_undo() {
+        let mut document = Document::default();
+        let a = document.add_node(NodeId::ROOT_NODE).unwrap();
+        document.set_node_name(a, "first").unwrap();
+        document.mark_persisted();
+        document.set_node_name(a, "second").unwrap();
+        let count = document.num_operations();
+        let name =
+            |document: &Document| document.nodes.get(a).unwrap().get_name().map(String::from);
+
+        // Undo adds an operation, the undone one stays in the journal
+        document.undo();
+        assert_eq!(name(&document), Some("first".to_string()));
+        assert_eq!(document.num_operations(), count + 1);
+        assert_eq!(document.unsaved_operations().map(|o| o.len()), Some(2));
+
+        // A reopened document can redo, and undo what came before
+        let mut buf = Vec::<u8>::new();
+        document.write(&mut buf).unwrap();
+        let mut reopened = Document::read(&mut buf.as_slice()).unwrap();
+        assert_eq!(name(&reopened), Some("first".to_string()));
+        assert!(reopened.can_redo());
+        reopened.redo();
+        assert_eq!(name(&reopened), Some("second".to_string()));
+        assert!(!reopened.can_redo());
+        reopened.undo();
+        reopened.undo();
+        assert_eq!(name(&reopened), None);
+        reopened.redo();
+        assert_eq!(name(&reopened), Some("first".to_string()));
+
+        // Editing ends redo, without losing the undone operations
+        reopened.set_node_name(a, "third").unwrap();
+        assert!(!reopened.can_redo());
+        assert!(reopened
+            .journal
+            .operations
+            .iter()
+            .any(|o| o.to_string().contains("second")));
+
+        // Collaborators receive undo and redo as appended operations
+        let mut received = Vec::<u8>::new();
+        for operation in &reopened.journal.operations[count + 1..] {
+            operation.write(&mut received).unwrap();
+        }
+        let mut remote = Document::read(&mut buf.as_slice()).unwrap();
+        remote.append_and_apply(&mut received.as_slice()).unwrap();
+        assert!(remote.nodes == reopened.nodes);
+        remote.undo();
+        assert_eq!(name(&remote), Some("first".to_string()));
+
+        // Editing an earlier revision keeps the later operations in the journal
+        let mut document = Document::read(&mut buf.as_slice()).unwrap();
+        document.jump_to_revision(2);
+        document.set_node_name(a, "other").unwrap();
+        document.undo();
+        document.redo();
+        assert_eq!(name(&document), Some("other".to_string()));
+        document.undo();
+        document.undo();
+        document.redo();
+        assert_eq!(name(&document), None);
+        assert!(document.state_at(count) == reopened.state_at(count));
+    }
+
+    #[test]
+    fn blame_and_attribute_history() {
+        let ann = Session::new("ann");
+        let bob = Session::new("bob");
+        let snapshot = || Operation::Snapshot {
+            author: "ann".to_string(),
+            message: "review".to_string(),
+            time: None,
+        };
+
+        let mut document = Document::default();
+        document.start_session(&ann);
+        let a = document.add_node(NodeId::ROOT_NODE).unwrap();
+        let created = document.num_operations() - 1;
+        document.set_node_name(a, "a").unwrap();
+        let named = document.num_operations() - 1;
+        document.set_node_type(a, "task").unwrap();
+        document.set_node_attribute_s(a, "status", "open").unwrap();
+        // Defining the name makes it a transaction, which ends after the attribute is set
+        let opened = document.num_operations() - 2;
+        document.set_node_tag(a, "urgent").unwrap();
+        document.add_and_apply(snapshot()).unwrap();
+        let first_snapshot = document.num_operations() - 1;
+
+        document.start_session(&bob);
+        document.set_node_attribute_s(a, "status", "done").unwrap();
+        let done = document.num_operations() - 1;
+        document.add_and_apply(snapshot()).unwrap();
+        document
+            .add_and_apply(Operation::RemoveTag { node: a, tag: 0 })
+            .unwrap();
+        document.set_node_name(a, "b").unwrap();
+        let renamed = document.num_operations() - 1;
+
+        let blame = document.blame(a).unwrap();
+        assert_eq!(blame.created.index, created);
+        assert_eq!(blame.created.snapshot, Some(first_snapshot));
+        assert_eq!(blame.created.session, Some(ann.clone()));
+        assert_eq!(blame.name.as_ref().unwrap().index, renamed);
+        assert_eq!(blame.name.as_ref().unwrap().snapshot, None);
+        assert_eq!(blame.name.unwrap().session.unwrap().author, "bob");
+        assert!(blame.type_id.is_some());
+        assert!(blame.moved.is_none());
+        assert!(blame.tags.is_empty());
+        let status = document.nodes.attribute_names.get_index("status").unwrap();
+        assert_eq!(blame.attributes[&status].index, done);
+        assert_eq!(blame.attributes[&status].snapshot, Some(done + 1));
+
+        let history: Vec<(usize, AttributeValue)> = document
+            .attribute_history(a, status)
+            .into_iter()
+            .map(|(origin, value)| (origin.index, value))
+            .collect();
+        let string = |s: &str| AttributeValue::String(s.to_string());
+        assert_eq!(
+            history,
+            vec![(opened, string("open")), (done, string("done"))]
+        );
+
+        // Undone operations are not part of the blame
+        document.undo();
+        assert_eq!(document.blame(a).unwrap().name.unwrap().index, named);
+        assert!(document.blame(NodeId::ROOT_NODE).is_none());
+        assert!(document.blame(NodeId::new(99)).is_none());
+
+        // So are undone sessions
+        document.start_session(&Session::new("carol"));
+        document.set_node_name(a, "c").unwrap();
+        document.undo();
+        document.undo();
+        document.set_node_name(a, "d").unwrap();
+        let name = document.blame(a).unwrap().name.unwrap();
+        assert_eq!(name.session.unwrap().author, "bob");
+    }
+
+    #[test]
+    fn query_nodes() {
+        let mut document = Document::default();
+        let issue = |document: &mut Document, summary: &str, status: &str, assignee: &str| {
+            let node = document.add_node(NodeId::ROOT_NODE).unwrap();
+            document.set_node_type(node, "issue").unwrap();
+            document
+                .set_node_attribute_s(node, "summary", summary)
+                .unwrap();
+            document
+                .set_node_attribute_s(node, "status", status)
+                .unwrap();
+            document
+                .set_node_attribute_s(node, "assignee", assignee)
+                .unwrap();
+            node
+        };
+        let crash = issue(&mut document, "Crash on save", "open", "Kim Lee");
+        let typo = issue(&mut document, "Typo in menu", "closed", "kim");
+        let slow = issue(&mut document, "Slow start", "open", "Sam");
+        document
+            .set_node_attribute(slow, "votes", AttributeValue::U32(12))
+            .unwrap();
+        document
+            .set_node_attribute(crash, "votes", AttributeValue::U32(3))
+            .unwrap();
+        document.set_node_tag(typo, "easy").unwrap();
+        let comment = document.add_node(crash).unwrap();
+        document.set_node_type(comment, "comment").unwrap();
+        document.set_node_name(comment, "first").unwrap();
+        let reply = document.add_node(comment).unwrap();
+        document.set_node_type(reply, "comment").unwrap();
+
+        let select = |query: &str| document.nodes.select(query).unwrap();
+        assert_eq!(select("issue"), vec![crash, typo, slow]);
+        assert_eq!(
+            select(r#"issue[status="open"][assignee~="kim"]"#),
+            vec![crash]
+        );
+        assert_eq!(select("issue > comment"), vec![comment]);
+        assert_eq!(select("issue comment"), vec![comment, reply]);
+        assert_eq!(select("issue comment > *"), vec![reply]);
+        assert_eq!(select("#first"), vec![comment]);
+        assert_eq!(select(".easy"), vec![typo]);
+        assert_eq!(select("[votes>=3]"), vec![crash, slow]);
+        assert_eq!(select("[votes > 5]"), vec![slow]);
+        assert_eq!(select("issue[votes!=3]"), vec![typo, slow]);
+        assert_eq!(select("[summary^=Typo]"), vec![typo]);
+        assert_eq!(select("[@type$=ment]"), vec![comment, reply]);
+        assert_eq!(select("task"), vec![]);
+        assert_eq!(
+            select("issue order by votes desc, summary"),
+            vec![slow, crash, typo]
+        );
+        assert_eq!(
+            select("issue order by @name, summary desc"),
+            vec![typo, slow, crash]
+        );
+
+        assert!(document.nodes.select("issue[").is_err());
+        let query = Query::parse(&format!("*[summary={}]", quote("Crash on save"))).unwrap();
+        assert!(query.matches(&document.nodes, crash));
+        assert!(!query.matches(&document.nodes, NodeId::ROOT_NODE));
+    }
+
     //#[test]
     fn load_existing_file() {
         let path = "test_data/checklistfile.binc";
@@ -4,12 +4,14 @@ mod store;
 use crate::store::Store;
 use binc::client::Client;
 use binc::document::Document;
-use binc::journal::Journal;
+use binc::journal::{Journal, JournalReader, JournalWriter};
 use binc::network_protocol::{NetworkRequest, NetworkResponse};
 use binc::node_id::NodeId;
-use binc::node_store::Node;
+use binc::node_store::{Node, NodeStore};
+use binc::util::parse_time;
 use clap::{Parser, Subcommand};
 use std::io;
+use std::io::BufReader;
 
 /// A simple command line tool for creating, manipulating, viewing and serving BINC documents
 #[derive(Parser, Debug)]
@@ -35,7 +37,47 @@ enum Commands {
     History { store: String },
 
     /// Print the document tree
-    Tree { path: String },
+    Tree {
+        path: String,
+
+        /// Print the tree as it was at a date (YYYY-MM-DD) or RFC 3339 time
+        #[arg(long)]
+        at: Option<String>,
+    },
+
+    /// Print the differences between two documents, or two revisions of one document
+    Diff {
+        path: String,
+
+        /// Compare the latest state of `path` to this document
+        other: Option<String>,
+
+        /// Revision of `path` to compare from, defaults to the start of the document
+        #[arg(long)]
+        from: Option<usize>,
+
+        /// Revision of `path` to compare to, defaults to the latest revision
+        #[arg(long)]
+        to: Option<usize>,
+    },
+
+    /// List the branches of a document
+    Branches { path: String },
+
+    /// Print which operations last set the name, type, attributes and tags of a node
+    Blame { path: String, node: usize },
+
+    /// List the nodes that match a query, such as `issue[status="open"] > comment`
+    Query { path: String, query: String },
+
+    /// Verify the checksums in a document
+    Verify { path: String },
+
+    /// List the operations that are not part of binc version 1
+    CheckV1 { path: String },
+
+    /// Write a copy of a document that only uses binc version 1 operations
+    ConvertV1 { path: String, output: String },
 
     /// Serve the contents of the directory over HTTP
     Serve { path: String, port: u16 },
@@ -67,14 +109,14 @@ fn main() -> io::Result<()> {
                     }
                 }
             }
-            Commands::Tree { path } => {
+            Commands::Tree { path, at } => {
                 println!("Printing document tree for {}", path);
                 if let Ok(repo) = client
                     .request(NetworkRequest::GetFileData { from: 0, path })?
                     .as_journal()
                 {
                     let document = Document::new(repo);
-                    print_tree(&document, NodeId::ROOT_NODE, 0, 0);
+                    print_document(&document, at.as_deref())?;
                 }
             }
             Commands::History { store: path } => {
@@ -105,12 +147,10 @@ fn main() -> io::Result<()> {
         Commands::History { store } => {
             println!("Listing changes for store {}", store);
 
-            let repo = Journal::read(&mut std::fs::File::open(store)?)?;
-            let mut index = 1;
-
-            for c in &repo.operations {
-                println!("{}: {}", index, c);
-                index += 1;
+            let file = BufReader::new(std::fs::File::open(store)?);
+            for (index, item) in JournalReader::new(file)?.enumerate() {
+                let (_, operation) = item?;
+                println!("{}: {}", index + 1, operation);
             }
 
             Ok(())
@@ -129,13 +169,142 @@ fn main() -> io::Result<()> {
 
             Ok(())
         }
-        Commands::Tree { path: store } => {
+        Commands::Tree { path: store, at } => {
             println!("Printing store {}", store);
 
-            let repo = Journal::read(&mut std::fs::File::open(store)?)?;
-            let document = Document::new(repo);
+            let document = Document::read(&mut BufReader::new(std::fs::File::open(store)?))?;
+
+            print_document(&document, at.as_deref())
+        }
+        Commands::Diff {
+            path,
+            other,
+            from,
+            to,
+        } => {
+            let document = Document::read(&mut BufReader::new(std::fs::File::open(&path)?))?;
+            let differences = if let Some(other) = other {
+                println!("Comparing {} to {}", path, other);
+                let other = Document::read(&mut BufReader::new(std::fs::File::open(other)?))?;
+                document.nodes.diff(&other.nodes)
+            } else {
+                let from = from.unwrap_or(0);
+                let to = to.unwrap_or(document.num_operations());
+                println!("Comparing revision {} of {} to revision {}", from, path, to);
+                document.diff(from, to)
+            };
+
+            for difference in &differences {
+                println!(" * {}", difference);
+            }
+            if differences.is_empty() {
+                println!("No differences");
+            }
+
+            Ok(())
+        }
+        Commands::Branches { path } => {
+            println!("Listing branches of {}", path);
+
+            let document = Document::read(&mut BufReader::new(std::fs::File::open(path)?))?;
+            let current = &document.current_branch().name;
+            for branch in document.branches() {
+                let marker = if &branch.name == current { "*" } else { " " };
+                println!("{} {}", marker, branch);
+            }
+
+            Ok(())
+        }
+        Commands::Blame { path, node } => {
+            println!("Blame for node {} of {}", node, path);
+
+            let document = Document::read(&mut BufReader::new(std::fs::File::open(path)?))?;
+            let Some(blame) = document.blame(NodeId::new(node)) else {
+                println!("Node {} does not exist", node);
+                return Ok(());
+            };
+            println!(" * created: {}", blame.created);
+            if let Some(origin) = &blame.moved {
+                println!(" * moved: {}", origin);
+            }
+            if let Some(origin) = &blame.name {
+                println!(" * name: {}", origin);
+            }
+            if let Some(origin) = &blame.type_id {
+                println!(" * type: {}", origin);
+            }
+            for (attribute, origin) in &blame.attributes {
+                println!(" * {}: {}", document.attribute_name(*attribute), origin);
+            }
+            for (tag, origin) in &blame.tags {
+                println!(" * tag {}: {}", document.tag_name(*tag), origin);
+            }
+
+            Ok(())
+        }
+        Commands::Query { path, query } => {
+            println!("Nodes of {} matching {}", path, query);
+
+            let document = Document::read(&mut BufReader::new(std::fs::File::open(path)?))?;
+            let nodes = document
+                .nodes
+                .select(&query)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            for id in &nodes {
+                if let Some(node) = document.nodes.get(*id) {
+                    let name = node.get_name().unwrap_or_default();
+                    let type_name = document.type_name(node.type_id);
+                    println!(" * ID{} [{}] {}", id.index(), type_name, name);
+                }
+            }
+            println!("{} nodes found", nodes.len());
+
+            Ok(())
+        }
+        Commands::Verify { path } => {
+            println!("Verifying {}", path);
+
+            let verification = Journal::verify(&mut std::fs::File::open(path)?)?;
+            for range in &verification.ranges {
+                println!(" * {}", range);
+            }
+            if verification.is_valid() {
+                println!("No corruption found");
+            } else {
+                println!("Document is corrupted");
+            }
+
+            Ok(())
+        }
+        Commands::CheckV1 { path } => {
+            println!("Checking {} for binc version 1 conformance", path);
+
+            let journal = Journal::read(&mut BufReader::new(std::fs::File::open(path)?))?;
+            let operations = journal.non_v1_operations();
+            for (index, operation) in &operations {
+                println!("{}: {}", index + 1, operation);
+            }
+            if operations.is_empty() {
+                println!("Document conforms to version 1");
+            } else {
+                println!("{} operations are not part of version 1", operations.len());
+            }
+
+            Ok(())
+        }
+        Commands::ConvertV1 { path, output } => {
+            println!("Converting {} to binc version 1 in {}", path, output);
 
-            print_tree(&document, NodeId::ROOT_NODE, 0, 0);
+            let journal = Journal::read(&mut BufReader::new(std::fs::File::open(path)?))?;
+            let conversion = journal.convert_to_v1();
+            let mut writer = JournalWriter::create(output)?.with_v1_only(true);
+            writer.write_operations(&conversion.journal.operations)?;
+            writer.flush()?;
+            println!(
+                "{} operations converted to strings, {} removed",
+                conversion.downgraded.len(),
+                conversion.removed.len()
+            );
 
             Ok(())
         }
@@ -153,6 +322,27 @@ fn list_files(files: Vec<String>) {
     }
 }
 
+/// Print the current tree, or the tree at the time given by `at`
+fn print_document(document: &Document, at: Option<&str>) -> io::Result<()> {
+    let Some(at) = at else {
+        print_tree(&document.nodes, NodeId::ROOT_NODE, 0, 0);
+        return Ok(());
+    };
+
+    let time = parse_time(at).ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid time {}", at))
+    })?;
+    let revision = document.revision_at(time).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No timestamped revision at or before {}", at),
+        )
+    })?;
+    println!("Revision {} of {}", revision, document.num_operations());
+    print_tree(&document.state_at(revision), NodeId::ROOT_NODE, 0, 0);
+    Ok(())
+}
+
 fn get_label(node: &Node, index_in_parent: usize) -> String {
     let name = node.get_name();
     let type_name = node.get_type();
@@ -172,8 +362,8 @@ fn get_label(node: &Node, index_in_parent: usize) -> String {
     format!("{}: ID{}", index_in_parent, node.id.index())
 }
 
-fn print_tree(document: &Document, id: NodeId, depth: i32, index_in_parent: usize) {
-    if let Some(node) = document.nodes.get(id) {
+fn print_tree(nodes: &NodeStore, id: NodeId, depth: i32, index_in_parent: usize) {
+    if let Some(node) = nodes.get(id) {
         let children = &node.children;
         let label = get_label(node, index_in_parent);
 
@@ -198,7 +388,7 @@ fn print_tree(document: &Document, id: NodeId, depth: i32, index_in_parent: usiz
 
         let mut index = 0;
         for child_id in children {
-            print_tree(document, child_id.clone(), depth + 1, index);
+            print_tree(nodes, child_id.clone(), depth + 1, index);
             index += 1;
         }
     }
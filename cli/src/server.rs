@@ -2,15 +2,18 @@ use crate::store::Store;
 use binc::network_protocol::{NetworkRequest, NetworkResponse};
 use std::io;
 use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
 
 struct Connection {
     stream: TcpStream,
-    store: Store,
+    store: Arc<Store>,
 }
 
 pub(crate) fn server(store: String, port: u16) {
     let addr = format!("0.0.0.0:{}", port);
     let listener = TcpListener::bind(addr).unwrap();
+    // Shared, so all connections append through the same writers
+    let store = Arc::new(Store::new(&store));
 
     for stream in listener.incoming() {
         match stream {
@@ -34,11 +37,8 @@ pub(crate) fn server(store: String, port: u16) {
 }
 
 impl Connection {
-    fn new(stream: TcpStream, root_dir: String) -> Connection {
-        Connection {
-            stream,
-            store: Store::new(&root_dir),
-        }
+    fn new(stream: TcpStream, store: Arc<Store>) -> Connection {
+        Connection { stream, store }
     }
 
     pub fn handle_connection(&mut self) -> io::Result<()> {
@@ -1,16 +1,25 @@
-use binc::journal::Journal;
+use binc::journal::{Journal, JournalReader, JournalWriter};
+use std::collections::HashMap;
 use std::fs::OpenOptions;
-use std::io::Write;
+use std::io::BufReader;
+use std::sync::Mutex;
 use std::{fs, io};
 
+/// Number of operations between the checksums added when appending
+const CHECKSUM_INTERVAL: usize = 100;
+
 pub struct Store {
     root_dir: String,
+    /// Writers for the files appended to, kept open so appending does not read the whole file.
+    /// All appends to a file have to go through the same store.
+    writers: Mutex<HashMap<String, JournalWriter>>,
 }
 
 impl Store {
     pub fn new(root: &str) -> Store {
         Store {
             root_dir: root.to_string(),
+            writers: Mutex::new(HashMap::new()),
         }
     }
 
@@ -38,8 +47,17 @@ impl Store {
     }
 
     pub fn get_file_data(&self, from: u64, path: String) -> io::Result<(u64, u64, Vec<u8>)> {
-        let repo = Journal::read(&mut fs::File::open(self.translate_path(&path))?)?;
-        let to = repo.operations.len() as u64;
+        let file = BufReader::new(fs::File::open(self.translate_path(&path))?);
+
+        let mut data = vec![0; 0];
+        let mut to = 0;
+        for item in JournalReader::new(file)? {
+            let (_, operation) = item?;
+            if to >= from {
+                operation.write(&mut data)?;
+            }
+            to += 1;
+        }
 
         if from > to {
             return Err(io::Error::new(
@@ -48,16 +66,6 @@ impl Store {
             ));
         }
 
-        let mut data = vec![0; 0];
-
-        let mut index = 0;
-        for change in &repo.operations {
-            if index >= from {
-                change.write(&mut data)?;
-            }
-            index += 1;
-        }
-
         Ok((from, to, data))
     }
 
@@ -75,18 +83,45 @@ impl Store {
             ));
         }
 
-        let fs_path = self.translate_path(path);
-        let repo = Journal::read(&mut fs::File::open(fs_path.clone())?)?;
-        if repo.operations.len() as u64 != from {
+        // Parse everything before writing, so a bad request cannot damage the file
+        let mut received = Journal::new();
+        received.append(&mut data.as_slice())?;
+        if received.operations.len() as u64 != to - from {
             return Err(io::Error::new(
                 io::ErrorKind::InvalidInput,
-                "Revision mismatch",
+                format!(
+                    "Expected {} operations, got {}",
+                    to - from,
+                    received.operations.len()
+                ),
             ));
         }
 
-        let mut file = OpenOptions::new().append(true).open(fs_path)?;
-        file.write(&data)?;
+        let path = self.translate_path(path);
+        let mut writers = self.writers.lock().unwrap();
+        if !writers.contains_key(&path) {
+            let writer = JournalWriter::open(&path)?.with_sync(true);
+            writers.insert(path.clone(), writer);
+        }
+        let writer = writers.get_mut(&path).unwrap();
+        if writer.operation_count() as u64 != from {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Revision mismatch",
+            ));
+        }
 
-        Ok(())
+        // The checksum is part of the file, clients get it with the next file data request
+        let result = writer.write_operations(&received.operations).and_then(|_| {
+            if writer.operations_since_checksum() >= CHECKSUM_INTERVAL {
+                writer.write_checksum()?;
+            }
+            writer.flush()
+        });
+        if result.is_err() {
+            // Opening the file again checks what was written
+            writers.remove(&path);
+        }
+        result
     }
 }
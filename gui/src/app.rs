@@ -1,21 +1,29 @@
-use crate::importer::{Import, Importer, IMPORTERS};
+use crate::importer::{file_uri, Import, Importer, IMPORTERS};
 use crate::persistent_client::PersistentClient;
 use binc::changes::Changes;
 use binc::document::Document;
-use binc::journal::Journal;
+use binc::journal::{Journal, JournalWriter};
 use binc::node_id::NodeId;
 use binc::node_store::Node;
 use binc::operation::Operation;
+use binc::session::Session;
+use binc::util::current_time;
 use eframe::egui;
 use eframe::egui::{Id, Modal, Sense, Ui, Widget};
 use std::collections::HashSet;
 use std::fs::File;
 use std::io;
+use std::io::BufReader;
 use std::path::PathBuf;
 use std::time::SystemTime;
 
 use crate::uiext::UiExt;
 
+/// Number of operations between checksums when saving
+const CHECKSUM_INTERVAL: usize = 100;
+/// Number of operations after which a checkpoint is added when saving, to speed up loading
+const CHECKPOINT_INTERVAL: usize = 10000;
+
 pub enum GuiAction {
     Undo,
     Redo,
@@ -87,8 +95,12 @@ pub struct Application {
     pub document: Document,
     pub ui: UiState,
     pub document_path: Option<PathBuf>,
+    /// Writer for `document_path`, kept open so that saving only appends
+    writer: Option<JournalWriter>,
     client: Option<PersistentClient>,
     last_update: SystemTime,
+    /// Session that changes made in this program instance are recorded with
+    session: Session,
 }
 
 impl Application {
@@ -110,6 +122,7 @@ impl Application {
         if let Ok((client, document)) = result {
             self.client = Some(client);
             self.document = document;
+            self.writer = None;
         } else if let Err(error) = result {
             let text = format!("Failed to connect to host\n\n{}", error.to_string());
             rfd::MessageDialog::new()
@@ -146,6 +159,20 @@ impl Application {
 
 impl Application {
     pub fn process_action(&mut self, action: GuiAction) {
+        let editing = matches!(
+            action,
+            GuiAction::AddNode { .. }
+                | GuiAction::MoveNode { .. }
+                | GuiAction::RemoveNode { .. }
+                | GuiAction::Commit { .. }
+                | GuiAction::WrappedChange { .. }
+        );
+        if editing {
+            // Everything an action changes is undone in one step
+            self.document.begin_transaction();
+            self.document.start_session(&self.session);
+        }
+
         match action {
             GuiAction::SelectNode { node } => self.select_node(node),
             GuiAction::AddNode { parent, index } => self.add_child(&parent, index),
@@ -156,7 +183,7 @@ impl Application {
             } => self.move_node(&node, &new_parent, index_in_new_parent),
             GuiAction::RemoveNode { node } => self.remove_node(&node),
             GuiAction::Commit { message } => self.commit(&message),
-            GuiAction::WrappedChange { change } => self.document.add_and_apply(change),
+            GuiAction::WrappedChange { change } => self.edit(change),
             GuiAction::Undo => self.document.undo(),
             GuiAction::Redo => self.document.redo(),
             GuiAction::SelectPreviousInTree => self.select_previous_in_tree(),
@@ -170,6 +197,10 @@ impl Application {
             GuiAction::ToggleEditing => self.toggle_editing(),
             GuiAction::SetRootNode { node } => self.ui.root = node,
         }
+
+        if editing {
+            self.document.end_transaction();
+        }
     }
 }
 
@@ -188,13 +219,16 @@ impl Application {
             document: new_document(),
             ui: UiState::default(),
             document_path: None,
+            writer: None,
             client: None,
             last_update: SystemTime::now(),
+            session: Session::new(&Self::get_author()),
         }
     }
 
     pub fn set_document(&mut self, document: Document) {
         self.document = document;
+        self.writer = None;
         self.ui.root = NodeId::ROOT_NODE;
         self.select_node(NodeId::NO_NODE);
     }
@@ -224,7 +258,7 @@ impl Application {
             parent: parent_id.clone(),
             index_in_parent: insertion_index,
         };
-        self.document.add_and_apply(c1);
+        self.apply(c1);
     }
 
     pub fn move_node(&mut self, node_id: &NodeId, new_parent_id: &NodeId, insertion_index: usize) {
@@ -233,20 +267,35 @@ impl Application {
             new_parent: new_parent_id.clone(),
             index_in_new_parent: insertion_index,
         };
-        self.document.add_and_apply(c);
+        self.apply(c);
     }
 
     pub fn remove_node(&mut self, node_id: &NodeId) {
         let c = Operation::RemoveNode {
             id: node_id.clone(),
         };
-        self.document.add_and_apply(c);
+        self.apply(c);
         self.select_node(NodeId::NO_NODE);
         if !self.node_exists(self.ui.root) {
             self.ui.root = NodeId::ROOT_NODE;
         }
     }
 
+    /// Apply a change made in the user interface. A change that does not fit the document is
+    /// left out and logged.
+    fn apply(&mut self, operation: Operation) {
+        if let Err(error) = self.document.add_and_apply(operation) {
+            log::error!("Change could not be applied: {}", error);
+        }
+    }
+
+    /// Apply a change typed into a field. Quick changes of the same field are combined.
+    fn edit(&mut self, operation: Operation) {
+        if let Err(error) = self.document.edit(operation) {
+            log::error!("Change could not be applied: {}", error);
+        }
+    }
+
     fn node_exists(&self, id: NodeId) -> bool {
         self.document.nodes.exists(id)
     }
@@ -257,9 +306,10 @@ impl Application {
 
     pub fn commit(&mut self, message: &str) {
         if !message.is_empty() {
-            self.document.add_and_apply(Operation::Snapshot {
+            self.apply(Operation::Snapshot {
                 author: Self::get_author(),
                 message: message.to_string(),
+                time: Some(current_time()),
             })
         }
         /*self.document.pending_changes.message = message.to_string();
@@ -503,10 +553,24 @@ pub fn create_toolbar(app: &mut Application, ui: &mut Ui, extra: impl FnOnce(&mu
                 .button_with_enable("Save", can_save(&app.document))
                 .clicked()
             {
-                save_document(&mut app.document, app.document_path.clone());
+                let result = save_document(
+                    &mut app.document,
+                    app.document_path.clone(),
+                    &mut app.writer,
+                );
+                if let Ok(Some(path)) = result {
+                    app.document_path = Some(path);
+                } else {
+                    show_error(result, "Failed to save document");
+                }
             }
             if ui.button("Save as…").clicked() {
-                save_document(&mut app.document, None);
+                let result = save_document(&mut app.document, None, &mut app.writer);
+                if let Ok(Some(path)) = result {
+                    app.document_path = Some(path);
+                } else {
+                    show_error(result, "Failed to save document");
+                }
             }
 
             ui.separator();
@@ -594,7 +658,7 @@ pub fn open_document() -> io::Result<Option<(Document, PathBuf)>> {
         .pick_file();
 
     if let Some(path) = path {
-        let mut file = File::open(path.clone())?;
+        let mut file = BufReader::new(File::open(path.clone())?);
         let document = Document::read(&mut file)?;
         return Ok(Some((document, path)));
     }
@@ -609,8 +673,9 @@ pub fn import_document_from_file(importer: &Importer) -> io::Result<Option<Docum
         .pick_file();
 
     if let Some(path) = path {
-        let mut file = File::open(path)?;
-        let repo = importer.import(&mut file);
+        let mut file = File::open(&path)?;
+        let uri = file_uri(&path)?;
+        let repo = importer.import(&mut file, &uri);
         let document = Document::new(repo?);
         return Ok(Some(document));
     }
@@ -625,10 +690,21 @@ pub fn can_save(document: &Document) -> bool {
 pub fn save_document(
     document: &mut Document,
     known_path: Option<PathBuf>,
+    writer: &mut Option<JournalWriter>,
 ) -> io::Result<Option<PathBuf>> {
+    if !document.can_redo() {
+        if document.operations_since_checkpoint() >= CHECKPOINT_INTERVAL {
+            document.add_checkpoint()?;
+        }
+        // Checksums are added to the journal, so the file has the same operations as the document
+        if document.operations_since_checksum() >= CHECKSUM_INTERVAL {
+            document.add_checksum()?;
+        }
+    }
+
     if let Some(path) = known_path {
-        let mut file = File::create(path.clone())?;
-        document.write(&mut file)?;
+        write_unsaved_operations(document, &path, writer)?;
+        document.mark_persisted();
         return Ok(Some(path));
     }
 
@@ -637,13 +713,42 @@ pub fn save_document(
         .save_file();
 
     if let Some(path) = path {
-        let mut file = File::create(path.clone())?;
-        document.write(&mut file)?;
+        *writer = Some(write_all_operations(document, &path)?);
+        document.mark_persisted();
         return Ok(Some(path));
     }
     Ok(None)
 }
 
+/// Append the operations since the last save, with the open writer if the file is still at the
+/// last save
+fn write_unsaved_operations(
+    document: &Document,
+    path: &PathBuf,
+    writer: &mut Option<JournalWriter>,
+) -> io::Result<()> {
+    let Some(operations) = document.unsaved_operations() else {
+        *writer = Some(write_all_operations(document, path)?);
+        return Ok(());
+    };
+
+    let saved = document.num_operations() - operations.len();
+    let open = match writer.take() {
+        Some(open) if open.operation_count() == saved => open,
+        _ => JournalWriter::open(path)?.with_sync(true),
+    };
+    let writer = writer.insert(open);
+    writer.write_operations(operations)?;
+    writer.flush()
+}
+
+fn write_all_operations(document: &Document, path: &PathBuf) -> io::Result<JournalWriter> {
+    let mut writer = JournalWriter::create(path)?.with_sync(true);
+    writer.write_operations(&document.journal.operations)?;
+    writer.flush()?;
+    Ok(writer)
+}
+
 pub fn new_document() -> Document {
     let mut document = Document::new(Journal::new());
     let id = document.next_id();
@@ -659,7 +764,9 @@ pub fn new_document() -> Document {
     changes
         .add_node(id, NodeId::ROOT_NODE, 2)
         .set_name(id, "Third");
-    document.add_and_apply_changes(changes);
+    document
+        .add_and_apply_changes(changes)
+        .expect("Initial nodes should apply to a new document");
     document
 }
 
@@ -670,16 +777,43 @@ mod tests {
 
     fn setup_app() -> Application {
         let mut app = Application::new();
+        app.set_document(Document::default());
         let mut changes = Changes::new();
         changes
             .add_node(NodeId::new(1), NodeId::ROOT_NODE, 0)
             .add_node(NodeId::new(2), NodeId::ROOT_NODE, 1)
             .add_node(NodeId::new(3), NodeId::new(1), 0);
 
-        app.document.add_and_apply_changes(changes);
+        app.document.add_and_apply_changes(changes).unwrap();
         app
     }
 
+    #[test]
+    fn test_typing_is_one_undo_step() {
+        let mut app = setup_app();
+        let count = app.document.num_operations();
+        for name in ["a", "ab", "abc"] {
+            app.process_action(GuiAction::WrappedChange {
+                change: Operation::SetName {
+                    node: NodeId::new(2),
+                    name: name.to_string(),
+                },
+            });
+        }
+        // The session, the combined name and the end of their transaction
+        assert_eq!(app.document.num_operations(), count + 3);
+        assert_eq!(
+            app.document.nodes.get(NodeId::new(2)).unwrap().get_name(),
+            Some("abc")
+        );
+
+        app.process_action(GuiAction::Undo);
+        assert_eq!(
+            app.document.nodes.get(NodeId::new(2)).unwrap().get_name(),
+            None
+        );
+    }
+
     #[test]
     fn test_select_next() {
         let mut app = setup_app();
@@ -737,4 +871,114 @@ mod tests {
             app.select_first_child();
         }
     }
+
+    #[test]
+    fn test_undo_action() {
+        let mut app = setup_app();
+        let count = app.document.node_count();
+        app.process_action(GuiAction::AddNode {
+            parent: NodeId::new(2),
+            index: 0,
+        });
+        assert_eq!(app.document.node_count(), count + 1);
+
+        // The session, the id reservation and the new node are one step
+        app.process_action(GuiAction::Undo);
+        assert_eq!(app.document.node_count(), count);
+        // So are the nodes added in the setup
+        app.process_action(GuiAction::Undo);
+        assert!(!app.document.nodes.exists(NodeId::new(1)));
+    }
+
+    fn add_named_nodes(document: &mut Document, count: usize) {
+        for i in 0..count {
+            let id = document.next_id();
+            let mut changes = Changes::new();
+            changes
+                .add_node(id, NodeId::ROOT_NODE, 0)
+                .set_name(id, &format!("node {}", i));
+            document.add_and_apply_changes(changes).unwrap();
+        }
+    }
+
+    fn read_saved(path: &PathBuf) -> Document {
+        Document::read(&mut BufReader::new(File::open(path).unwrap())).unwrap()
+    }
+
+    #[test]
+    fn test_save_appends_same_operations() {
+        let path = std::env::temp_dir().join(format!("binc-gui-save-{}.binc", std::process::id()));
+        let mut document = Document::default();
+        let mut writer = None;
+        add_named_nodes(&mut document, CHECKSUM_INTERVAL);
+        save_document(&mut document, Some(path.clone()), &mut writer).unwrap();
+        add_named_nodes(&mut document, CHECKSUM_INTERVAL);
+        save_document(&mut document, Some(path.clone()), &mut writer).unwrap();
+
+        // Checksums are part of the journal, so the file has the same operations
+        let saved = read_saved(&path);
+        assert!(document.operations_since_checksum() < document.num_operations());
+        assert_eq!(saved.num_operations(), document.num_operations());
+        assert!(saved.nodes == document.nodes);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_undo_is_saved() {
+        let path = std::env::temp_dir().join(format!("binc-gui-undo-{}.binc", std::process::id()));
+        let mut document = Document::default();
+        let mut writer = None;
+        add_named_nodes(&mut document, CHECKSUM_INTERVAL);
+        save_document(&mut document, Some(path.clone()), &mut writer).unwrap();
+        document.undo();
+        document.undo();
+
+        // The history shows what redo brings back
+        let latest = document.redo_revision().unwrap();
+        assert_eq!(
+            document.state_at(latest).find_roots().len(),
+            document.find_roots().len() + 2
+        );
+
+        add_named_nodes(&mut document, CHECKSUM_INTERVAL);
+        save_document(&mut document, Some(path.clone()), &mut writer).unwrap();
+        document.undo();
+        save_document(&mut document, Some(path.clone()), &mut writer).unwrap();
+
+        let mut saved = read_saved(&path);
+        assert!(saved.nodes == document.nodes);
+        assert_eq!(saved.redo_revision(), document.redo_revision());
+        saved.redo();
+        document.redo();
+        assert!(saved.nodes == document.nodes);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_saved_checkpoint_is_used() {
+        let path =
+            std::env::temp_dir().join(format!("binc-gui-checkpoint-{}.binc", std::process::id()));
+        let mut document = Document::default();
+        let mut writer = None;
+        add_named_nodes(&mut document, CHECKSUM_INTERVAL);
+        save_document(&mut document, Some(path.clone()), &mut writer).unwrap();
+        document.add_checkpoint().unwrap();
+        add_named_nodes(&mut document, 1);
+        save_document(&mut document, Some(path.clone()), &mut writer).unwrap();
+
+        // The checkpoint is at the revision it records, so loading starts from it
+        let saved = read_saved(&path);
+        let position = saved
+            .journal
+            .operations
+            .iter()
+            .position(|o| matches!(o, Operation::Checkpoint { .. }))
+            .unwrap();
+        assert!(matches!(
+            saved.journal.operations[position],
+            Operation::Checkpoint { revision, .. } if revision == position
+        ));
+        assert!(saved.nodes == document.nodes);
+        std::fs::remove_file(&path).unwrap();
+    }
 }
@@ -207,8 +207,7 @@ impl eframe::App for ExplorerApp {
                         .show(ui, |ui| {
                             self.history.create_history(
                                 ui,
-                                &self.application.document.journal,
-                                self.application.document.undo_revision,
+                                &self.application.document,
                                 &mut on_action,
                             );
                         });
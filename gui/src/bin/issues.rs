@@ -4,8 +4,11 @@
 )] // hide console window on Windows in release
 #![allow(rustdoc::missing_crate_level_docs)]
 
+use std::cmp::Reverse;
 use std::fs::File;
+use binc::error::QueryError;
 use binc::node_id::NodeId;
+use binc::query::quote;
 use bincgui::app::{create_toolbar, Application};
 use eframe::{egui, App, CreationContext, Storage};
 use binc::document::Document;
@@ -28,6 +31,8 @@ struct IssuesApp {
     application: Application,
     search_string: String,
     found_issues: Vec<NodeId>,
+    /// Why the search string is not a valid query
+    search_error: Option<String>,
 }
 
 impl IssuesApp {
@@ -53,47 +58,46 @@ impl IssuesApp {
             application: Application::new(),
             search_string: String::new(),
             found_issues: vec![],
+            search_error: None,
         }
     }
 
     fn update_search(&mut self) {
-        self.found_issues = self.get_issues_for_search(&self.search_string, 30);
+        match self.get_issues_for_search(&self.search_string, 30) {
+            Ok(issues) => {
+                self.found_issues = issues;
+                self.search_error = None;
+            }
+            Err(error) => self.search_error = Some(error.to_string()),
+        }
     }
 
-    fn get_issues_for_search(&self, search_string: &str, limit: usize) -> Vec<NodeId> {
-        if !search_string.is_empty() {
-            let search_string = search_string.to_lowercase();
-            let terms = search_string.split(" ");
-
-            if let Some(issue_id) = self.application.document.nodes.type_names.get_index("issue") {
-                let mut issues = vec![];
-                let summary_id = self.application.document.nodes.attribute_names.get_index("summary");
-
-                for node in self.application.document.nodes.nodes().iter().rev() {
-                    if Some(issue_id) == node.type_id {
-                        if let Some(summary) = node.get_string_attribute(summary_id.unwrap()) {
-                            let mut found = true;
-                            let mut t = terms.clone();
-                            while let Some(term) = t.next() {
-                                if !summary.to_lowercase().contains(&term) {
-                                    found = false;
-                                    break;
-                                }
-                            }
-                            if found {
-                                issues.push(node.id);
-
-                                if issues.len() >= limit {
-                                    break;
-                                }
-                            }
-                        }
-                    }
-                }
-                return issues;
-            }
+    /// Searches with `[` or `>` or an order clause are queries such as `issue[status="open"]`,
+    /// other words are looked for in the summaries of the issues, newest first
+    fn get_issues_for_search(
+        &self,
+        search_string: &str,
+        limit: usize,
+    ) -> Result<Vec<NodeId>, QueryError> {
+        let nodes = &self.application.document.nodes;
+        if search_string.contains(['[', '>']) || search_string.contains(" order by ") {
+            let mut issues = nodes.select(search_string)?;
+            issues.truncate(limit);
+            return Ok(issues);
         }
-        vec![]
+        if search_string.trim().is_empty() {
+            return Ok(vec![]);
+        }
+
+        let mut query = "issue".to_string();
+        for term in search_string.split_whitespace() {
+            query += &format!("[summary~={}]", quote(term));
+        }
+        // Ids are handed out in increasing order, so the highest are the newest
+        let mut issues = nodes.select(&query)?;
+        issues.sort_by_key(|id| Reverse(id.index()));
+        issues.truncate(limit);
+        Ok(issues)
     }
 }
 
@@ -119,6 +123,9 @@ impl eframe::App for IssuesApp {
                 if ui.text_edit_singleline(&mut self.search_string).changed() {
                     self.update_search();
                 }
+                if let Some(error) = &self.search_error {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                }
 
                 ui.separator();
 
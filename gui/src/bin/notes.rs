@@ -3,6 +3,7 @@
 
 use binc::builder::NodeBuilder;
 use binc::document::Document;
+use binc::error::ApplyError;
 use binc::node_id::NodeId;
 use binc::node_store::Node;
 use bincgui::app::{create_toolbar, Application, GuiAction};
@@ -45,13 +46,7 @@ impl NotesApp {
     }
 
     fn new() -> Self {
-        let mut d = Document::default();
-        let l1 = d.add_node(NodeId::ROOT_NODE);
-        d.set_node_type(l1, "list");
-        d.set_node_name(l1, "My List");
-        let t1 = d.add_node(l1);
-        d.set_node_type(t1, "task");
-        d.set_node_name(t1, "start");
+        let (d, l1) = Self::initial_document().expect("Initial nodes should apply");
 
         let mut app = Self {
             application: Application::new_with_document(d),
@@ -60,6 +55,17 @@ impl NotesApp {
         app
     }
 
+    fn initial_document() -> Result<(Document, NodeId), ApplyError> {
+        let mut d = Document::default();
+        let l1 = d.add_node(NodeId::ROOT_NODE)?;
+        d.set_node_type(l1, "list")?;
+        d.set_node_name(l1, "My List")?;
+        let t1 = d.add_node(l1)?;
+        d.set_node_type(t1, "task")?;
+        d.set_node_name(t1, "start")?;
+        Ok((d, l1))
+    }
+
     fn child_nodes(&self, ui: &mut Ui, node: &Node) {
         for n in &node.children {
             self.node(ui, self.get_node(n.clone()));
@@ -1,10 +1,14 @@
 use crate::app::GuiAction;
-use binc::journal::Journal;
+use binc::diff::Difference;
+use binc::document::Document;
 use eframe::egui::Ui;
 
 pub struct History {
     pub show_history: bool,
     snapshot_message: String,
+    /// Differences between the revision shown and the one with everything redone, with the
+    /// revisions they are for
+    differences: Option<((usize, usize), Vec<Difference>)>,
 }
 
 impl History {
@@ -12,19 +16,37 @@ impl History {
         Self {
             show_history: false,
             snapshot_message: String::new(),
+            differences: None,
         }
     }
 
     pub fn create_history(
-        &self,
+        &mut self,
         ui: &mut Ui,
-        journal: &Journal,
-        undo_revision: Option<usize>,
+        document: &Document,
         _on_action: &mut impl FnMut(GuiAction),
     ) {
-        let to = undo_revision.unwrap_or(journal.operations.len());
+        let journal = &document.journal;
+        let to = document.current_revision();
 
-        if undo_revision.is_some() {
+        if let Some(latest) = document.redo_revision() {
+            let revisions = (to, latest);
+            if self.differences.as_ref().map(|(r, _)| *r) != Some(revisions) {
+                self.differences = Some((revisions, document.diff(revisions.0, revisions.1)));
+            }
+            if let Some((_, differences)) = &self.differences {
+                ui.collapsing(
+                    format!("{} differences to the latest revision", differences.len()),
+                    |ui| {
+                        for difference in differences {
+                            ui.label(difference.to_string());
+                        }
+                    },
+                );
+            }
+        }
+
+        if document.undo_revision.is_some() {
             journal.operations[to..]
                 .iter()
                 .rev()
@@ -1,8 +1,11 @@
 use binc::changes::Changes;
 use binc::journal::Journal;
 use binc::node_id::{NodeId, NodeIdGenerator};
+use binc::sources::Source;
 use std::io;
 use std::io::Read;
+use std::path::Path;
+use url::Url;
 use xml::reader::XmlEvent;
 use xml::EventReader;
 
@@ -13,15 +16,16 @@ pub enum Importer {
 }
 
 pub trait Import {
-    fn import<R: Read>(&self, reader: &mut R) -> io::Result<Journal>;
+    /// Import the content of `reader`, recording `uri` as its source
+    fn import<R: Read>(&self, reader: &mut R, uri: &str) -> io::Result<Journal>;
     fn get_name(&self) -> &str;
     fn file_extensions(&self) -> Vec<&str>;
 }
 
 impl Import for Importer {
-    fn import<R: Read>(&self, reader: &mut R) -> io::Result<Journal> {
+    fn import<R: Read>(&self, reader: &mut R, uri: &str) -> io::Result<Journal> {
         match self {
-            Importer::XML => import_xml(reader),
+            Importer::XML => import_xml(reader, uri),
         }
     }
 
@@ -38,8 +42,24 @@ impl Import for Importer {
     }
 }
 
-fn import_xml<R: Read>(reader: &mut R) -> io::Result<Journal> {
-    let parser = EventReader::new(reader);
+/// The file URI of `path`, which is made absolute with links resolved
+pub fn file_uri(path: &Path) -> io::Result<String> {
+    let path = path.canonicalize()?;
+    Url::from_file_path(&path).map(String::from).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} has no file URI", path.display()),
+        )
+    })
+}
+
+fn import_xml<R: Read>(reader: &mut R, uri: &str) -> io::Result<Journal> {
+    // The whole content is needed for the source hash
+    let mut content = vec![];
+    reader.read_to_end(&mut content)?;
+    let source = Source::from_content(uri, &content);
+
+    let parser = EventReader::new(content.as_slice());
     let mut changes = Changes::new();
     let mut depth = 0;
     let mut parent_id_stack = Vec::<NodeId>::new();
@@ -66,6 +86,9 @@ fn import_xml<R: Read>(reader: &mut R) -> io::Result<Journal> {
                 count_stack.push(index_in_parent + 1);
                 changes.add_node(current_id, *parent_id, index_in_parent);
                 changes.set_type_s(current_id, name.local_name.as_str());
+                if *parent_id == NodeId::ROOT_NODE {
+                    changes.add_source(0, current_id, &source);
+                }
 
                 for attr in attributes {
                     changes.set_string_s(
@@ -122,10 +145,36 @@ mod tests {
         let cursor = Cursor::new(xml_data);
         let mut reader = BufReader::new(cursor);
 
-        let result = import_xml(&mut reader);
+        let result = import_xml(&mut reader, "file:///data/test.xml");
         assert!(result.is_ok());
 
         let document = Document::new(result.unwrap());
         // Add more assertions to verify the contents of the journal
+
+        let root = document.find_roots()[0];
+        let child = document.nodes.get(root).unwrap().children[0];
+        let sources = document.nodes.sources_for(child);
+        assert_eq!(sources.len(), 1);
+        assert_eq!(sources[0].1.uri, "file:///data/test.xml");
+        assert_eq!(
+            sources[0].1.hash,
+            Source::from_content("", xml_data.as_bytes()).hash
+        );
+    }
+
+    #[test]
+    fn test_file_uri() {
+        let uri = file_uri(Path::new("Cargo.toml")).unwrap();
+        assert!(uri.starts_with("file:///"), "{}", uri);
+        assert!(uri.ends_with("/gui/Cargo.toml"), "{}", uri);
+
+        let path = std::env::temp_dir().join("binc import test.xml");
+        std::fs::write(&path, "<root/>").unwrap();
+        assert!(file_uri(&path)
+            .unwrap()
+            .ends_with("/binc%20import%20test.xml"));
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(file_uri(Path::new("does not exist.xml")).is_err());
     }
 }